@@ -1,35 +1,56 @@
-/// I went to the woods because I wished to live deliberately, to front only the essential facts of
-/// life, and see if I could not learn what it had to teach, and not, when I came to die, discover 
-/// that I had not lived. I did not wish to live what was not life, living is so dear; nor did I 
-/// wish to practise resignation, unless it was quite necessary. I wanted to live deep and suck out
-/// all the marrow of life, to live so sturdily and Spartan-like as to put to rout all that was not
-/// life, to cut a broad swath and shave close, to drive life into a corner, and reduce it to its
-/// lowest terms. - Henry David Thoreau
+//! I went to the woods because I wished to live deliberately, to front only the essential facts of
+//! life, and see if I could not learn what it had to teach, and not, when I came to die, discover 
+//! that I had not lived. I did not wish to live what was not life, living is so dear; nor did I 
+//! wish to practise resignation, unless it was quite necessary. I wanted to live deep and suck out
+//! all the marrow of life, to live so sturdily and Spartan-like as to put to rout all that was not
+//! life, to cut a broad swath and shave close, to drive life into a corner, and reduce it to its
+//! lowest terms. - Henry David Thoreau
 
 extern crate rand;
 extern crate mursten;
-extern crate mursten_ggez_backend;
 extern crate nalgebra;
 extern crate petgraph;
 extern crate ggez;
+#[cfg(test)]
+extern crate image;
 
-use mursten::graphics::{Draw, Graphics, DrawPrimitives, Color};
+use mursten::graphics::{Draw, DrawPrimitives, Color};
 use mursten::logic::{Update, ElapsedDelta};
 use mursten::input::{JoystickProvider, Dpad};
+use controls::{KeyboardProvider, MouseProvider};
 use petgraph::stable_graph::{StableGraph, NodeIndex};
 use std::ops::{Index, IndexMut};
+use rand::prng::XorShiftRng;
 use nalgebra::*;
 
 use entities::*;
+use clock::*;
+use worldgen::*;
+use chunks::*;
+use spatial::*;
+use collision::*;
+use ai::*;
+use navigation::*;
 use ui::*;
+use fire::*;
+use controls::Input;
 
 
 struct World {
-    player_id: NodeIndex,
+    seed: u64,
+    rng: WorldRng,
+    /// One node per player, in the order they joined. Players stay in the graph when they die.
+    players: Vec<NodeIndex>,
     content: StableGraph<Entity, Edge>,
+    index: SpatialIndex,
+    nav: Navigator,
+    chunks: Chunks,
     spawn_cooldown: f32,
     speed: f32,
     delta: f32,
+    clock: Clock,
+    /// Where whatever moved in the last update was before it, to draw in between.
+    moved: std::collections::HashMap<NodeIndex, Point2<f32>>,
 }
 
 impl Index<NodeIndex> for World {
@@ -52,9 +73,6129 @@ impl ElapsedDelta for World {
     }
 }
 
-#[derive(Clone)]
-struct Edge;
+/// Which player, counting from 0 in the order they joined.
+type PlayerIndex = usize;
 
+/// How two entities in the world relate. Edges point from the first entity in the name to the
+/// second: a bag contains an item, a player owns the tent, food is attached to the mouse
+/// carrying it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Edge {
+    Owns,
+    AttachedTo,
+    Targets,
+    HomeOf,
+    Contains,
+}
+
+const TREE_HITS: u32 = 4;
+const TREE_LOGS: u32 = 2;
+const TREE_REGROW_TIME: f32 = 120.0;
+const BUSH_BERRIES: u32 = 3;
+const BUSH_REGROW_TIME: f32 = 40.0;
+const SLEEP_TIME_SCALE: f32 = 10.0;
+const DAY_LENGTH: f32 = 240.0;
+const SPAWN_INTERVAL: f32 = 3.0;
+const START: (f32, f32) = (150.0, 100.0);
+/// How far in front of themselves players can reach.
+const REACH: f32 = 20.0;
+/// Seconds of game in each step of the simulation, whatever the frame rate.
+const STEP: f32 = 1.0 / 60.0;
+/// Frame times never add up exactly, so a step this close to due gets taken now.
+const STEP_SLACK: f32 = 1e-4;
+/// Longest frame that gets played in full. After a stall the game slows down rather than
+/// spending its frames catching up.
+const MAX_FRAME: f32 = 0.25;
+
+/// What every step of the simulation gets to see.
+struct FixedStep;
+
+impl ElapsedDelta for FixedStep {
+    fn delta(&self) -> f32 {
+        STEP
+    }
+}
+
+
+mod clock {
+    use super::*;
+    use codec::{Encode, Writer, Reader, DecodeError};
+
+    /// Keeps track of the time of day. `time` counts in days since the world was created, so
+    /// the fractional part is the time of day and the integer part the calendar day.
+    #[derive(Clone, Debug)]
+    pub struct Clock {
+        start: f32,
+        elapsed: f32,
+        day_length: f32,
+    }
+
+    impl Clock {
+        /// Hour of the day the world starts at.
+        pub const DAWN: f32 = 7.0;
+
+        pub fn new(day_length: f32) -> Self {
+            Self { start: Self::DAWN / 24.0, elapsed: 0.0, day_length }
+        }
+
+        pub fn advance(&mut self, delta: f32) {
+            self.elapsed += delta;
+        }
+
+        #[cfg(test)]
+        pub fn day_length(&self) -> f32 {
+            self.day_length
+        }
+
+        pub fn time(&self) -> f32 {
+            self.start + self.elapsed / self.day_length
+        }
+
+        /// Calendar day, starting at 0.
+        pub fn day(&self) -> u32 {
+            self.time().floor() as u32
+        }
+
+        /// Whole days passed since the clock started.
+        pub fn days_elapsed(&self) -> u32 {
+            (self.elapsed / self.day_length).floor() as u32
+        }
+
+        pub fn hour(&self) -> f32 {
+            self.time().fract() * 24.0
+        }
+
+        /// 1.0 at noon, 0.0 at midnight.
+        pub fn daylight(&self) -> f32 {
+            0.5 - (self.time().fract() * f32::two_pi()).cos() * 0.5
+        }
+
+        /// How cold the night is, from 0.0 during the day to 1.0 at midnight.
+        pub fn coldness(&self) -> f32 {
+            ((0.5 - self.daylight()) * 2.0).max(0.0)
+        }
+
+        pub fn is_night(&self) -> bool {
+            self.daylight() < 0.25
+        }
+    }
+
+    impl Encode for Clock {
+        fn encode(&self, w: &mut Writer) {
+            w.f32(self.start);
+            w.f32(self.elapsed);
+            w.f32(self.day_length);
+        }
+        fn decode(r: &mut Reader) -> Result<Self, DecodeError> {
+            Ok(Clock { start: r.f32()?, elapsed: r.f32()?, day_length: r.f32()? })
+        }
+    }
+}
+
+
+mod worldgen {
+    use super::*;
+    use rand::{Rng, RngCore, SeedableRng};
+
+    /// Nothing is generated this close to the start, that's where the camp is.
+    const CAMP_RADIUS: f32 = 160.0;
+
+    pub fn rng_from_seed(seed: u64) -> XorShiftRng {
+        let mut bytes = [0u8; 16];
+        for i in 0..8 {
+            bytes[i] = (seed >> (i * 8)) as u8;
+            bytes[i + 8] = (splitmix(seed) >> (i * 8)) as u8;
+        }
+        XorShiftRng::from_seed(bytes)
+    }
+
+    /// The world's random numbers: the generator its seed starts, counting the numbers it gave
+    /// since. That count is all it takes to pick up where it was.
+    #[derive(Clone, Debug)]
+    pub struct WorldRng {
+        rng: XorShiftRng,
+        pub draws: u64,
+    }
+
+    impl WorldRng {
+        pub fn new(seed: u64) -> Self {
+            Self { rng: rng_from_seed(seed), draws: 0 }
+        }
+
+        /// The generator of `seed` once it has given `draws` numbers.
+        pub fn resume(seed: u64, draws: u64) -> Self {
+            let mut rng = Self::new(seed);
+            for _ in 0..draws {
+                rng.next_u32();
+            }
+            rng
+        }
+    }
+
+    // Everything goes through next_u32 to be counted, the same way XorShiftRng does it, so the
+    // numbers come out as they would from the generator itself
+    impl RngCore for WorldRng {
+        fn next_u32(&mut self) -> u32 {
+            self.draws += 1;
+            self.rng.next_u32()
+        }
+        fn next_u64(&mut self) -> u64 {
+            let low = self.next_u32() as u64;
+            (self.next_u32() as u64) << 32 | low
+        }
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            for chunk in dest.chunks_mut(8) {
+                let n = chunk.len();
+                if n > 4 {
+                    chunk.copy_from_slice(&self.next_u64().to_le_bytes()[..n]);
+                }
+                else {
+                    chunk.copy_from_slice(&self.next_u32().to_le_bytes()[..n]);
+                }
+            }
+        }
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    fn splitmix(x: u64) -> u64 {
+        let mut z = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum Biome {
+        Meadow,
+        Forest,
+        Wetland,
+        Rocky,
+    }
+
+    /// Places everything in the world from a seed. Every cell is generated on its own from
+    /// hashes of the seed and its coordinates, so the order cells are generated in doesn't matter.
+    pub struct Generator {
+        seed: u64,
+    }
+
+    impl Generator {
+        pub const CELL: f32 = 40.0;
+        /// Ponds can only be centered on cells that are multiples of this, so they never overlap.
+        const POND_SPACING: i32 = 3;
+
+        pub fn new(seed: u64) -> Self {
+            Self { seed }
+        }
+
+        fn hash(&self, x: i32, y: i32, salt: u64) -> u64 {
+            splitmix(self.seed ^ splitmix((x as u32 as u64) << 32 | y as u32 as u64) ^ splitmix(salt))
+        }
+
+        fn cell_rng(&self, i: i32, j: i32, salt: u64) -> XorShiftRng {
+            rng_from_seed(self.hash(i, j, salt))
+        }
+
+        /// Smooth value noise in [0, 1), with features about `scale` pixels wide.
+        fn noise(&self, p: Point2<f32>, scale: f32, salt: u64) -> f32 {
+            let x = p.x / scale;
+            let y = p.y / scale;
+            let (x0, y0) = (x.floor(), y.floor());
+            let (tx, ty) = (x - x0, y - y0);
+            let (sx, sy) = (tx * tx * (3.0 - 2.0 * tx), ty * ty * (3.0 - 2.0 * ty));
+            let corner = |dx: i32, dy: i32| {
+                (self.hash(x0 as i32 + dx, y0 as i32 + dy, salt) >> 40) as f32 / (1u64 << 24) as f32
+            };
+            let top = corner(0, 0) + (corner(1, 0) - corner(0, 0)) * sx;
+            let bottom = corner(0, 1) + (corner(1, 1) - corner(0, 1)) * sx;
+            top + (bottom - top) * sy
+        }
+
+        pub fn biome(&self, p: Point2<f32>) -> Biome {
+            let moisture = self.noise(p, 300.0, 1) * 0.7 + self.noise(p, 90.0, 2) * 0.3;
+            let fertility = self.noise(p, 250.0, 3) * 0.7 + self.noise(p, 70.0, 4) * 0.3;
+            if moisture > 0.65 {
+                Biome::Wetland
+            }
+            else if fertility > 0.6 {
+                Biome::Forest
+            }
+            else if fertility < 0.38 {
+                Biome::Rocky
+            }
+            else {
+                Biome::Meadow
+            }
+        }
+
+        fn cell_center(i: i32, j: i32) -> Point2<f32> {
+            Point2::new((i as f32 + 0.5) * Self::CELL, (j as f32 + 0.5) * Self::CELL)
+        }
+
+        fn is_in_camp(p: Point2<f32>) -> bool {
+            (p - Point2::new(START.0, START.1)).norm() < CAMP_RADIUS
+        }
+
+        /// The pond centered in this cell, if any.
+        pub fn pond_in_cell(&self, i: i32, j: i32) -> Option<(Point2<f32>, f32)> {
+            if i % Self::POND_SPACING != 0 || j % Self::POND_SPACING != 0 {
+                return None;
+            }
+            let center = Self::cell_center(i, j);
+            if self.biome(center) != Biome::Wetland || Self::is_in_camp(center) {
+                return None;
+            }
+            let mut rng = self.cell_rng(i, j, 10);
+            if rng.gen::<f32>() > 0.6 {
+                return None;
+            }
+            let radius = rng.gen_range(25.0, 50.0);
+            let offset = Vector2::new(rng.gen_range(-10.0, 10.0), rng.gen_range(-10.0, 10.0));
+            Some((center + offset, radius))
+        }
+
+        fn is_near_pond(&self, p: Point2<f32>, margin: f32) -> bool {
+            let (ci, cj) = ((p.x / Self::CELL).floor() as i32, (p.y / Self::CELL).floor() as i32);
+            let reach = Self::POND_SPACING;
+            for i in (ci - reach)..(ci + reach + 1) {
+                for j in (cj - reach)..(cj + reach + 1) {
+                    if let Some((center, radius)) = self.pond_in_cell(i, j) {
+                        if (p - center).norm() < radius + margin {
+                            return true;
+                        }
+                    }
+                }
+            }
+            false
+        }
+
+        /// Everything that lives in a single cell.
+        pub fn cell(&self, i: i32, j: i32) -> Vec<Entity> {
+            let mut entities = vec![];
+            if let Some((center, radius)) = self.pond_in_cell(i, j) {
+                entities.push(Entity::new(EntityKind::Pond(radius), center));
+            }
+
+            let center = Self::cell_center(i, j);
+            let mut rng = self.cell_rng(i, j, 20);
+            // Two slots per cell, each may get something depending on the biome
+            for _ in 0..2 {
+                let offset = Vector2::new(rng.gen_range(-15.0, 15.0), rng.gen_range(-15.0, 15.0));
+                let position = center + offset;
+                let roll = rng.gen::<f32>();
+                let kind = match self.biome(position) {
+                    Biome::Forest => {
+                        if roll < 0.45 { Some(EntityKind::Tree(TREE_HITS)) }
+                        else if roll < 0.55 { Some(EntityKind::Mushroom) }
+                        else if roll < 0.65 { Some(EntityKind::Bush(Bush::new())) }
+                        else { None }
+                    },
+                    Biome::Wetland => {
+                        if roll < 0.3 { Some(EntityKind::Grass) }
+                        else if roll < 0.4 { Some(EntityKind::Bush(Bush::new())) }
+                        else { None }
+                    },
+                    Biome::Rocky => {
+                        if roll < 0.35 { Some(EntityKind::Stone) }
+                        else if roll < 0.45 { Some(EntityKind::Grass) }
+                        else { None }
+                    },
+                    Biome::Meadow => {
+                        if roll < 0.3 { Some(EntityKind::Grass) }
+                        else if roll < 0.35 { Some(EntityKind::Bush(Bush::new())) }
+                        else if roll < 0.38 { Some(EntityKind::Tree(TREE_HITS)) }
+                        else { None }
+                    },
+                };
+                if let Some(kind) = kind {
+                    if !Self::is_in_camp(position) && !self.is_near_pond(position, kind.size()) {
+                        entities.push(Entity::new(kind, position));
+                    }
+                }
+            }
+            entities
+        }
+
+        /// Everything in the cells between `from` and `to`, both inclusive.
+        pub fn area(&self, from: (i32, i32), to: (i32, i32)) -> Vec<Entity> {
+            let mut entities = vec![];
+            for j in from.1..(to.1 + 1) {
+                for i in from.0..(to.0 + 1) {
+                    entities.extend(self.cell(i, j));
+                }
+            }
+            entities
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::collections::BTreeMap;
+
+        const FROM: (i32, i32) = (-20, -20);
+        const TO: (i32, i32) = (20, 20);
+
+        /// How many of each thing there is around the camp.
+        fn census(seed: u64) -> BTreeMap<&'static str, usize> {
+            let mut census = BTreeMap::new();
+            for e in Generator::new(seed).area(FROM, TO) {
+                *census.entry(e.kind.name()).or_insert(0) += 1;
+            }
+            census
+        }
+
+        fn places(seed: u64) -> Vec<(&'static str, Point2<f32>)> {
+            Generator::new(seed).area(FROM, TO).into_iter().map(|e| (e.kind.name(), e.position)).collect()
+        }
+
+        #[test]
+        fn same_seed_same_world() {
+            assert_eq!(places(3), places(3));
+            assert_ne!(places(3), places(4));
+        }
+
+        #[test]
+        fn cells_come_out_the_same_in_any_order() {
+            let generator = Generator::new(3);
+            let mut cells = vec![];
+            for j in (FROM.1..(TO.1 + 1)).rev() {
+                for i in (FROM.0..(TO.0 + 1)).rev() {
+                    cells.push(generator.cell(i, j));
+                }
+            }
+            cells.reverse();
+            let backwards: Vec<(&'static str, Point2<f32>)> = cells.into_iter()
+                .flatten()
+                .map(|e| (e.kind.name(), e.position))
+                .collect();
+            assert_eq!(backwards, places(3));
+        }
+
+        #[test]
+        fn the_camp_is_left_clear() {
+            for seed in 0..8 {
+                for e in Generator::new(seed).area(FROM, TO) {
+                    assert!(!Generator::is_in_camp(e.position), "Seed {} put a {} in the camp at {:?}", seed, e.kind.name(), e.position);
+                }
+            }
+        }
+
+        #[test]
+        fn nothing_grows_in_ponds() {
+            let entities = Generator::new(1).area(FROM, TO);
+            let ponds: Vec<(Point2<f32>, f32)> = entities.iter()
+                .filter_map(|e| if let EntityKind::Pond(radius) = e.kind { Some((e.position, radius)) } else { None })
+                .collect();
+            for e in entities.iter().filter(|e| e.kind.name() != "pond") {
+                for &(center, radius) in &ponds {
+                    assert!((e.position - center).norm() >= radius + e.kind.size(), "A {} at {:?} is in a pond", e.kind.name(), e.position);
+                }
+            }
+        }
+
+        /// If this changes, so does every world players have seen so far. Only update it when
+        /// that's what you want.
+        #[test]
+        fn seed_one_snapshot() {
+            let census: Vec<String> = census(1).into_iter().map(|(name, count)| format!("{} {}", name, count)).collect();
+            assert_eq!(census.join(", "), "bush 153, grass 487, mushroom 89, pond 12, stone 355, tree 377");
+        }
+    }
+}
+
+
+mod codec {
+    use super::*;
+
+    /// Why some bytes couldn't be turned back into game state.
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum DecodeError {
+        UnexpectedEnd,
+        InvalidTag(&'static str, u8),
+        /// Points at something that isn't there, like an edge to a missing node.
+        BadReference(&'static str),
+    }
+
+    pub struct Writer {
+        bytes: Vec<u8>,
+    }
+
+    impl Writer {
+        pub fn new() -> Self {
+            Self { bytes: vec![] }
+        }
+
+        pub fn into_bytes(self) -> Vec<u8> {
+            self.bytes
+        }
+
+        pub fn u8(&mut self, v: u8) {
+            self.bytes.push(v);
+        }
+
+        pub fn bool(&mut self, v: bool) {
+            self.u8(v as u8);
+        }
+
+        pub fn u32(&mut self, v: u32) {
+            for i in 0..4 {
+                self.u8((v >> (i * 8)) as u8);
+            }
+        }
+
+        pub fn u64(&mut self, v: u64) {
+            self.u32(v as u32);
+            self.u32((v >> 32) as u32);
+        }
+
+        pub fn f32(&mut self, v: f32) {
+            self.u32(v.to_bits());
+        }
+
+        pub fn point(&mut self, p: Point2<f32>) {
+            self.f32(p.x);
+            self.f32(p.y);
+        }
+
+        pub fn vector(&mut self, v: Vector2<f32>) {
+            self.f32(v.x);
+            self.f32(v.y);
+        }
+
+        pub fn bytes(&mut self, v: &[u8]) {
+            self.u32(v.len() as u32);
+            self.bytes.extend_from_slice(v);
+        }
+    }
+
+    pub struct Reader<'a> {
+        bytes: &'a [u8],
+    }
+
+    impl<'a> Reader<'a> {
+        pub fn new(bytes: &'a [u8]) -> Self {
+            Self { bytes }
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.bytes.is_empty()
+        }
+
+        /// Bytes left to read.
+        pub fn remaining(&self) -> usize {
+            self.bytes.len()
+        }
+
+        pub fn u8(&mut self) -> Result<u8, DecodeError> {
+            let (first, rest) = self.bytes.split_first().ok_or(DecodeError::UnexpectedEnd)?;
+            self.bytes = rest;
+            Ok(*first)
+        }
+
+        pub fn bool(&mut self) -> Result<bool, DecodeError> {
+            match self.u8()? {
+                0 => Ok(false),
+                1 => Ok(true),
+                t => Err(DecodeError::InvalidTag("bool", t)),
+            }
+        }
+
+        pub fn u32(&mut self) -> Result<u32, DecodeError> {
+            let mut v = 0;
+            for i in 0..4 {
+                v |= (self.u8()? as u32) << (i * 8);
+            }
+            Ok(v)
+        }
+
+        pub fn u64(&mut self) -> Result<u64, DecodeError> {
+            let low = self.u32()? as u64;
+            let high = self.u32()? as u64;
+            Ok(low | high << 32)
+        }
+
+        pub fn f32(&mut self) -> Result<f32, DecodeError> {
+            Ok(f32::from_bits(self.u32()?))
+        }
+
+        pub fn point(&mut self) -> Result<Point2<f32>, DecodeError> {
+            Ok(Point2::new(self.f32()?, self.f32()?))
+        }
+
+        pub fn vector(&mut self) -> Result<Vector2<f32>, DecodeError> {
+            Ok(Vector2::new(self.f32()?, self.f32()?))
+        }
+
+        pub fn bytes(&mut self) -> Result<&'a [u8], DecodeError> {
+            let len = self.u32()? as usize;
+            if self.bytes.len() < len {
+                return Err(DecodeError::UnexpectedEnd);
+            }
+            let (bytes, rest) = self.bytes.split_at(len);
+            self.bytes = rest;
+            Ok(bytes)
+        }
+    }
+
+    /// Compact binary form of game state, used to keep unloaded chunks around.
+    pub trait Encode: Sized {
+        fn encode(&self, w: &mut Writer);
+        fn decode(r: &mut Reader) -> Result<Self, DecodeError>;
+    }
+
+    impl<T: Encode> Encode for Vec<T> {
+        fn encode(&self, w: &mut Writer) {
+            w.u32(self.len() as u32);
+            for v in self.iter() {
+                v.encode(w);
+            }
+        }
+        fn decode(r: &mut Reader) -> Result<Self, DecodeError> {
+            let len = r.u32()?;
+            (0..len).map(|_| T::decode(r)).collect()
+        }
+    }
+
+    impl<T: Encode> Encode for Option<T> {
+        fn encode(&self, w: &mut Writer) {
+            match self {
+                Some(v) => {
+                    w.bool(true);
+                    v.encode(w);
+                },
+                None => w.bool(false),
+            }
+        }
+        fn decode(r: &mut Reader) -> Result<Self, DecodeError> {
+            if r.bool()? { Ok(Some(T::decode(r)?)) } else { Ok(None) }
+        }
+    }
+
+    impl Encode for Dpad {
+        fn encode(&self, w: &mut Writer) {
+            let i = Player::HANDS.iter().position(|hand| hand == self).unwrap();
+            w.u8(i as u8);
+        }
+        fn decode(r: &mut Reader) -> Result<Self, DecodeError> {
+            let t = r.u8()?;
+            Player::HANDS.get(t as usize).cloned().ok_or(DecodeError::InvalidTag("dpad", t))
+        }
+    }
+
+    impl Encode for Item {
+        fn encode(&self, w: &mut Writer) {
+            match self {
+                Item::Axe => w.u8(0),
+                Item::Berry => w.u8(1),
+                Item::Bottle(full) => {
+                    w.u8(2);
+                    w.bool(*full);
+                },
+                Item::Log => w.u8(3),
+                Item::Mushroom(cooked) => {
+                    w.u8(4);
+                    w.bool(*cooked);
+                },
+                Item::Stone => w.u8(5),
+                Item::Grass => w.u8(6),
+                Item::Rope => w.u8(7),
+            }
+        }
+        fn decode(r: &mut Reader) -> Result<Self, DecodeError> {
+            match r.u8()? {
+                0 => Ok(Item::Axe),
+                1 => Ok(Item::Berry),
+                2 => Ok(Item::Bottle(r.bool()?)),
+                3 => Ok(Item::Log),
+                4 => Ok(Item::Mushroom(r.bool()?)),
+                5 => Ok(Item::Stone),
+                6 => Ok(Item::Grass),
+                7 => Ok(Item::Rope),
+                t => Err(DecodeError::InvalidTag("item", t)),
+            }
+        }
+    }
+
+    impl Encode for Stack {
+        fn encode(&self, w: &mut Writer) {
+            self.item.encode(w);
+            w.u32(self.count);
+        }
+        fn decode(r: &mut Reader) -> Result<Self, DecodeError> {
+            Ok(Stack { item: Item::decode(r)?, count: r.u32()? })
+        }
+    }
+
+    impl Encode for Inventory {
+        fn encode(&self, w: &mut Writer) {
+            self.slots().to_vec().encode(w);
+        }
+        fn decode(r: &mut Reader) -> Result<Self, DecodeError> {
+            Ok(Inventory::from_slots(Vec::decode(r)?))
+        }
+    }
+
+    impl Encode for Rest {
+        fn encode(&self, w: &mut Writer) {
+            w.u8(match self { Rest::Tent => 0, Rest::Outdoors => 1 });
+        }
+        fn decode(r: &mut Reader) -> Result<Self, DecodeError> {
+            match r.u8()? {
+                0 => Ok(Rest::Tent),
+                1 => Ok(Rest::Outdoors),
+                t => Err(DecodeError::InvalidTag("rest", t)),
+            }
+        }
+    }
+
+    impl Encode for Player {
+        fn encode(&self, w: &mut Writer) {
+            // Hands in a fixed order, so the same player always encodes the same way
+            for hand in Player::HANDS.iter() {
+                self.hands.get(hand).cloned().encode(w);
+            }
+            self.current_hand.encode(w);
+            w.vector(self.log_speed);
+            w.f32(self.t);
+            w.f32(self.thirst);
+            w.f32(self.hunger);
+            w.f32(self.sleep);
+            w.f32(self.health);
+            self.rest.encode(w);
+        }
+        fn decode(r: &mut Reader) -> Result<Self, DecodeError> {
+            let mut player = Player::new();
+            player.hands.clear();
+            for hand in Player::HANDS.iter() {
+                if let Some(item) = Option::<Item>::decode(r)? {
+                    player.hands.insert(*hand, item);
+                }
+            }
+            player.current_hand = Dpad::decode(r)?;
+            player.log_speed = r.vector()?;
+            player.t = r.f32()?;
+            player.thirst = r.f32()?;
+            player.hunger = r.f32()?;
+            player.sleep = r.f32()?;
+            player.health = r.f32()?;
+            player.rest = Option::decode(r)?;
+            Ok(player)
+        }
+    }
+
+    impl Encode for EntityKind {
+        fn encode(&self, w: &mut Writer) {
+            match self {
+                EntityKind::Player(player) => {
+                    w.u8(0);
+                    player.encode(w);
+                },
+                EntityKind::Bag(inventory) => {
+                    w.u8(1);
+                    inventory.encode(w);
+                },
+                EntityKind::Tent(inventory) => {
+                    w.u8(2);
+                    inventory.encode(w);
+                },
+                EntityKind::Grass => w.u8(3),
+                EntityKind::Stone => w.u8(4),
+                EntityKind::Tree(hits) => {
+                    w.u8(5);
+                    w.u32(*hits);
+                },
+                EntityKind::Stump(regrow) => {
+                    w.u8(6);
+                    w.f32(*regrow);
+                },
+                EntityKind::Bush(bush) => {
+                    w.u8(7);
+                    w.u32(bush.berries);
+                    w.f32(bush.regrow);
+                },
+                EntityKind::Mushroom => w.u8(8),
+                EntityKind::Axe => w.u8(9),
+                EntityKind::Pond(size) => {
+                    w.u8(10);
+                    w.f32(*size);
+                },
+                EntityKind::Dropped(item) => {
+                    w.u8(11);
+                    item.encode(w);
+                },
+                EntityKind::Monster(Monster::Mouse, brain) => {
+                    w.u8(12);
+                    brain.encode(w);
+                },
+                EntityKind::Campfire(fire) => {
+                    w.u8(13);
+                    fire.encode(w);
+                },
+            }
+        }
+        fn decode(r: &mut Reader) -> Result<Self, DecodeError> {
+            match r.u8()? {
+                0 => Ok(EntityKind::Player(Player::decode(r)?)),
+                1 => Ok(EntityKind::Bag(Inventory::decode(r)?)),
+                2 => Ok(EntityKind::Tent(Inventory::decode(r)?)),
+                3 => Ok(EntityKind::Grass),
+                4 => Ok(EntityKind::Stone),
+                5 => Ok(EntityKind::Tree(r.u32()?)),
+                6 => Ok(EntityKind::Stump(r.f32()?)),
+                7 => Ok(EntityKind::Bush(Bush { berries: r.u32()?, regrow: r.f32()? })),
+                8 => Ok(EntityKind::Mushroom),
+                9 => Ok(EntityKind::Axe),
+                10 => Ok(EntityKind::Pond(r.f32()?)),
+                11 => Ok(EntityKind::Dropped(Item::decode(r)?)),
+                12 => Ok(EntityKind::Monster(Monster::Mouse, Brain::decode(r)?)),
+                13 => Ok(EntityKind::Campfire(Fire::decode(r)?)),
+                t => Err(DecodeError::InvalidTag("entity kind", t)),
+            }
+        }
+    }
+
+    impl Encode for Brain {
+        fn encode(&self, w: &mut Writer) {
+            w.u8(match self.behavior {
+                Behavior::Wander => 0,
+                Behavior::Flee => 1,
+                Behavior::StealBerries => 2,
+                Behavior::RaidTent => 3,
+                Behavior::GoHome => 4,
+            });
+            w.vector(self.heading);
+            w.f32(self.timer);
+            w.u32(self.path.len() as u32);
+            for p in self.path.iter() {
+                w.point(*p);
+            }
+            w.f32(self.repath);
+        }
+        fn decode(r: &mut Reader) -> Result<Self, DecodeError> {
+            let behavior = match r.u8()? {
+                0 => Behavior::Wander,
+                1 => Behavior::Flee,
+                2 => Behavior::StealBerries,
+                3 => Behavior::RaidTent,
+                4 => Behavior::GoHome,
+                t => return Err(DecodeError::InvalidTag("behavior", t)),
+            };
+            let heading = r.vector()?;
+            let timer = r.f32()?;
+            let path = (0..r.u32()?).map(|_| r.point()).collect::<Result<_, _>>()?;
+            Ok(Brain { behavior, heading, timer, path, repath: r.f32()? })
+        }
+    }
+
+    impl Encode for Edge {
+        fn encode(&self, w: &mut Writer) {
+            w.u8(match self {
+                Edge::Owns => 0,
+                Edge::AttachedTo => 1,
+                Edge::Targets => 2,
+                Edge::HomeOf => 3,
+                Edge::Contains => 4,
+            });
+        }
+        fn decode(r: &mut Reader) -> Result<Self, DecodeError> {
+            match r.u8()? {
+                0 => Ok(Edge::Owns),
+                1 => Ok(Edge::AttachedTo),
+                2 => Ok(Edge::Targets),
+                3 => Ok(Edge::HomeOf),
+                4 => Ok(Edge::Contains),
+                t => Err(DecodeError::InvalidTag("edge", t)),
+            }
+        }
+    }
+
+    impl Encode for Entity {
+        fn encode(&self, w: &mut Writer) {
+            self.kind.encode(w);
+            w.point(self.position);
+        }
+        fn decode(r: &mut Reader) -> Result<Self, DecodeError> {
+            let kind = EntityKind::decode(r)?;
+            Ok(Entity::new(kind, r.point()?))
+        }
+    }
+}
+
+
+mod chunks {
+    use super::*;
+    use codec::{Encode, Writer, Reader, DecodeError};
+    use std::collections::{HashMap, HashSet};
+
+    /// Stands for the first player in stored edges, since players never get stored with a
+    /// chunk. The next player is one less, and so on.
+    const PLAYER: u32 = u32::MAX;
+    pub const MAX_PLAYERS: u32 = 256;
+
+    pub type ChunkCoord = (i32, i32);
+
+    /// Cells of the generator on each side of a chunk.
+    const CHUNK_CELLS: i32 = 8;
+    pub const CHUNK_SIZE: f32 = Generator::CELL * CHUNK_CELLS as f32;
+    /// Chunks up to this far from the player's chunk are kept loaded...
+    const LOAD_RADIUS: i32 = 1;
+    /// ...and only get stored away once they are further than this, so walking back and forth
+    /// over a chunk border doesn't keep loading and unloading the same chunks.
+    const UNLOAD_RADIUS: i32 = 2;
+
+    pub fn chunk_of(position: Point2<f32>) -> ChunkCoord {
+        ((position.x / CHUNK_SIZE).floor() as i32, (position.y / CHUNK_SIZE).floor() as i32)
+    }
+
+    fn distance(a: ChunkCoord, b: ChunkCoord) -> i32 {
+        (a.0 - b.0).abs().max((a.1 - b.1).abs())
+    }
+
+    /// Bookkeeping of which parts of the world are in the graph and which are stored away.
+    #[derive(Clone, Debug)]
+    pub struct Chunks {
+        /// Chunks the players were in the last time chunks got streamed.
+        centers: Vec<ChunkCoord>,
+        loaded: HashSet<ChunkCoord>,
+        stored: HashMap<ChunkCoord, Vec<u8>>,
+    }
+
+    impl Chunks {
+        pub fn new() -> Self {
+            Self { centers: vec![], loaded: HashSet::new(), stored: HashMap::new() }
+        }
+
+        pub fn is_loaded(&self, chunk: ChunkCoord) -> bool {
+            self.loaded.contains(&chunk)
+        }
+    }
+
+    fn encode_coord(w: &mut Writer, (x, y): ChunkCoord) {
+        w.u32(x as u32);
+        w.u32(y as u32);
+    }
+
+    fn decode_coord(r: &mut Reader) -> Result<ChunkCoord, DecodeError> {
+        Ok((r.u32()? as i32, r.u32()? as i32))
+    }
+
+    impl Encode for Chunks {
+        fn encode(&self, w: &mut Writer) {
+            w.u32(self.centers.len() as u32);
+            for center in self.centers.iter() {
+                encode_coord(w, *center);
+            }
+            // Sorted, so the same chunks always encode to the same bytes
+            let mut loaded: Vec<&ChunkCoord> = self.loaded.iter().collect();
+            loaded.sort();
+            w.u32(loaded.len() as u32);
+            for chunk in loaded {
+                encode_coord(w, *chunk);
+            }
+            let mut stored: Vec<(&ChunkCoord, &Vec<u8>)> = self.stored.iter().collect();
+            stored.sort();
+            w.u32(stored.len() as u32);
+            for (chunk, bytes) in stored {
+                encode_coord(w, *chunk);
+                w.bytes(bytes);
+            }
+        }
+        fn decode(r: &mut Reader) -> Result<Self, DecodeError> {
+            let centers = (0..r.u32()?).map(|_| decode_coord(r)).collect::<Result<_, _>>()?;
+            let mut chunks = Chunks { centers, loaded: HashSet::new(), stored: HashMap::new() };
+            for _ in 0..r.u32()? {
+                chunks.loaded.insert(decode_coord(r)?);
+            }
+            for _ in 0..r.u32()? {
+                let chunk = decode_coord(r)?;
+                chunks.stored.insert(chunk, r.bytes()?.to_vec());
+            }
+            Ok(chunks)
+        }
+    }
+
+    impl World {
+        /// Loads the chunks around the players and stores away the ones all of them left
+        /// behind. Only does any work when a player moves into another chunk.
+        pub fn stream_chunks(&mut self) {
+            let mut centers: Vec<ChunkCoord> = (0..self.player_count())
+                .filter_map(|p| self.player_position(p))
+                .map(chunk_of)
+                .collect();
+            centers.sort();
+            centers.dedup();
+            if self.chunks.centers == centers {
+                return;
+            }
+            self.chunks.centers = centers.clone();
+
+            let mut far: Vec<ChunkCoord> = self.chunks.loaded.iter()
+                .cloned()
+                .filter(|chunk| centers.iter().all(|center| distance(*chunk, *center) > UNLOAD_RADIUS))
+                .collect();
+            // Set order changes from run to run, and the order chunks go away in decides which
+            // ids get reused later on
+            far.sort();
+            for chunk in far {
+                self.unload_chunk(chunk);
+            }
+
+            for center in centers {
+                for x in (center.0 - LOAD_RADIUS)..(center.0 + LOAD_RADIUS + 1) {
+                    for y in (center.1 - LOAD_RADIUS)..(center.1 + LOAD_RADIUS + 1) {
+                        if !self.chunks.is_loaded((x, y)) {
+                            self.load_chunk((x, y));
+                        }
+                    }
+                }
+            }
+        }
+
+        fn load_chunk(&mut self, chunk: ChunkCoord) {
+            let restored = match self.chunks.stored.remove(&chunk) {
+                Some(bytes) => match self.restore_chunk(&bytes) {
+                    Ok(()) => true,
+                    Err(error) => {
+                        // Whatever was there is lost, the land grows back as it first was
+                        eprintln!("Stored chunk {:?} doesn't add up, dropping it: {:?}", chunk, error);
+                        false
+                    },
+                },
+                None => false,
+            };
+            if !restored {
+                let from = (chunk.0 * CHUNK_CELLS, chunk.1 * CHUNK_CELLS);
+                let to = (from.0 + CHUNK_CELLS - 1, from.1 + CHUNK_CELLS - 1);
+                for entity in Generator::new(self.seed).area(from, to) {
+                    self.add_entity(entity);
+                }
+            }
+            self.chunks.loaded.insert(chunk);
+        }
+
+        /// Puts a stored chunk back in the world. It's all read before anything gets added, so a
+        /// chunk that doesn't add up leaves the world as it was.
+        fn restore_chunk(&mut self, bytes: &[u8]) -> Result<(), DecodeError> {
+            let mut r = Reader::new(bytes);
+            let entities = Vec::<Entity>::decode(&mut r)?;
+            let (count, players) = (entities.len() as u32, self.players.len() as u32);
+            let exists = |i: u32| if i > PLAYER - MAX_PLAYERS { PLAYER - i < players } else { i < count };
+            let mut edges = vec![];
+            for _ in 0..r.u32()? {
+                let (from, to) = (r.u32()?, r.u32()?);
+                if !exists(from) || !exists(to) {
+                    return Err(DecodeError::BadReference("edge end"));
+                }
+                edges.push((from, to, Edge::decode(&mut r)?));
+            }
+
+            let ids: Vec<NodeIndex> = entities.into_iter().map(|e| self.add_entity(e)).collect();
+            let players = self.players.clone();
+            let node = |i: u32| if i > PLAYER - MAX_PLAYERS { players[(PLAYER - i) as usize] } else { ids[i as usize] };
+            for (from, to, edge) in edges {
+                self.content.add_edge(node(from), node(to), edge);
+            }
+            Ok(())
+        }
+
+        /// Stores away whatever wandered off into a chunk that isn't loaded, along with the rest
+        /// of that chunk, so nothing stays in the world out of every player's reach.
+        pub fn store_strays(&mut self, moved: &[NodeIndex]) {
+            let mut strays: Vec<ChunkCoord> = moved.iter()
+                .filter(|id| self.content.contains_node(**id) && !self.is_player_node(**id) && !self.is_carried(**id))
+                .map(|id| chunk_of(self.content[*id].position))
+                .filter(|chunk| !self.chunks.is_loaded(*chunk))
+                .collect();
+            strays.sort();
+            strays.dedup();
+            for chunk in strays {
+                self.load_chunk(chunk);
+                self.unload_chunk(chunk);
+            }
+        }
+
+        /// Stores away everything in the chunk, together with whatever depends on it and the
+        /// edges between them. Attached entities go with what they're attached to, wherever
+        /// that is. Edges to players are kept too, any other edge leaving the chunk is lost.
+        fn unload_chunk(&mut self, chunk: ChunkCoord) {
+            let mut ids: Vec<NodeIndex> = vec![];
+            let placed: Vec<NodeIndex> = self.content.node_indices()
+                .filter(|id| !self.is_player_node(*id) && !self.is_carried(*id))
+                .filter(|id| chunk_of(self.content[*id].position) == chunk)
+                .collect();
+            for id in placed {
+                for dependent in self.with_dependents(id) {
+                    if !self.is_player_node(dependent) && !ids.contains(&dependent) {
+                        ids.push(dependent);
+                    }
+                }
+            }
+
+            let position = |id: NodeIndex| match self.players.iter().position(|player| *player == id) {
+                Some(p) => Some(PLAYER - p as u32),
+                None => ids.iter().position(|other| *other == id).map(|i| i as u32),
+            };
+            let is_player = |i: u32| i > PLAYER - MAX_PLAYERS;
+            let edges: Vec<(u32, u32, Edge)> = self.content.edge_indices()
+                .filter_map(|e| {
+                    let (from, to) = self.content.edge_endpoints(e)?;
+                    let (from, to) = (position(from)?, position(to)?);
+                    if is_player(from) && is_player(to) { None } else { Some((from, to, self.content[e])) }
+                })
+                .collect();
+
+            let entities: Vec<Entity> = ids.iter()
+                .map(|id| self.detach_entity(*id).unwrap())
+                .collect();
+            let mut w = Writer::new();
+            entities.encode(&mut w);
+            w.u32(edges.len() as u32);
+            for (from, to, edge) in edges {
+                w.u32(from);
+                w.u32(to);
+                edge.encode(&mut w);
+            }
+            self.chunks.stored.insert(chunk, w.into_bytes());
+            self.chunks.loaded.remove(&chunk);
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const FAR: ChunkCoord = (20, 20);
+
+        fn far_away() -> Point2<f32> {
+            Point2::new((FAR.0 as f32 + 0.5) * CHUNK_SIZE, (FAR.1 as f32 + 0.5) * CHUNK_SIZE)
+        }
+
+        fn mice(world: &World) -> Vec<Point2<f32>> {
+            world.content.node_indices()
+                .filter(|id| world[*id].kind.name() == "mouse")
+                .map(|id| world[id].position)
+                .collect()
+        }
+
+        #[test]
+        fn damaged_chunks_grow_back() {
+            let mut fresh = World::from_seed(0);
+            fresh.load_chunk(FAR);
+
+            let mut world = World::from_seed(0);
+            let before = world.content.node_count();
+            world.chunks.stored.insert(FAR, vec![1, 2, 3]);
+            world.load_chunk(FAR);
+            assert!(world.chunks.is_loaded(FAR));
+            assert_eq!(world.content.node_count(), fresh.content.node_count());
+            assert!(world.content.node_count() > before);
+        }
+
+        #[test]
+        fn chunks_with_edges_to_nowhere_change_nothing() {
+            let mut world = World::from_seed(0);
+            let before = world.content.node_count();
+            let mut w = Writer::new();
+            vec![Entity::new(EntityKind::Stone, far_away())].encode(&mut w);
+            w.u32(1);
+            w.u32(0);
+            w.u32(1);
+            Edge::Owns.encode(&mut w);
+            match world.restore_chunk(&w.into_bytes()) {
+                Err(DecodeError::BadReference("edge end")) => {},
+                other => panic!("Restored a chunk with a dangling edge: {:?}", other),
+            }
+            assert_eq!(world.content.node_count(), before);
+        }
+
+        #[test]
+        fn strays_are_stored_with_the_chunk_they_went_to() {
+            let mut world = World::from_seed(0);
+            let count = mice(&world).len();
+            let mouse = world.add_entity(Entity::new(EntityKind::Monster(Monster::Mouse, Brain::new()), far_away()));
+            world.store_strays(&[mouse]);
+            assert!(!world.content.contains_node(mouse));
+            assert_eq!(mice(&world).len(), count);
+            assert!(!world.chunks.is_loaded(FAR));
+
+            world.load_chunk(FAR);
+            assert!(mice(&world).contains(&far_away()), "The mouse didn't come back with its chunk");
+        }
+    }
+}
+
+
+mod relations {
+    use super::*;
+    use petgraph::Direction;
+    use petgraph::visit::EdgeRef;
+
+    impl World {
+        /// Relates two entities. Linking them twice with the same kind of edge does nothing.
+        pub fn link(&mut self, from: NodeIndex, to: NodeIndex, edge: Edge) {
+            if !self.is_linked(from, to, edge) {
+                self.content.add_edge(from, to, edge);
+            }
+        }
+
+        pub fn unlink(&mut self, from: NodeIndex, to: NodeIndex, edge: Edge) {
+            let found = self.content.edges_directed(from, Direction::Outgoing)
+                .find(|e| e.target() == to && *e.weight() == edge)
+                .map(|e| e.id());
+            if let Some(e) = found {
+                self.content.remove_edge(e);
+            }
+        }
+
+        pub fn is_linked(&self, from: NodeIndex, to: NodeIndex, edge: Edge) -> bool {
+            self.content.edges_directed(from, Direction::Outgoing)
+                .any(|e| e.target() == to && *e.weight() == edge)
+        }
+
+        /// Entities this one points to with the given kind of edge, in id order whatever order
+        /// the edges were added in.
+        pub fn related(&self, id: NodeIndex, edge: Edge) -> Vec<NodeIndex> {
+            let mut related: Vec<NodeIndex> = self.content.edges_directed(id, Direction::Outgoing)
+                .filter(|e| *e.weight() == edge)
+                .map(|e| e.target())
+                .collect();
+            related.sort();
+            related
+        }
+
+        /// Entities pointing to this one with the given kind of edge, in id order.
+        pub fn related_from(&self, id: NodeIndex, edge: Edge) -> Vec<NodeIndex> {
+            let mut related: Vec<NodeIndex> = self.content.edges_directed(id, Direction::Incoming)
+                .filter(|e| *e.weight() == edge)
+                .map(|e| e.source())
+                .collect();
+            related.sort();
+            related
+        }
+
+        pub fn contents(&self, container: NodeIndex) -> Vec<NodeIndex> {
+            self.related(container, Edge::Contains)
+        }
+
+        pub fn container_of(&self, id: NodeIndex) -> Option<NodeIndex> {
+            self.related_from(id, Edge::Contains).first().cloned()
+        }
+
+        /// Whoever owns the entity. A shared tent gives the player that owned it first.
+        pub fn owner_of(&self, id: NodeIndex) -> Option<NodeIndex> {
+            self.related_from(id, Edge::Owns).first().cloned()
+        }
+
+        pub fn attached_to(&self, id: NodeIndex) -> Option<NodeIndex> {
+            self.related(id, Edge::AttachedTo).first().cloned()
+        }
+
+        pub fn target_of(&self, id: NodeIndex) -> Option<NodeIndex> {
+            self.related(id, Edge::Targets).first().cloned()
+        }
+
+        pub fn home_of(&self, id: NodeIndex) -> Option<NodeIndex> {
+            self.related_from(id, Edge::HomeOf).first().cloned()
+        }
+
+        pub fn residents(&self, home: NodeIndex) -> Vec<NodeIndex> {
+            self.related(home, Edge::HomeOf)
+        }
+
+        /// Points an entity at something else, or at nothing, forgetting what it was after.
+        pub fn retarget(&mut self, id: NodeIndex, target: Option<NodeIndex>) {
+            for old in self.related(id, Edge::Targets) {
+                if Some(old) != target {
+                    self.unlink(id, old, Edge::Targets);
+                }
+            }
+            if let Some(target) = target {
+                self.link(id, target, Edge::Targets);
+            }
+        }
+
+        /// Entities that can't exist without this one: what it contains and what is attached to it.
+        pub fn dependents(&self, id: NodeIndex) -> Vec<NodeIndex> {
+            let mut dependents = self.contents(id);
+            dependents.extend(self.related_from(id, Edge::AttachedTo));
+            dependents
+        }
+
+        /// Whether the entity goes wherever something else does, attached to it or inside it.
+        pub fn is_carried(&self, id: NodeIndex) -> bool {
+            self.attached_to(id).is_some() || self.container_of(id).is_some()
+        }
+
+        /// The entity with all its dependents, their dependents and so on.
+        pub fn with_dependents(&self, id: NodeIndex) -> Vec<NodeIndex> {
+            let mut all = vec![id];
+            let mut i = 0;
+            while i < all.len() {
+                for dependent in self.dependents(all[i]) {
+                    if !all.contains(&dependent) {
+                        all.push(dependent);
+                    }
+                }
+                i += 1;
+            }
+            all
+        }
+
+        /// Takes whatever is attached to something to where it is now.
+        pub fn carry_attached(&mut self) {
+            let attached: Vec<(NodeIndex, NodeIndex)> = self.content.edge_indices()
+                .filter(|e| self.content[*e] == Edge::AttachedTo)
+                .filter_map(|e| self.content.edge_endpoints(e))
+                .collect();
+            for (id, carrier) in attached {
+                let (from, to) = (self.content[id].position, self.content[carrier].position + Vector2::new(0.0, CARRY_OFFSET));
+                if from != to {
+                    self.moved.entry(id).or_insert(from);
+                    self.index.update(id, to, self.content[id].kind.size());
+                    self.content[id].position = to;
+                }
+            }
+        }
+    }
+
+    /// How far in front of whatever carries them attached entities go, so they're drawn on top.
+    const CARRY_OFFSET: f32 = 0.5;
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn carried() -> (World, NodeIndex, NodeIndex) {
+            let mut world = World::empty(0);
+            let mouse = world.add_entity(Entity::new(EntityKind::Monster(Monster::Mouse, Brain::new()), Point2::new(10.0, 10.0)));
+            let food = world.add_entity(Entity::new(EntityKind::Dropped(Item::Berry), Point2::new(10.0, 10.0)));
+            world.link(food, mouse, Edge::AttachedTo);
+            (world, mouse, food)
+        }
+
+        #[test]
+        fn removing_takes_the_dependents_along() {
+            let (mut world, mouse, food) = carried();
+            let stone = world.add_entity(Entity::new(EntityKind::Stone, Point2::new(40.0, 10.0)));
+            let crumb = world.add_entity(Entity::new(EntityKind::Dropped(Item::Berry), Point2::new(10.0, 10.0)));
+            world.link(crumb, food, Edge::AttachedTo);
+            assert_eq!(world.with_dependents(mouse), vec![mouse, food, crumb]);
+
+            let removed = world.remove_entity(mouse).expect("The mouse wasn't there to remove");
+            assert_eq!(removed.kind.name(), "mouse");
+            assert_eq!(world.content.node_indices().collect::<Vec<_>>(), vec![stone]);
+            assert!(world.entities_within(Point2::new(10.0, 10.0), 20.0).is_empty());
+        }
+
+        #[test]
+        fn attached_entities_go_with_their_carrier() {
+            let (mut world, mouse, food) = carried();
+            assert_eq!(world.attached_to(food), Some(mouse));
+            assert_eq!(world.dependents(mouse), vec![food]);
+
+            let mut mouse_there = world[mouse].clone();
+            mouse_there.position = Point2::new(50.0, 20.0);
+            world.replace_entity(mouse, mouse_there);
+            world.carry_attached();
+            assert_eq!(world[food].position, Point2::new(50.0, 20.0 + CARRY_OFFSET));
+            assert_eq!(world.entities_within(Point2::new(50.0, 20.0), 1.0).len(), 2);
+
+            world.unlink(food, mouse, Edge::AttachedTo);
+            assert_eq!(world.attached_to(food), None);
+            world.remove_entity(mouse);
+            assert!(world.content.contains_node(food));
+        }
+
+        #[test]
+        fn links_are_not_repeated() {
+            let (mut world, mouse, food) = carried();
+            world.link(food, mouse, Edge::AttachedTo);
+            assert_eq!(world.content.edge_count(), 1);
+            assert!(world.is_linked(food, mouse, Edge::AttachedTo));
+            assert!(!world.is_linked(mouse, food, Edge::AttachedTo));
+        }
+
+        #[test]
+        fn contents_are_packed_away_in_their_container() {
+            let mut world = World::empty(0);
+            let p = world.add_player();
+            let front = world.point_in_front_of_player(p).unwrap();
+            let bag = world.add_entity(Entity::new(EntityKind::Bag(Inventory::bag()), front));
+            let axe = world.add_entity(Entity::new(EntityKind::Axe, front));
+            assert_eq!(world.pickupable_in_front_of_player(p), Some(axe));
+
+            world.link(bag, axe, Edge::Contains);
+            assert_eq!(world.contents(bag), vec![axe]);
+            assert_eq!(world.container_of(axe), Some(bag));
+            assert_eq!(world.container_of(bag), None);
+            assert_eq!(world.pickupable_in_front_of_player(p), None);
+            assert_eq!(world.with_dependents(bag), vec![bag, axe]);
+
+            world.remove_entity(bag);
+            assert!(!world.content.contains_node(axe));
+        }
+
+        #[test]
+        fn players_only_sleep_in_tents_of_their_own() {
+            let mut world = World::empty(0);
+            let p = world.add_player();
+            let other = world.add_player();
+            let front = world.point_in_front_of_player(p).unwrap();
+            let tent = world.add_entity(Entity::new(EntityKind::Tent(Inventory::tent()), front));
+            assert_eq!(world.owner_of(tent), None);
+            assert_eq!(world.tent_in_front_of_player(p), Some(tent));
+
+            let owner = world.player_node(other).unwrap();
+            world.link(owner, tent, Edge::Owns);
+            assert_eq!(world.owner_of(tent), Some(owner));
+            assert_eq!(world.tent_in_front_of_player(p), None);
+
+            let me = world.player_node(p).unwrap();
+            world.link(me, tent, Edge::Owns);
+            assert_eq!(world.owner_of(tent), Some(me));
+            assert_eq!(world.tent_in_front_of_player(p), Some(tent));
+        }
+    }
+}
+
+
+mod ai {
+    use super::*;
+    use rand::Rng;
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum Behavior {
+        Wander,
+        Flee,
+        StealBerries,
+        RaidTent,
+        GoHome,
+    }
+
+    /// Utility AI for the wildlife: every frame each behavior gets a score from what is around,
+    /// and the best one decides where to go. All randomness comes from the world, so the same
+    /// seed always gives the same choices.
+    #[derive(Clone, Debug)]
+    pub struct Brain {
+        pub behavior: Behavior,
+        pub heading: Vector2<f32>,
+        pub timer: f32,
+        pub path: Vec<Point2<f32>>,
+        pub repath: f32,
+    }
+
+    const RADIUS: f32 = 8.0;
+    const REPATH_TIME: f32 = 1.5;
+    const FLEE_DISTANCE: f32 = 80.0;
+    const SMELL_DISTANCE: f32 = 200.0;
+    const TENT_DISTANCE: f32 = 300.0;
+    const REACH: f32 = 6.0;
+    const NEST_SIZE: usize = 3;
+
+    fn bush_with_berries(kind: &EntityKind) -> bool {
+        if let EntityKind::Bush(bush) = kind { bush.berries > 0 } else { false }
+    }
+
+    fn stone(kind: &EntityKind) -> bool {
+        matches!(kind, EntityKind::Stone)
+    }
+
+    fn tent_with_food(kind: &EntityKind) -> bool {
+        if let EntityKind::Tent(inventory) = kind {
+            inventory.slots().iter().any(|s| s.as_ref().map(|s| s.item.nutrition().is_some()).unwrap_or(false))
+        } else { false }
+    }
+
+    impl Brain {
+        pub fn new() -> Self {
+            Self {
+                behavior: Behavior::Wander,
+                heading: Vector2::new(0.0, 0.0),
+                timer: 0.0,
+                path: vec![],
+                repath: 0.0,
+            }
+        }
+
+        /// Scores every behavior and picks the best one. Ties go to the first one listed.
+        fn choose(&self, me: NodeIndex, position: Point2<f32>, w: &World) -> (Behavior, Option<NodeIndex>) {
+            let mut options = vec![(Behavior::Wander, None, 0.3)];
+
+            if let Some(player) = w.nearest_awake_player(position) {
+                let d = (player - position).norm();
+                if d < FLEE_DISTANCE {
+                    options.push((Behavior::Flee, None, 2.0 * (1.0 - d / FLEE_DISTANCE)));
+                }
+            }
+
+            // Whatever a mouse steals it carries along, and it only steals one thing at a time
+            if !w.dependents(me).is_empty() {
+                if let Some(home) = w.home_of(me) {
+                    options.push((Behavior::GoHome, Some(home), 1.0));
+                }
+            } else {
+                if w.clock.is_night() {
+                    let tent = w.index.nearest(position, TENT_DISTANCE, |id| tent_with_food(&w[id].kind));
+                    if let Some(tent) = tent {
+                        options.push((Behavior::RaidTent, Some(tent), 0.5 + w.clock.coldness() * 0.5));
+                    }
+                }
+                let bush = w.index.nearest(position, SMELL_DISTANCE, |id| bush_with_berries(&w[id].kind));
+                if let Some(bush) = bush {
+                    options.push((Behavior::StealBerries, Some(bush), 0.7));
+                }
+            }
+
+            let mut best = options[0];
+            for option in options.into_iter().skip(1) {
+                if option.2 > best.2 {
+                    best = option;
+                }
+            }
+            (best.0, best.1)
+        }
+
+        /// Mice nest under the nearest stone that isn't full yet, and stay there for good.
+        fn settle(&self, me: NodeIndex, position: Point2<f32>, w: &mut World) {
+            if w.home_of(me).is_some() {
+                return;
+            }
+            let home = w.index.nearest(position, SMELL_DISTANCE, |id| stone(&w[id].kind) && w.residents(id).len() < NEST_SIZE);
+            if let Some(home) = home {
+                w.link(home, me, Edge::HomeOf);
+            }
+        }
+
+        /// Decides what to do and returns the velocity to move at.
+        pub fn think(&mut self, me: NodeIndex, position: Point2<f32>, w: &mut World) -> Vector2<f32> {
+            self.settle(me, position, w);
+            let (behavior, target) = self.choose(me, position, w);
+            w.retarget(me, target);
+            // Somewhere to go that isn't there anymore leaves the mouse wandering
+            let (behavior, target) = match (behavior, w.target_of(me)) {
+                (Behavior::StealBerries, None) | (Behavior::RaidTent, None) | (Behavior::GoHome, None) => (Behavior::Wander, None),
+                (behavior, target) => (behavior, target),
+            };
+            self.behavior = behavior;
+            self.timer -= w.delta();
+
+            match (behavior, target) {
+                (Behavior::Flee, _) => {
+                    let away = position - w.nearest_awake_player(position).unwrap_or(position);
+                    if away.norm() > 0.0 { away.normalize() * 60.0 } else { Vector2::new(60.0, 0.0) }
+                },
+                (Behavior::StealBerries, Some(target)) | (Behavior::RaidTent, Some(target)) | (Behavior::GoHome, Some(target)) => {
+                    let goal = w[target].position;
+                    if (goal - position).norm() < w[target].kind.size() + RADIUS + REACH {
+                        self.path.clear();
+                        if behavior == Behavior::GoHome {
+                            self.eat(me, w);
+                        } else {
+                            self.grab(me, target, position, w);
+                        }
+                        return Vector2::new(0.0, 0.0);
+                    }
+
+                    // Route around whatever is in the way, looking again every now and then
+                    self.repath -= w.delta();
+                    if self.path.is_empty() || self.repath <= 0.0 {
+                        self.path = w.find_path(position, goal, RADIUS).unwrap_or_default();
+                        self.repath = REPATH_TIME;
+                    }
+                    while self.path.len() > 1 && (self.path[0] - position).norm() < 4.0 {
+                        self.path.remove(0);
+                    }
+                    let next = self.path.first().cloned().unwrap_or(goal);
+                    let to = next - position;
+                    let speed = match behavior {
+                        Behavior::RaidTent => 30.0,
+                        Behavior::GoHome => 40.0,
+                        _ => 35.0,
+                    };
+                    if to.norm() > 0.0 { to.normalize() * speed } else { Vector2::new(0.0, 0.0) }
+                },
+                _ => {
+                    if self.timer <= 0.0 {
+                        // Mice with nowhere to go eat on the spot
+                        self.eat(me, w);
+                        self.timer = w.rng.gen_range(2.0, 4.0);
+                        let angle = w.rng.gen_range(0.0, f32::two_pi());
+                        let moving = w.rng.gen::<f32>() < 0.7;
+                        self.heading = if moving { Rotation2::new(angle) * Vector2::new(20.0, 0.0) } else { Vector2::new(0.0, 0.0) };
+                    }
+                    self.heading
+                },
+            }
+        }
+
+        fn eat(&self, me: NodeIndex, w: &mut World) {
+            for food in w.dependents(me) {
+                w.remove_entity(food);
+            }
+        }
+
+        fn grab(&mut self, me: NodeIndex, target: NodeIndex, position: Point2<f32>, w: &mut World) {
+            let taken = match w[target].kind {
+                EntityKind::Bush(ref mut bush) if bush.berries > 0 => {
+                    bush.berries -= 1;
+                    Some(Item::Berry)
+                },
+                EntityKind::Tent(ref mut inventory) => {
+                    let slot = inventory.slots().iter()
+                        .position(|s| s.as_ref().map(|s| s.item.nutrition().is_some()).unwrap_or(false));
+                    slot.and_then(|slot| inventory.take(slot))
+                },
+                _ => None,
+            };
+            if let Some(item) = taken {
+                let food = w.add_entity(Entity::new(EntityKind::Dropped(item), position));
+                w.link(food, me, Edge::AttachedTo);
+                self.timer = 3.0;
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn mouse_at(w: &mut World, position: Point2<f32>) -> NodeIndex {
+            w.add_entity(Entity::new(EntityKind::Monster(Monster::Mouse, Brain::new()), position))
+        }
+
+        fn behavior(w: &World, mouse: NodeIndex) -> Behavior {
+            match w[mouse].kind {
+                EntityKind::Monster(_, ref brain) => brain.behavior,
+                _ => panic!("That's no mouse"),
+            }
+        }
+
+        /// Lets the mouse think and move for a frame, the way the world update does.
+        fn step(w: &mut World, mouse: NodeIndex) {
+            w.delta = STEP;
+            let mut e = w[mouse].clone();
+            e.update(mouse, w);
+            w.replace_entity(mouse, e);
+            w.carry_attached();
+        }
+
+        fn berries(w: &World, bush: NodeIndex) -> u32 {
+            if let EntityKind::Bush(ref bush) = w[bush].kind { bush.berries } else { 0 }
+        }
+
+        #[test]
+        fn mice_run_from_players() {
+            let mut w = World::empty(0);
+            let p = w.add_player();
+            let player = w.player_position(p).unwrap();
+            let mouse = mouse_at(&mut w, player + Vector2::new(30.0, 0.0));
+            for _ in 0..30 {
+                step(&mut w, mouse);
+            }
+            assert_eq!(behavior(&w, mouse), Behavior::Flee);
+            assert!((w[mouse].position - player).norm() > 35.0, "The mouse didn't get away");
+            assert_eq!(w.target_of(mouse), None);
+        }
+
+        #[test]
+        fn mice_steal_berries_and_eat_them_at_home() {
+            let mut w = World::empty(0);
+            let mouse = mouse_at(&mut w, Point2::new(0.0, 0.0));
+            let bush = w.add_entity(Entity::new(EntityKind::Bush(Bush::new()), Point2::new(60.0, 0.0)));
+            let home = w.add_entity(Entity::new(EntityKind::Stone, Point2::new(-60.0, 0.0)));
+
+            step(&mut w, mouse);
+            assert_eq!(w.home_of(mouse), Some(home));
+            assert_eq!(w.residents(home), vec![mouse]);
+            assert_eq!(behavior(&w, mouse), Behavior::StealBerries);
+            assert_eq!(w.target_of(mouse), Some(bush));
+
+            let mut steps = 0;
+            while w.dependents(mouse).is_empty() {
+                step(&mut w, mouse);
+                steps += 1;
+                assert!(steps < 600, "The mouse never reached the bush");
+            }
+            assert_eq!(berries(&w, bush), BUSH_BERRIES - 1);
+
+            step(&mut w, mouse);
+            assert_eq!(behavior(&w, mouse), Behavior::GoHome);
+            assert_eq!(w.target_of(mouse), Some(home));
+            let food = w.dependents(mouse)[0];
+            while w.content.contains_node(food) {
+                assert!((w[food].position - w[mouse].position).norm() < 1.0, "The mouse dropped the berry");
+                step(&mut w, mouse);
+                steps += 1;
+                assert!(steps < 1200, "The mouse never made it home");
+            }
+            assert!((w[mouse].position - w[home].position).norm() < 30.0);
+            assert_eq!(berries(&w, bush), BUSH_BERRIES - 1);
+        }
+
+        #[test]
+        fn nests_fill_up() {
+            let mut w = World::empty(0);
+            let near = w.add_entity(Entity::new(EntityKind::Stone, Point2::new(20.0, 0.0)));
+            let far = w.add_entity(Entity::new(EntityKind::Stone, Point2::new(100.0, 0.0)));
+            let mice: Vec<NodeIndex> = (0..4).map(|i| mouse_at(&mut w, Point2::new(0.0, 20.0 * i as f32))).collect();
+            for mouse in &mice {
+                step(&mut w, *mouse);
+            }
+            assert_eq!(w.residents(near), mice[..NEST_SIZE].to_vec());
+            assert_eq!(w.home_of(mice[NEST_SIZE]), Some(far));
+        }
+
+        #[test]
+        fn mice_with_nowhere_to_be_wander_the_same_way_every_time() {
+            let wander = || {
+                let mut w = World::empty(7);
+                let mouse = mouse_at(&mut w, Point2::new(0.0, 0.0));
+                for _ in 0..300 {
+                    step(&mut w, mouse);
+                }
+                assert_eq!(behavior(&w, mouse), Behavior::Wander);
+                w[mouse].position
+            };
+            assert_eq!(wander(), wander());
+        }
+
+        #[test]
+        fn mice_that_lose_their_home_eat_where_they_are() {
+            let mut w = World::empty(0);
+            let mouse = mouse_at(&mut w, Point2::new(0.0, 0.0));
+            let home = w.add_entity(Entity::new(EntityKind::Stone, Point2::new(-60.0, 0.0)));
+            let food = w.add_entity(Entity::new(EntityKind::Dropped(Item::Berry), Point2::new(0.0, 0.0)));
+            w.link(food, mouse, Edge::AttachedTo);
+            step(&mut w, mouse);
+            assert_eq!(behavior(&w, mouse), Behavior::GoHome);
+
+            w.remove_entity(home);
+            step(&mut w, mouse);
+            assert_eq!(behavior(&w, mouse), Behavior::Wander);
+            assert_eq!(w.target_of(mouse), None);
+            assert!(!w.content.contains_node(food));
+        }
+    }
+}
+
+
+mod navigation {
+    use super::*;
+    use std::collections::BinaryHeap;
+    use std::cmp::Ordering;
+
+    /// Walkable cells around some area of the world, for an agent of a given radius.
+    #[derive(Clone, Debug)]
+    pub struct NavGrid {
+        origin: Point2<f32>,
+        width: i32,
+        height: i32,
+        radius: f32,
+        blocked: Vec<bool>,
+    }
+
+    type Cell = (i32, i32);
+
+    #[derive(PartialEq)]
+    struct Open {
+        cost: f32,
+        index: usize,
+    }
+
+    impl Eq for Open {}
+
+    impl Ord for Open {
+        // Reversed so the heap pops the cheapest first, ties broken by cell so it is deterministic
+        fn cmp(&self, other: &Self) -> Ordering {
+            other.cost.partial_cmp(&self.cost).unwrap_or(Ordering::Equal)
+                .then_with(|| other.index.cmp(&self.index))
+        }
+    }
+
+    impl PartialOrd for Open {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl NavGrid {
+        pub const CELL: f32 = 10.0;
+
+        /// Marks as blocked every cell where an agent of `radius` would overlap something solid.
+        pub fn build(world: &World, from: Point2<f32>, to: Point2<f32>, radius: f32) -> Self {
+            let width = ((to.x - from.x) / Self::CELL).ceil().max(1.0) as i32;
+            let height = ((to.y - from.y) / Self::CELL).ceil().max(1.0) as i32;
+            let mut grid = Self { origin: from, width, height, radius, blocked: vec![false; (width * height) as usize] };
+            for y in 0..height {
+                for x in 0..width {
+                    let center = grid.center((x, y));
+                    let blocked = world.entities_within(center, radius).into_iter().any(|id| {
+                        let e = &world[id];
+                        e.kind.is_solid() && e.kind.shape().push_circle_out(e.position, center, radius).is_some()
+                    });
+                    grid.blocked[(y * width + x) as usize] = blocked;
+                }
+            }
+            grid
+        }
+
+        pub fn radius(&self) -> f32 {
+            self.radius
+        }
+
+        pub fn contains(&self, p: Point2<f32>) -> bool {
+            let (x, y) = self.cell_of(p);
+            x >= 0 && y >= 0 && x < self.width && y < self.height
+        }
+
+        fn cell_of(&self, p: Point2<f32>) -> Cell {
+            (((p.x - self.origin.x) / Self::CELL).floor() as i32, ((p.y - self.origin.y) / Self::CELL).floor() as i32)
+        }
+
+        fn center(&self, (x, y): Cell) -> Point2<f32> {
+            self.origin + Vector2::new((x as f32 + 0.5) * Self::CELL, (y as f32 + 0.5) * Self::CELL)
+        }
+
+        fn index(&self, (x, y): Cell) -> usize {
+            (y * self.width + x) as usize
+        }
+
+        fn is_blocked(&self, (x, y): Cell) -> bool {
+            x < 0 || y < 0 || x >= self.width || y >= self.height || self.blocked[self.index((x, y))]
+        }
+
+        /// Closest free cell, for goals that sit inside something solid like a bush, or agents
+        /// pressed up against one.
+        fn nearest_free(&self, cell: Cell) -> Option<Cell> {
+            let max = self.width.max(self.height);
+            for ring in 0..max {
+                let mut best: Option<(Cell, i32)> = None;
+                for x in (cell.0 - ring)..(cell.0 + ring + 1) {
+                    for y in (cell.1 - ring)..(cell.1 + ring + 1) {
+                        if (x - cell.0).abs() != ring && (y - cell.1).abs() != ring {
+                            continue;
+                        }
+                        let d = (x - cell.0).pow(2) + (y - cell.1).pow(2);
+                        if !self.is_blocked((x, y)) && best.map(|(_, b)| d < b).unwrap_or(true) {
+                            best = Some(((x, y), d));
+                        }
+                    }
+                }
+                if let Some((c, _)) = best {
+                    return Some(c);
+                }
+            }
+            None
+        }
+
+        /// A* over the grid, moving in eight directions without cutting corners. Returns the
+        /// waypoints to follow, ending at `to`, or at the closest free spot if `to` is blocked.
+        /// Agents starting in a blocked cell first step out to the closest free one.
+        pub fn find_path(&self, from: Point2<f32>, to: Point2<f32>) -> Option<Vec<Point2<f32>>> {
+            if !self.contains(from) {
+                return None;
+            }
+            let from_cell = self.cell_of(from);
+            let start = if self.is_blocked(from_cell) { self.nearest_free(from_cell)? } else { from_cell };
+            let goal_cell = self.cell_of(to);
+            let (goal, exact) = if self.is_blocked(goal_cell) {
+                (self.nearest_free(goal_cell)?, false)
+            } else {
+                (goal_cell, true)
+            };
+
+            let heuristic = |(x, y): Cell| {
+                let (dx, dy) = ((x - goal.0).abs() as f32, (y - goal.1).abs() as f32);
+                dx.max(dy) + (2f32.sqrt() - 1.0) * dx.min(dy)
+            };
+
+            let size = (self.width * self.height) as usize;
+            let mut cost = vec![f32::INFINITY; size];
+            let mut came_from: Vec<Option<Cell>> = vec![None; size];
+            let mut open = BinaryHeap::new();
+            cost[self.index(start)] = 0.0;
+            open.push(Open { cost: heuristic(start), index: self.index(start) });
+
+            while let Some(Open { index, .. }) = open.pop() {
+                let cell = (index as i32 % self.width, index as i32 / self.width);
+                if cell == goal {
+                    let mut cells = vec![cell];
+                    while let Some(previous) = came_from[self.index(*cells.last().unwrap())] {
+                        cells.push(previous);
+                    }
+                    if start != from_cell {
+                        cells.push(start);
+                    }
+                    cells.reverse();
+                    return Some(self.waypoints(&cells, if exact { Some(to) } else { None }));
+                }
+                for dx in -1..2 {
+                    for dy in -1..2 {
+                        if dx == 0 && dy == 0 {
+                            continue;
+                        }
+                        let next = (cell.0 + dx, cell.1 + dy);
+                        if self.is_blocked(next) {
+                            continue;
+                        }
+                        let diagonal = dx != 0 && dy != 0;
+                        if diagonal && (self.is_blocked((cell.0 + dx, cell.1)) || self.is_blocked((cell.0, cell.1 + dy))) {
+                            continue;
+                        }
+                        let step = if diagonal { 2f32.sqrt() } else { 1.0 };
+                        let new_cost = cost[index] + step;
+                        let next_index = self.index(next);
+                        if new_cost < cost[next_index] {
+                            cost[next_index] = new_cost;
+                            came_from[next_index] = Some(cell);
+                            open.push(Open { cost: new_cost + heuristic(next), index: next_index });
+                        }
+                    }
+                }
+            }
+            None
+        }
+
+        /// Turns a chain of cells into points, keeping only the ones where the direction changes.
+        fn waypoints(&self, cells: &[Cell], end: Option<Point2<f32>>) -> Vec<Point2<f32>> {
+            let mut points = vec![];
+            for i in 1..cells.len() {
+                let last = i + 1 == cells.len();
+                if last {
+                    points.push(self.center(cells[i]));
+                    continue;
+                }
+                let before = (cells[i].0 - cells[i - 1].0, cells[i].1 - cells[i - 1].1);
+                let after = (cells[i + 1].0 - cells[i].0, cells[i + 1].1 - cells[i].1);
+                if before != after {
+                    points.push(self.center(cells[i]));
+                }
+            }
+            if let Some(end) = end {
+                points.pop();
+                points.push(end);
+            }
+            points
+        }
+    }
+
+    /// Keeps the last grid around and only builds a new one when the world changed or the path
+    /// asked for falls outside of it.
+    #[derive(Clone, Debug)]
+    pub struct Navigator {
+        grid: Option<NavGrid>,
+    }
+
+    impl Navigator {
+        /// Extra room around the endpoints, so paths can go around obstacles between them.
+        const MARGIN: f32 = 120.0;
+        /// Endpoints further apart than this are not worth routing.
+        pub const MAX_DISTANCE: f32 = 800.0;
+
+        pub fn new() -> Self {
+            Self { grid: None }
+        }
+
+        pub fn invalidate(&mut self) {
+            self.grid = None;
+        }
+    }
+
+    impl World {
+        /// Waypoints for an agent of `radius` to go from one point to another, or None when
+        /// there's no way through.
+        pub fn find_path(&mut self, from: Point2<f32>, to: Point2<f32>, radius: f32) -> Option<Vec<Point2<f32>>> {
+            if (to - from).norm() > Navigator::MAX_DISTANCE {
+                return None;
+            }
+            let fits = match self.nav.grid {
+                Some(ref grid) => grid.radius() == radius && grid.contains(from) && grid.contains(to),
+                None => false,
+            };
+            if !fits {
+                let margin = Vector2::new(Navigator::MARGIN, Navigator::MARGIN);
+                let low = Point2::new(from.x.min(to.x), from.y.min(to.y)) - margin;
+                let high = Point2::new(from.x.max(to.x), from.y.max(to.y)) + margin;
+                // Snap to the grid so nearby requests share the same cells
+                let snap = |v: f32| (v / NavGrid::CELL).floor() * NavGrid::CELL;
+                let low = Point2::new(snap(low.x), snap(low.y));
+                let high = Point2::new(snap(high.x) + NavGrid::CELL, snap(high.y) + NavGrid::CELL);
+                self.nav.grid = Some(NavGrid::build(self, low, high, radius));
+            }
+            self.nav.grid.as_ref().unwrap().find_path(from, to)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const RADIUS: f32 = 8.0;
+
+        /// A wall of stones across the way from left to right.
+        fn walled() -> (World, Vec<Point2<f32>>) {
+            let mut world = World::empty(0);
+            let stones: Vec<Point2<f32>> = (-4..5).map(|i| Point2::new(0.0, 10.0 * i as f32)).collect();
+            for stone in &stones {
+                world.add_entity(Entity::new(EntityKind::Stone, *stone));
+            }
+            (world, stones)
+        }
+
+        fn clear_of(path: &[Point2<f32>], stones: &[Point2<f32>]) -> bool {
+            path.iter().all(|p| stones.iter().all(|s| (p - s).norm() >= 8.0 + RADIUS))
+        }
+
+        #[test]
+        fn paths_go_around_walls() {
+            let (mut world, stones) = walled();
+            let to = Point2::new(50.0, 0.0);
+            let path = world.find_path(Point2::new(-50.0, 0.0), to, RADIUS).expect("No way around the wall");
+            assert_eq!(path.last(), Some(&to));
+            assert!(clear_of(&path, &stones), "The path goes through the wall: {:?}", path);
+            assert!(path.iter().any(|p| p.y.abs() > 40.0), "The path doesn't go around the wall: {:?}", path);
+        }
+
+        #[test]
+        fn agents_stuck_to_a_wall_step_away_first() {
+            let (mut world, stones) = walled();
+            let from = Point2::new(-12.0, 0.0);
+            let path = world.find_path(from, Point2::new(50.0, 0.0), RADIUS).expect("No way out from against the wall");
+            assert!(clear_of(&path, &stones), "The path goes through the wall: {:?}", path);
+            assert!((path[0] - from).norm() <= NavGrid::CELL * 2.0, "The first step is too far away: {:?}", path);
+        }
+
+        #[test]
+        fn blocked_goals_end_next_to_them() {
+            let (mut world, stones) = walled();
+            let path = world.find_path(Point2::new(-50.0, 0.0), Point2::new(0.0, 0.0), RADIUS).expect("Couldn't get close");
+            let end = *path.last().unwrap();
+            assert!(clear_of(&path, &stones));
+            assert!((end - Point2::new(0.0, 0.0)).norm() < 8.0 + RADIUS + NavGrid::CELL * 1.5, "The path ends too far: {:?}", path);
+        }
+
+        #[test]
+        fn far_away_places_are_not_routed() {
+            let mut world = World::empty(0);
+            let far = Point2::new(Navigator::MAX_DISTANCE + 1.0, 0.0);
+            assert_eq!(world.find_path(Point2::new(0.0, 0.0), far, RADIUS), None);
+        }
+    }
+}
+
+
+mod collision {
+    use super::*;
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum Shape {
+        Circle(f32),
+        /// Convex, in clockwise or counter-clockwise order.
+        Polygon(Vec<Point2<f32>>),
+    }
+
+    impl Shape {
+        pub fn radius(&self) -> f32 {
+            match self {
+                Shape::Circle(r) => *r,
+                Shape::Polygon(points) => points.iter().map(|p| p.coords.norm()).fold(0.0, f32::max),
+            }
+        }
+
+        /// How to push a circle at `center` so it stops overlapping this shape placed at
+        /// `position`: the direction to push and how far.
+        pub fn push_circle_out(&self, position: Point2<f32>, center: Point2<f32>, radius: f32) -> Option<(Vector2<f32>, f32)> {
+            match self {
+                Shape::Circle(r) => {
+                    let d = center - position;
+                    let distance = d.norm();
+                    let depth = radius + r - distance;
+                    if depth <= 0.0 {
+                        return None;
+                    }
+                    let normal = if distance > 0.0 { d / distance } else { Vector2::y() };
+                    Some((normal, depth))
+                },
+                Shape::Polygon(points) => {
+                    let local = center - position.coords;
+                    let mut inside = true;
+                    let mut closest: Option<(Point2<f32>, f32)> = None;
+                    let orientation = polygon_orientation(points);
+                    for i in 0..points.len() {
+                        let a = points[i];
+                        let b = points[(i + 1) % points.len()];
+                        let edge = b - a;
+                        let t = ((local - a).dot(&edge) / edge.norm_squared()).clamp(0.0, 1.0);
+                        let q = a + edge * t;
+                        let distance = (local - q).norm();
+                        if closest.map(|(_, d)| distance < d).unwrap_or(true) {
+                            closest = Some((q, distance));
+                        }
+                        let cross = edge.x * (local.y - a.y) - edge.y * (local.x - a.x);
+                        if cross * orientation < 0.0 {
+                            inside = false;
+                        }
+                    }
+                    let (q, distance) = closest?;
+                    if inside {
+                        // Deep inside, get out through the closest edge
+                        let normal = if distance > 0.0 { (q - local) / distance } else { Vector2::y() };
+                        Some((normal, radius + distance))
+                    }
+                    else if distance < radius {
+                        Some(((local - q) / distance, radius - distance))
+                    }
+                    else {
+                        None
+                    }
+                },
+            }
+        }
+    }
+
+    fn polygon_orientation(points: &[Point2<f32>]) -> f32 {
+        let mut area = 0.0;
+        for i in 0..points.len() {
+            let a = points[i];
+            let b = points[(i + 1) % points.len()];
+            area += a.x * b.y - b.x * a.y;
+        }
+        area.signum()
+    }
+
+    impl World {
+        /// Moves a circle by `movement`, pushing it out of anything solid it runs into. Whatever
+        /// part of the movement goes along the obstacle is kept, so it slides around it.
+        pub fn slide(&self, from: Point2<f32>, radius: f32, movement: Vector2<f32>) -> Point2<f32> {
+            let mut position = from + movement;
+            for _ in 0..4 {
+                let mut pushed = false;
+                for id in self.entities_within(position, radius) {
+                    let other = &self.content[id];
+                    if !other.kind.is_solid() {
+                        continue;
+                    }
+                    if let Some((normal, depth)) = other.kind.shape().push_circle_out(other.position, position, radius) {
+                        position += normal * depth;
+                        pushed = true;
+                    }
+                }
+                if !pushed {
+                    break;
+                }
+            }
+            position
+        }
+    }
+}
+
+
+mod spatial {
+    use super::*;
+    use std::collections::HashMap;
+
+    type Bucket = (i32, i32);
+
+    /// Spatial hash of entity footprints. Every entity is listed in each bucket its
+    /// footprint overlaps, so queries only have to look at the buckets they touch.
+    #[derive(Clone, Debug)]
+    pub struct SpatialIndex {
+        buckets: HashMap<Bucket, Vec<NodeIndex>>,
+        entries: HashMap<NodeIndex, (Point2<f32>, f32)>,
+    }
+
+    impl SpatialIndex {
+        pub const BUCKET_SIZE: f32 = 40.0;
+
+        pub fn new() -> Self {
+            Self { buckets: HashMap::new(), entries: HashMap::new() }
+        }
+
+        fn bucket_of(p: Point2<f32>) -> Bucket {
+            ((p.x / Self::BUCKET_SIZE).floor() as i32, (p.y / Self::BUCKET_SIZE).floor() as i32)
+        }
+
+        fn buckets_around(center: Point2<f32>, radius: f32) -> Vec<Bucket> {
+            let from = Self::bucket_of(center - Vector2::new(radius, radius));
+            let to = Self::bucket_of(center + Vector2::new(radius, radius));
+            let mut buckets = vec![];
+            for x in from.0..(to.0 + 1) {
+                for y in from.1..(to.1 + 1) {
+                    buckets.push((x, y));
+                }
+            }
+            buckets
+        }
+
+        pub fn insert(&mut self, id: NodeIndex, position: Point2<f32>, size: f32) {
+            for bucket in Self::buckets_around(position, size) {
+                self.buckets.entry(bucket).or_default().push(id);
+            }
+            self.entries.insert(id, (position, size));
+        }
+
+        pub fn remove(&mut self, id: NodeIndex) {
+            if let Some((position, size)) = self.entries.remove(&id) {
+                for bucket in Self::buckets_around(position, size) {
+                    let empty = match self.buckets.get_mut(&bucket) {
+                        Some(ids) => {
+                            ids.retain(|other| *other != id);
+                            ids.is_empty()
+                        },
+                        None => false,
+                    };
+                    if empty {
+                        self.buckets.remove(&bucket);
+                    }
+                }
+            }
+        }
+
+        /// Moves an entity in the index, telling whether anything changed.
+        pub fn update(&mut self, id: NodeIndex, position: Point2<f32>, size: f32) -> bool {
+            if self.entries.get(&id) == Some(&(position, size)) {
+                return false;
+            }
+            self.remove(id);
+            self.insert(id, position, size);
+            true
+        }
+
+        /// Entities whose footprint touches the circle.
+        pub fn within_radius(&self, center: Point2<f32>, radius: f32) -> Vec<NodeIndex> {
+            let mut found = vec![];
+            for bucket in Self::buckets_around(center, radius) {
+                if let Some(ids) = self.buckets.get(&bucket) {
+                    for id in ids {
+                        let (position, size) = self.entries[id];
+                        if (position - center).norm() <= radius + size && !found.contains(id) {
+                            found.push(*id);
+                        }
+                    }
+                }
+            }
+            found
+        }
+
+        /// Entities whose footprint the ray goes through, with the distance along the ray
+        /// where it enters them, closest first.
+        pub fn along_ray(&self, origin: Point2<f32>, direction: Vector2<f32>, length: f32) -> Vec<(NodeIndex, f32)> {
+            let direction = direction.normalize();
+            let mut found: Vec<(NodeIndex, f32)> = vec![];
+
+            // Walk the buckets the ray crosses, one border at a time
+            let mut bucket = Self::bucket_of(origin);
+            let last = Self::bucket_of(origin + direction * length);
+            let step = (direction.x.signum() as i32, direction.y.signum() as i32);
+            let border = |b: i32, s: i32| (b + if s > 0 { 1 } else { 0 }) as f32 * Self::BUCKET_SIZE;
+            let crossing = |from: f32, to: f32, d: f32| if d == 0.0 { f32::INFINITY } else { (to - from) / d };
+            let mut next_x = crossing(origin.x, border(bucket.0, step.0), direction.x);
+            let mut next_y = crossing(origin.y, border(bucket.1, step.1), direction.y);
+            let delta_x = if direction.x == 0.0 { f32::INFINITY } else { Self::BUCKET_SIZE / direction.x.abs() };
+            let delta_y = if direction.y == 0.0 { f32::INFINITY } else { Self::BUCKET_SIZE / direction.y.abs() };
+
+            loop {
+                if let Some(ids) = self.buckets.get(&bucket) {
+                    for id in ids {
+                        if found.iter().any(|(other, _)| other == id) {
+                            continue;
+                        }
+                        let (position, size) = self.entries[id];
+                        let to_center = position - origin;
+                        let along = to_center.dot(&direction);
+                        let away = (to_center - direction * along).norm();
+                        if away > size {
+                            continue;
+                        }
+                        let enter = along - (size * size - away * away).sqrt();
+                        let exit = along + (size * size - away * away).sqrt();
+                        if exit >= 0.0 && enter <= length {
+                            found.push((*id, enter.max(0.0)));
+                        }
+                    }
+                }
+                if bucket == last || next_x.min(next_y) > length {
+                    break;
+                }
+                if next_x < next_y {
+                    bucket.0 += step.0;
+                    next_x += delta_x;
+                }
+                else {
+                    bucket.1 += step.1;
+                    next_y += delta_y;
+                }
+            }
+
+            found.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
+            found
+        }
+
+        /// Closest entity accepted by the filter, measuring to the edge of its footprint.
+        pub fn nearest<F>(&self, center: Point2<f32>, max_distance: f32, filter: F) -> Option<NodeIndex>
+        where
+            F: Fn(NodeIndex) -> bool,
+        {
+            let origin = Self::bucket_of(center);
+            let mut best: Option<(NodeIndex, f32)> = None;
+            let rings = (max_distance / Self::BUCKET_SIZE).ceil() as i32 + 1;
+            for ring in 0..(rings + 1) {
+                // Nothing in this ring or further out can beat what we have
+                if let Some((_, d)) = best {
+                    if (ring - 1) as f32 * Self::BUCKET_SIZE > d {
+                        break;
+                    }
+                }
+                for x in (origin.0 - ring)..(origin.0 + ring + 1) {
+                    for y in (origin.1 - ring)..(origin.1 + ring + 1) {
+                        if (x - origin.0).abs() != ring && (y - origin.1).abs() != ring {
+                            continue;
+                        }
+                        let ids = match self.buckets.get(&(x, y)) {
+                            Some(ids) => ids,
+                            None => continue,
+                        };
+                        for id in ids {
+                            let (position, size) = self.entries[id];
+                            let d = ((position - center).norm() - size).max(0.0);
+                            let better = best.map(|(_, b)| d < b).unwrap_or(true);
+                            if d <= max_distance && better && filter(*id) {
+                                best = Some((*id, d));
+                            }
+                        }
+                    }
+                }
+            }
+            best.map(|(id, _)| id)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn id(i: usize) -> NodeIndex {
+            NodeIndex::new(i)
+        }
+
+        /// A row of stones going right from the origin, one more off to the side, and a big
+        /// one spanning a few buckets further down the row.
+        fn row() -> SpatialIndex {
+            let mut index = SpatialIndex::new();
+            index.insert(id(0), Point2::new(30.0, 0.0), 5.0);
+            index.insert(id(1), Point2::new(90.0, 3.0), 5.0);
+            index.insert(id(2), Point2::new(60.0, 50.0), 5.0);
+            index.insert(id(3), Point2::new(200.0, -10.0), 60.0);
+            index.insert(id(4), Point2::new(-30.0, 0.0), 5.0);
+            index
+        }
+
+        #[test]
+        fn rays_find_what_they_go_through_closest_first() {
+            let index = row();
+            let hits = index.along_ray(Point2::new(0.0, 0.0), Vector2::new(1.0, 0.0), 300.0);
+            let ids: Vec<NodeIndex> = hits.iter().map(|(id, _)| *id).collect();
+            assert_eq!(ids, vec![id(0), id(1), id(3)]);
+            assert!((hits[0].1 - 25.0).abs() < 1e-3);
+            assert!((hits[1].1 - 86.0).abs() < 1e-3, "Entered the second stone at {}", hits[1].1);
+            assert!((hits[2].1 - (200.0 - 3500.0f32.sqrt())).abs() < 1e-3);
+        }
+
+        #[test]
+        fn rays_stop_at_their_length() {
+            let index = row();
+            let ids: Vec<NodeIndex> = index.along_ray(Point2::new(0.0, 0.0), Vector2::new(2.0, 0.0), 50.0)
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect();
+            assert_eq!(ids, vec![id(0)]);
+            // Starting inside something counts as going through it from the start
+            assert_eq!(index.along_ray(Point2::new(30.0, 2.0), Vector2::new(0.0, 1.0), 10.0), vec![(id(0), 0.0)]);
+        }
+
+        #[test]
+        fn rays_find_things_in_every_direction() {
+            let index = row();
+            let to_the_side = Vector2::new(60.0, 50.0).normalize();
+            let ids: Vec<NodeIndex> = index.along_ray(Point2::new(0.0, 0.0), to_the_side, 100.0)
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect();
+            assert_eq!(ids, vec![id(2)]);
+            assert_eq!(index.along_ray(Point2::new(0.0, 0.0), Vector2::new(-1.0, 0.0), 100.0).len(), 1);
+            assert!(index.along_ray(Point2::new(0.0, 0.0), Vector2::new(0.0, -1.0), 100.0).is_empty());
+        }
+    }
+}
+
+
+/// Timing of the spatial queries and of collisions, against going through every entity. Run
+/// with `cargo test --release bench -- --ignored --nocapture`.
+#[cfg(test)]
+mod bench {
+    use super::*;
+    use rand::Rng;
+    use std::time::{Duration, Instant};
+
+    const QUERIES: u32 = 10_000;
+    const SIZES: [u32; 5] = [1_000, 5_000, 10_000, 20_000, 40_000];
+
+    fn per_query(d: Duration) -> u64 {
+        (d.as_secs() * 1_000_000_000 + d.subsec_nanos() as u64) / QUERIES as u64
+    }
+
+    #[test]
+    #[ignore]
+    fn collision() {
+        println!("{:>8} {:>14} {:>14}", "entities", "index ns/query", "scan ns/query");
+        for &n in SIZES.iter() {
+            let mut rng = rng_from_seed(n as u64);
+            // Same density every time, the world just gets bigger
+            let side = (n as f32).sqrt() * 40.0;
+            let mut index = SpatialIndex::new();
+            let mut entities = vec![];
+            for i in 0..n as usize {
+                let position = Point2::new(rng.gen_range(0.0, side), rng.gen_range(0.0, side));
+                index.insert(NodeIndex::new(i), position, 10.0);
+                entities.push(position);
+            }
+            let queries: Vec<Point2<f32>> = (0..QUERIES)
+                .map(|_| Point2::new(rng.gen_range(0.0, side), rng.gen_range(0.0, side)))
+                .collect();
+
+            let start = Instant::now();
+            let mut hits = 0;
+            for q in queries.iter() {
+                hits += index.within_radius(*q, 12.0).len();
+            }
+            let indexed = start.elapsed();
+
+            let start = Instant::now();
+            let mut scanned_hits = 0;
+            for q in queries.iter() {
+                scanned_hits += entities.iter().filter(|p| (**p - *q).norm() <= 22.0).count();
+            }
+            let scanned = start.elapsed();
+
+            assert_eq!(hits, scanned_hits);
+            println!("{:>8} {:>14} {:>14}", n, per_query(indexed), per_query(scanned));
+        }
+    }
+
+    /// `World::slide` the way it was before the index, pushing out of every solid entity.
+    fn slide_by_scan(w: &World, from: Point2<f32>, radius: f32, movement: Vector2<f32>) -> Point2<f32> {
+        let mut position = from + movement;
+        for _ in 0..4 {
+            let mut pushed = false;
+            for id in w.content.node_indices() {
+                let other = &w[id];
+                if !other.kind.is_solid() {
+                    continue;
+                }
+                if let Some((normal, depth)) = other.kind.shape().push_circle_out(other.position, position, radius) {
+                    position += normal * depth;
+                    pushed = true;
+                }
+            }
+            if !pushed {
+                break;
+            }
+        }
+        position
+    }
+
+    #[test]
+    #[ignore]
+    fn slide() {
+        println!("{:>8} {:>14} {:>14}", "entities", "index ns/slide", "scan ns/slide");
+        for &n in SIZES.iter() {
+            // Far enough apart that nothing runs into two stones at once, or which one it gets
+            // pushed out of first would depend on the order they're found in
+            let mut rng = rng_from_seed(n as u64);
+            let row = (n as f32).sqrt().ceil() as u32;
+            let side = row as f32 * 40.0;
+            let mut world = World::empty(0);
+            for i in 0..n {
+                let cell = Point2::new((i % row) as f32 * 40.0 + 20.0, (i / row) as f32 * 40.0 + 20.0);
+                let jitter = Vector2::new(rng.gen_range(-3.0, 3.0), rng.gen_range(-3.0, 3.0));
+                world.add_entity(Entity::new(EntityKind::Stone, cell + jitter));
+            }
+            let moves: Vec<(Point2<f32>, Vector2<f32>)> = (0..QUERIES)
+                .map(|_| {
+                    let from = Point2::new(rng.gen_range(0.0, side), rng.gen_range(0.0, side));
+                    (from, Vector2::new(rng.gen_range(-2.0, 2.0), rng.gen_range(-2.0, 2.0)))
+                })
+                .collect();
+
+            let start = Instant::now();
+            let indexed: Vec<Point2<f32>> = moves.iter().map(|&(from, movement)| world.slide(from, 8.0, movement)).collect();
+            let indexed_time = start.elapsed();
+
+            let start = Instant::now();
+            let scanned: Vec<Point2<f32>> = moves.iter().map(|&(from, movement)| slide_by_scan(&world, from, 8.0, movement)).collect();
+            let scanned_time = start.elapsed();
+
+            assert_eq!(indexed, scanned);
+            println!("{:>8} {:>14} {:>14}", n, per_query(indexed_time), per_query(scanned_time));
+        }
+    }
+}
+
+
+mod controls {
+    use super::*;
+    use codec::{Encode, Writer, Reader, DecodeError};
+    use mursten::input::{JoystickState, ButtonState};
+
+    /// The abstract actions the game is played with, whatever device they come from.
+    #[derive(Clone, Copy, Debug, Default, PartialEq)]
+    pub struct Input {
+        pub d_pad: Option<Dpad>,
+        pub a: bool,
+        pub b: bool,
+    }
+
+    impl Input {
+        pub fn idle() -> Self {
+            Self::default()
+        }
+        #[cfg(test)]
+        pub fn walk(direction: Dpad) -> Self {
+            Self { d_pad: Some(direction), ..Self::default() }
+        }
+        #[cfg(test)]
+        pub fn a() -> Self {
+            Self { a: true, ..Self::default() }
+        }
+        #[cfg(test)]
+        pub fn b() -> Self {
+            Self { b: true, ..Self::default() }
+        }
+        #[cfg(test)]
+        pub fn with_d_pad(self, direction: Dpad) -> Self {
+            Self { d_pad: Some(direction), ..self }
+        }
+        pub fn from_state(state: &JoystickState) -> Self {
+            Self { d_pad: state.d_pad, a: state.a.is_pressed(), b: state.b.is_pressed() }
+        }
+        pub fn state(&self) -> JoystickState {
+            JoystickState { d_pad: self.d_pad, a: button(self.a), b: button(self.b) }
+        }
+        /// Both inputs at once. Buttons are pressed if pressed on either, and the first one's
+        /// d-pad wins.
+        pub fn or(self, other: Input) -> Self {
+            Self { d_pad: self.d_pad.or(other.d_pad), a: self.a || other.a, b: self.b || other.b }
+        }
+        /// Buttons and d-pad packed in five bits.
+        pub fn bits(&self) -> u8 {
+            let d_pad = self.d_pad
+                .map(|d| Player::HANDS.iter().position(|hand| *hand == d).unwrap() as u8 + 1)
+                .unwrap_or(0);
+            self.a as u8 | (self.b as u8) << 1 | d_pad << 2
+        }
+        pub fn from_bits(bits: u8) -> Result<Self, DecodeError> {
+            let d_pad = match bits >> 2 {
+                0 => None,
+                i => Some(*Player::HANDS.get(i as usize - 1).ok_or(DecodeError::InvalidTag("d-pad", bits))?),
+            };
+            Ok(Input { d_pad, a: bits & 1 != 0, b: bits & 2 != 0 })
+        }
+    }
+
+    impl Encode for Input {
+        fn encode(&self, w: &mut Writer) {
+            w.u8(self.bits());
+        }
+        fn decode(r: &mut Reader) -> Result<Self, DecodeError> {
+            Self::from_bits(r.u8()?)
+        }
+    }
+
+    fn button(pressed: bool) -> ButtonState {
+        if pressed { ButtonState::Pressed } else { ButtonState::NotPressed }
+    }
+
+    /// The keys the game is played with.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub enum Key {
+        Up,
+        Down,
+        Left,
+        Right,
+        W,
+        A,
+        S,
+        D,
+        Z,
+        X,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub enum MouseButton {
+        Left,
+        Right,
+    }
+
+    /// Tells which keys are down. mursten only reads joysticks, so this is the game's own.
+    pub trait KeyboardProvider {
+        fn is_key_pressed(&self, key: Key) -> bool;
+    }
+
+    /// Tells where the pointer is, in screen coordinates, and which buttons are down.
+    pub trait MouseProvider {
+        fn mouse_position(&self) -> Point2<f32>;
+        fn is_mouse_button_pressed(&self, button: MouseButton) -> bool;
+    }
+
+    /// Keys for each d-pad direction, in `Player::HANDS` order, and for the buttons.
+    pub const DIRECTION_KEYS: [[Key; 2]; 4] = [[Key::Up, Key::W], [Key::Right, Key::D], [Key::Down, Key::S], [Key::Left, Key::A]];
+    pub const A_KEY: Key = Key::Z;
+    pub const B_KEY: Key = Key::X;
+
+    /// How far from the middle of the screen, where the selector is drawn, the pointer has to be
+    /// to pick a direction.
+    const POINTER_DEAD_ZONE: f32 = 16.0;
+    const SCREEN_CENTER: (f32, f32) = (160.0, 120.0);
+
+    pub fn from_keyboard<K: KeyboardProvider>(keyboard: &K) -> Input {
+        let d_pad = Player::HANDS.iter()
+            .zip(DIRECTION_KEYS.iter())
+            .find(|&(_, keys)| keys.iter().any(|key| keyboard.is_key_pressed(*key)))
+            .map(|(hand, _)| *hand);
+        Input { d_pad, a: keyboard.is_key_pressed(A_KEY), b: keyboard.is_key_pressed(B_KEY) }
+    }
+
+    /// Left and right click are A and B. While the left button is down the pointer picks a
+    /// direction around the middle of the screen, which is how the selector gets used.
+    pub fn from_mouse<M: MouseProvider>(mouse: &M) -> Input {
+        let a = mouse.is_mouse_button_pressed(MouseButton::Left);
+        let b = mouse.is_mouse_button_pressed(MouseButton::Right);
+        let offset = mouse.mouse_position() - Point2::new(SCREEN_CENTER.0, SCREEN_CENTER.1);
+        let d_pad = if a && offset.norm() > POINTER_DEAD_ZONE {
+            Some(if offset.x.abs() > offset.y.abs() {
+                if offset.x > 0.0 { Dpad::Right } else { Dpad::Left }
+            }
+            else {
+                if offset.y > 0.0 { Dpad::Down } else { Dpad::Up }
+            })
+        }
+        else {
+            None
+        };
+        Input { d_pad, a, b }
+    }
+
+    /// Everything player `p` is doing right now. Each player gets a joystick of their own, and
+    /// the first one also plays with the keyboard and mouse. The joystick goes first, then the
+    /// keyboard, then the mouse.
+    pub fn read<C>(context: &C, p: PlayerIndex) -> Input
+    where
+        C: JoystickProvider + KeyboardProvider + MouseProvider,
+    {
+        let joystick = context.available_joysticks().get(p)
+            .map(|jid| Input::from_state(&context.joystick(*jid)))
+            .unwrap_or_default();
+        if p > 0 {
+            return joystick;
+        }
+        joystick.or(from_keyboard(context)).or(from_mouse(context))
+    }
+}
+
+
+
+/// The game's own window on top of ggez. ggez hands out key, mouse and controller events one at
+/// a time, so they get kept here as whatever is held down right now, which is how the game
+/// reads its devices.
+mod window {
+    use super::*;
+    use std::collections::HashSet;
+    use controls::{Key, MouseButton};
+    use mursten::Scene;
+    use mursten::input::{JoystickState, JoystickId};
+    use mursten::graphics::DrawMode;
+    use ggez::{Context, GameResult, conf, event, graphics, timer};
+    use ggez::event::{EventHandler, Keycode, Mod, MouseState, Button};
+
+    const WIDTH: u32 = 320;
+    const HEIGHT: u32 = 240;
+    /// Window pixels for each pixel of the game.
+    const ZOOM: u32 = 2;
+    const CIRCLE_SEGMENTS: usize = 24;
+
+    /// Everything held down on the keyboard, the mouse and each controller, and how long the
+    /// last frame took.
+    #[derive(Clone, Debug)]
+    pub struct Devices {
+        pub delta: f32,
+        keys: HashSet<Key>,
+        buttons: HashSet<MouseButton>,
+        pointer: Point2<f32>,
+        /// Controllers by the id ggez gives them, in the order they were first used.
+        controllers: Vec<(i32, Input)>,
+    }
+
+    impl Devices {
+        pub fn new() -> Self {
+            Self {
+                delta: 0.0,
+                keys: HashSet::new(),
+                buttons: HashSet::new(),
+                pointer: Point2::new(WIDTH as f32 / 2.0, HEIGHT as f32 / 2.0),
+                controllers: vec![],
+            }
+        }
+
+        pub fn key(&mut self, key: Key, down: bool) {
+            if down { self.keys.insert(key); } else { self.keys.remove(&key); }
+        }
+
+        pub fn mouse_button(&mut self, button: MouseButton, down: bool) {
+            if down { self.buttons.insert(button); } else { self.buttons.remove(&button); }
+        }
+
+        /// Where the pointer is, in window pixels.
+        pub fn point_at(&mut self, x: i32, y: i32) {
+            self.pointer = Point2::new(x as f32, y as f32) / ZOOM as f32;
+        }
+
+        fn controller(&mut self, id: i32) -> &mut Input {
+            let i = match self.controllers.iter().position(|&(other, _)| other == id) {
+                Some(i) => i,
+                None => {
+                    self.controllers.push((id, Input::idle()));
+                    self.controllers.len() - 1
+                },
+            };
+            &mut self.controllers[i].1
+        }
+
+        /// A controller only shows up once one of its buttons gets pressed.
+        pub fn controller_button(&mut self, id: i32, button: Button, down: bool) {
+            let input = self.controller(id);
+            let direction = match button {
+                Button::A => { input.a = down; return; },
+                Button::B => { input.b = down; return; },
+                Button::DPadUp => Dpad::Up,
+                Button::DPadRight => Dpad::Right,
+                Button::DPadDown => Dpad::Down,
+                Button::DPadLeft => Dpad::Left,
+                _ => return,
+            };
+            if down {
+                input.d_pad = Some(direction);
+            }
+            else if input.d_pad == Some(direction) {
+                input.d_pad = None;
+            }
+        }
+    }
+
+    impl ElapsedDelta for Devices {
+        fn delta(&self) -> f32 {
+            self.delta
+        }
+    }
+
+    impl JoystickProvider for Devices {
+        fn available_joysticks(&self) -> Vec<JoystickId> {
+            (0..self.controllers.len()).collect()
+        }
+        fn joystick(&self, id: JoystickId) -> JoystickState {
+            self.controllers.get(id).map(|&(_, input)| input).unwrap_or_default().state()
+        }
+    }
+
+    impl KeyboardProvider for Devices {
+        fn is_key_pressed(&self, key: Key) -> bool {
+            self.keys.contains(&key)
+        }
+    }
+
+    impl MouseProvider for Devices {
+        fn mouse_position(&self) -> Point2<f32> {
+            self.pointer
+        }
+        fn is_mouse_button_pressed(&self, button: MouseButton) -> bool {
+            self.buttons.contains(&button)
+        }
+    }
+
+    fn key(keycode: Keycode) -> Option<Key> {
+        Some(match keycode {
+            Keycode::Up => Key::Up,
+            Keycode::Down => Key::Down,
+            Keycode::Left => Key::Left,
+            Keycode::Right => Key::Right,
+            Keycode::W => Key::W,
+            Keycode::A => Key::A,
+            Keycode::S => Key::S,
+            Keycode::D => Key::D,
+            Keycode::Z => Key::Z,
+            Keycode::X => Key::X,
+            _ => return None,
+        })
+    }
+
+    fn mouse_button(button: event::MouseButton) -> Option<MouseButton> {
+        match button {
+            event::MouseButton::Left => Some(MouseButton::Left),
+            event::MouseButton::Right => Some(MouseButton::Right),
+            _ => None,
+        }
+    }
+
+    /// Draws with ggez, doing the transforms itself so circles and rectangles turn and scale
+    /// like everything else.
+    pub struct Surface<'a> {
+        ctx: &'a mut Context,
+        font: &'a graphics::Font,
+        transform: Matrix3<f32>,
+    }
+
+    impl<'a> Surface<'a> {
+        fn point(&self, p: Point2<f32>) -> graphics::Point2 {
+            let v = self.transform * Vector3::new(p.x, p.y, 1.0);
+            graphics::Point2::new(v.x, v.y)
+        }
+
+        fn mode(&self, mode: DrawMode) -> graphics::DrawMode {
+            match mode {
+                DrawMode::Fill => graphics::DrawMode::Fill,
+                // The camera only ever scales evenly
+                DrawMode::Line(width) => graphics::DrawMode::Line(width * Vector2::new(self.transform[(0, 0)], self.transform[(1, 0)]).norm()),
+            }
+        }
+
+        fn shape(&mut self, mode: DrawMode, points: &[Point2<f32>]) {
+            let points: Vec<graphics::Point2> = points.iter().map(|p| self.point(*p)).collect();
+            let mode = self.mode(mode);
+            if let Err(error) = graphics::polygon(self.ctx, mode, &points) {
+                eprintln!("Can't draw: {:?}", error);
+            }
+        }
+    }
+
+    impl<'a> DrawPrimitives for Surface<'a> {
+        fn set_color<C: Color>(&mut self, color: C) {
+            let [r, g, b, a] = color.into_rgba();
+            let _ = graphics::set_color(self.ctx, graphics::Color::new(r, g, b, a));
+        }
+        fn clear<C: Color>(&mut self, color: C) {
+            let [r, g, b, a] = color.into_rgba();
+            graphics::set_background_color(self.ctx, graphics::Color::new(r, g, b, a));
+            graphics::clear(self.ctx);
+        }
+        fn present(&mut self) {
+            graphics::present(self.ctx);
+        }
+        fn circle(&mut self, mode: DrawMode, origin: Point2<f32>, radius: f32) {
+            self.ellipse(mode, origin, radius, radius);
+        }
+        fn ellipse(&mut self, mode: DrawMode, origin: Point2<f32>, width: f32, height: f32) {
+            let points: Vec<Point2<f32>> = (0..CIRCLE_SEGMENTS)
+                .map(|i| {
+                    let angle = i as f32 / CIRCLE_SEGMENTS as f32 * 2.0 * std::f32::consts::PI;
+                    origin + Vector2::new(angle.cos() * width, angle.sin() * height)
+                })
+                .collect();
+            self.shape(mode, &points);
+        }
+        fn rectangle(&mut self, mode: DrawMode, origin: Point2<f32>, width: f32, height: f32) {
+            self.shape(mode, &[
+                origin,
+                origin + Vector2::new(width, 0.0),
+                origin + Vector2::new(width, height),
+                origin + Vector2::new(0.0, height),
+            ]);
+        }
+        fn square_centered(&mut self, mode: DrawMode, origin: Point2<f32>, size: f32) {
+            let half = size / 2.0;
+            self.rectangle(mode, origin - Vector2::new(half, half), size, size);
+        }
+        fn polygon(&mut self, mode: DrawMode, points: &Vec<Point2<f32>>) {
+            self.shape(mode, points);
+        }
+        fn text(&mut self, origin: Point2<f32>, text: &str) {
+            let at = self.point(origin);
+            let drawn = graphics::Text::new(self.ctx, text, self.font)
+                .and_then(|text| graphics::draw(self.ctx, &text, at, 0.0));
+            if let Err(error) = drawn {
+                eprintln!("Can't write {:?}: {:?}", text, error);
+            }
+        }
+        fn get_transform(&self) -> Matrix3<f32> {
+            self.transform
+        }
+        fn set_transform(&mut self, transform: &Matrix3<f32>) {
+            self.transform = *transform;
+        }
+    }
+
+    struct Host<S> {
+        scene: S,
+        devices: Devices,
+        font: graphics::Font,
+    }
+
+    impl<S> EventHandler for Host<S>
+    where
+        S: Scene + Update<Devices> + for<'a> Draw<Surface<'a>>,
+    {
+        fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
+            self.devices.delta = timer::duration_to_f64(timer::get_delta(ctx)) as f32;
+            self.scene.update(&mut self.devices);
+            Ok(())
+        }
+        fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
+            let mut surface = Surface { ctx, font: &self.font, transform: Matrix3::identity() };
+            self.scene.draw(&mut surface);
+            Ok(())
+        }
+        fn key_down_event(&mut self, ctx: &mut Context, keycode: Keycode, _keymod: Mod, _repeat: bool) {
+            if keycode == Keycode::Escape {
+                let _ = ctx.quit();
+            }
+            if let Some(key) = key(keycode) {
+                self.devices.key(key, true);
+            }
+        }
+        fn key_up_event(&mut self, _ctx: &mut Context, keycode: Keycode, _keymod: Mod, _repeat: bool) {
+            if let Some(key) = key(keycode) {
+                self.devices.key(key, false);
+            }
+        }
+        fn mouse_button_down_event(&mut self, _ctx: &mut Context, button: event::MouseButton, x: i32, y: i32) {
+            self.devices.point_at(x, y);
+            if let Some(button) = mouse_button(button) {
+                self.devices.mouse_button(button, true);
+            }
+        }
+        fn mouse_button_up_event(&mut self, _ctx: &mut Context, button: event::MouseButton, x: i32, y: i32) {
+            self.devices.point_at(x, y);
+            if let Some(button) = mouse_button(button) {
+                self.devices.mouse_button(button, false);
+            }
+        }
+        fn mouse_motion_event(&mut self, _ctx: &mut Context, _state: MouseState, x: i32, y: i32, _xrel: i32, _yrel: i32) {
+            self.devices.point_at(x, y);
+        }
+        fn controller_button_down_event(&mut self, _ctx: &mut Context, button: Button, instance_id: i32) {
+            self.devices.controller_button(instance_id, button, true);
+        }
+        fn controller_button_up_event(&mut self, _ctx: &mut Context, button: Button, instance_id: i32) {
+            self.devices.controller_button(instance_id, button, false);
+        }
+    }
+
+    /// Opens the window and plays the scene in it until it gets closed.
+    pub fn run<S>(scene: S)
+    where
+        S: Scene + Update<Devices> + for<'a> Draw<Surface<'a>>,
+    {
+        let mut c = conf::Conf::new();
+        c.window_setup.title = "Walden".to_string();
+        c.window_mode.width = WIDTH * ZOOM;
+        c.window_mode.height = HEIGHT * ZOOM;
+        let played = Context::load_from_conf("walden", "teglvaerk", c).and_then(|mut ctx| {
+            graphics::set_screen_coordinates(&mut ctx, graphics::Rect::new(0.0, 0.0, WIDTH as f32, HEIGHT as f32))?;
+            let font = graphics::Font::default_font()?;
+            let mut host = Host { scene, devices: Devices::new(), font };
+            event::run(&mut ctx, &mut host)
+        });
+        if let Err(error) = played {
+            eprintln!("The window closed on an error: {:?}", error);
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn play(game: &mut Walden, devices: &mut Devices, frames: u32) {
+            devices.delta = STEP;
+            for _ in 0..frames {
+                game.update(devices);
+            }
+        }
+
+        #[test]
+        fn the_keyboard_plays_in_the_window() {
+            let mut game = Walden::with_world(World::from_seed(0));
+            let mut devices = Devices::new();
+            let start = game.world.player_position(0).unwrap();
+            devices.key(Key::W, true);
+            play(&mut game, &mut devices, 30);
+            devices.key(Key::W, false);
+            play(&mut game, &mut devices, 30);
+            assert!(game.world.player_position(0).unwrap().y < start.y - 10.0, "W didn't walk the player up");
+        }
+
+        #[test]
+        fn the_mouse_points_the_selector_the_window_way() {
+            let mut devices = Devices::new();
+            devices.point_at(WIDTH as i32 * 2 - 10, HEIGHT as i32);
+            devices.mouse_button(MouseButton::Left, true);
+            assert_eq!(controls::read(&devices, 0), Input { d_pad: Some(Dpad::Right), a: true, b: false });
+            devices.mouse_button(MouseButton::Left, false);
+            assert_eq!(controls::read(&devices, 0), Input::idle());
+        }
+
+        #[test]
+        fn controllers_join_when_used() {
+            let mut game = Walden::with_world(World::from_seed(0));
+            let mut devices = Devices::new();
+            devices.controller_button(7, Button::DPadLeft, true);
+            play(&mut game, &mut devices, 1);
+            assert_eq!(game.world.player_count(), 1);
+            assert_eq!(controls::read(&devices, 0).d_pad, Some(Dpad::Left));
+
+            devices.controller_button(3, Button::A, true);
+            play(&mut game, &mut devices, 1);
+            assert_eq!(game.world.player_count(), 2);
+            devices.controller_button(3, Button::A, false);
+            devices.controller_button(7, Button::DPadLeft, false);
+            assert_eq!(controls::read(&devices, 0), Input::idle());
+        }
+    }
+}
+
+#[cfg(test)]
+mod headless {
+    use super::*;
+    use controls::{Input, Key, MouseButton, DIRECTION_KEYS, A_KEY, B_KEY};
+    use mursten::graphics::{Color, DrawMode};
+    use mursten::input::{JoystickState, JoystickId};
+
+    /// Inputs held for a number of frames each, one after the other. Once it runs out the
+    /// joystick stays idle.
+    #[derive(Clone, Debug, Default)]
+    pub struct Script {
+        steps: Vec<(Input, u32)>,
+    }
+
+    impl Script {
+        pub fn new() -> Self {
+            Self::default()
+        }
+        pub fn hold(mut self, input: Input, frames: u32) -> Self {
+            self.steps.push((input, frames));
+            self
+        }
+        pub fn wait(self, frames: u32) -> Self {
+            self.hold(Input::idle(), frames)
+        }
+        /// Press and release, leaving the button down long enough for the selector to settle.
+        pub fn tap(self, input: Input) -> Self {
+            self.hold(input, 2).wait(2)
+        }
+        pub fn len(&self) -> u32 {
+            self.steps.iter().map(|&(_, frames)| frames).sum()
+        }
+        pub fn input_at(&self, frame: u32) -> Input {
+            let mut start = 0;
+            for &(input, frames) in self.steps.iter() {
+                if frame < start + frames {
+                    return input;
+                }
+                start += frames;
+            }
+            Input::idle()
+        }
+    }
+
+    /// What the script gets played on.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum Device {
+        Joystick,
+        Keyboard,
+    }
+
+    /// A backend with no window: a fixed timestep, a joystick or keyboard driven by a script and
+    /// a surface that only keeps count of what gets drawn.
+    pub struct Headless {
+        delta: f32,
+        frame: u32,
+        script: Script,
+        /// Joysticks for the other players.
+        others: Vec<Script>,
+        device: Device,
+        transform: Matrix3<f32>,
+        pub draw_calls: usize,
+        pub presented: u32,
+    }
+
+    impl Headless {
+        pub fn new(delta: f32, script: Script) -> Self {
+            Self {
+                delta,
+                frame: 0,
+                script,
+                others: vec![],
+                device: Device::Joystick,
+                transform: Matrix3::identity(),
+                draw_calls: 0,
+                presented: 0,
+            }
+        }
+        /// Plays the script on the keyboard instead, with no joystick plugged in.
+        pub fn on_keyboard(self) -> Self {
+            Self { device: Device::Keyboard, ..self }
+        }
+        /// Plugs in one more joystick, for the next player.
+        pub fn with_joystick(mut self, script: Script) -> Self {
+            self.others.push(script);
+            self
+        }
+        pub fn frame(&self) -> u32 {
+            self.frame
+        }
+        pub fn input(&self) -> Input {
+            self.script.input_at(self.frame)
+        }
+        /// Runs one frame of the scene, updating then drawing it like the real game loop does.
+        pub fn step<G>(&mut self, game: &mut G)
+        where
+            G: Update<Self> + Draw<Self>,
+        {
+            game.update(self);
+            game.draw(self);
+            self.frame += 1;
+        }
+        pub fn run<G>(&mut self, game: &mut G, frames: u32)
+        where
+            G: Update<Self> + Draw<Self>,
+        {
+            for _ in 0..frames {
+                self.step(game);
+            }
+        }
+    }
+
+    impl ElapsedDelta for Headless {
+        fn delta(&self) -> f32 {
+            self.delta
+        }
+    }
+
+    impl JoystickProvider for Headless {
+        fn available_joysticks(&self) -> Vec<JoystickId> {
+            if self.device == Device::Joystick { (0..self.others.len() + 1).collect() } else { vec![] }
+        }
+        fn joystick(&self, id: JoystickId) -> JoystickState {
+            match id {
+                0 => self.input().state(),
+                id => self.others.get(id - 1).map(|script| script.input_at(self.frame)).unwrap_or_default().state(),
+            }
+        }
+    }
+
+    impl KeyboardProvider for Headless {
+        fn is_key_pressed(&self, key: Key) -> bool {
+            if self.device != Device::Keyboard {
+                return false;
+            }
+            let input = self.input();
+            // Always the first key of each pair, the arrows
+            let direction = input.d_pad
+                .and_then(|d| Player::HANDS.iter().position(|hand| *hand == d))
+                .map(|i| DIRECTION_KEYS[i][0] == key)
+                .unwrap_or(false);
+            direction || (input.a && key == A_KEY) || (input.b && key == B_KEY)
+        }
+    }
+
+    impl MouseProvider for Headless {
+        fn mouse_position(&self) -> Point2<f32> {
+            Point2::new(160.0, 120.0)
+        }
+        fn is_mouse_button_pressed(&self, _button: MouseButton) -> bool {
+            false
+        }
+    }
+
+    impl DrawPrimitives for Headless {
+        fn set_color<C: Color>(&mut self, _color: C) {}
+        fn clear<C: Color>(&mut self, _color: C) {
+            self.draw_calls += 1;
+        }
+        fn present(&mut self) {
+            self.presented += 1;
+        }
+        fn circle(&mut self, _mode: DrawMode, _origin: Point2<f32>, _radius: f32) {
+            self.draw_calls += 1;
+        }
+        fn ellipse(&mut self, _mode: DrawMode, _origin: Point2<f32>, _width: f32, _height: f32) {
+            self.draw_calls += 1;
+        }
+        fn rectangle(&mut self, _mode: DrawMode, _origin: Point2<f32>, _width: f32, _height: f32) {
+            self.draw_calls += 1;
+        }
+        fn square_centered(&mut self, _mode: DrawMode, _origin: Point2<f32>, _size: f32) {
+            self.draw_calls += 1;
+        }
+        fn polygon(&mut self, _mode: DrawMode, _points: &Vec<Point2<f32>>) {
+            self.draw_calls += 1;
+        }
+        fn text(&mut self, _origin: Point2<f32>, _text: &str) {
+            self.draw_calls += 1;
+        }
+        fn get_transform(&self) -> Matrix3<f32> {
+            self.transform
+        }
+        fn set_transform(&mut self, transform: &Matrix3<f32>) {
+            self.transform = *transform;
+        }
+    }
+
+    mod tests {
+        use super::*;
+        use replay::{Frame, world_bytes};
+
+        /// Walks around the camp, grabs whatever is at hand and waits, ten seconds all told.
+        fn session() -> Script {
+            Script::new()
+                .hold(Input::walk(Dpad::Right), 90)
+                .hold(Input::walk(Dpad::Down), 60)
+                .tap(Input::a())
+                .hold(Input::walk(Dpad::Left), 120)
+                .tap(Input::b())
+                .wait(322)
+        }
+
+        #[test]
+        fn walking_takes_the_player_that_way() {
+            let mut game = Walden::with_world(World::from_seed(0));
+            let start = game.world.player_position(0).unwrap();
+            let mut backend = Headless::new(1.0 / 60.0, Script::new().hold(Input::walk(Dpad::Up), 30));
+            backend.run(&mut game, 30);
+            let position = game.world.player_position(0).unwrap();
+            assert!(position.y < start.y - 10.0, "The player only got from {:?} to {:?}", start, position);
+            assert_eq!(position.x, start.x);
+
+            // and slows down to a stop once they let go
+            backend.run(&mut game, 30);
+            let stopped = game.world.player_position(0).unwrap();
+            backend.run(&mut game, 30);
+            assert_eq!(game.world.player_position(0).unwrap(), stopped);
+        }
+
+        #[test]
+        fn ten_seconds_around_the_camp() {
+            let script = session();
+            let mut backend = Headless::new(1.0 / 60.0, script.clone());
+            let mut game = Walden::with_world(World::from_seed(0));
+            let entities = game.world.content.node_count();
+            backend.run(&mut game, script.len());
+
+            assert_eq!(backend.frame(), script.len());
+            assert_eq!(backend.presented, script.len());
+            assert!(backend.draw_calls > backend.presented as usize, "Frames were presented with nothing on them");
+            assert_eq!(game.state, GameState::Playing);
+            let player = game.world.player(0).unwrap();
+            assert_eq!(player.health, 1.0);
+            assert!(player.hunger < 1.0 && player.thirst < player.hunger, "Ten seconds left the player at {} hunger and {} thirst", player.hunger, player.thirst);
+            assert!(game.world.content.node_count() > entities, "No land was generated around the camp");
+        }
+
+        #[test]
+        fn a_minute_without_water_is_too_long() {
+            let mut backend = Headless::new(1.0 / 60.0, Script::new());
+            let mut game = Walden::with_world(World::from_seed(0));
+            backend.run(&mut game, 3600);
+            assert!(game.world.player(0).unwrap().is_dead());
+            assert_eq!(game.state, GameState::GameOver { days: 0 });
+        }
+
+        #[test]
+        fn pressing_a_after_game_over_starts_over() {
+            let mut game = Walden::with_world(World::with_players(0, 2));
+            let mut backend = Headless::new(1.0 / 60.0, Script::new().wait(3600).tap(Input::a()).wait(60))
+                .with_joystick(Script::new());
+            backend.run(&mut game, 3600);
+            assert!(game.is_game_over());
+            let seed = game.world.seed;
+
+            // Holding the button does nothing until it is let go
+            backend.run(&mut game, 1);
+            assert!(game.is_game_over());
+            backend.run(&mut game, 60);
+            assert_eq!(game.state, GameState::Playing);
+            assert_ne!(game.world.seed, seed, "Started over in the same world");
+            assert_eq!(game.world.player_count(), 2);
+            assert_eq!(game.seats.len(), 2);
+            for p in 0..2 {
+                let player = game.world.player(p).unwrap();
+                assert!(!player.is_dead());
+                assert!(player.thirst > 0.9, "Player {} started over thirsty", p + 1);
+            }
+            assert_eq!(game.world.days_survived(), 0);
+        }
+
+        #[test]
+        fn keyboard_plays_like_a_joystick() {
+
+            let script = session();
+            let mut on_joystick = Walden::with_world(World::from_seed(0));
+            Headless::new(1.0 / 60.0, script.clone()).run(&mut on_joystick, script.len());
+            let mut on_keyboard = Walden::with_world(World::from_seed(0));
+            let mut keyboard = Headless::new(1.0 / 60.0, script.clone()).on_keyboard();
+            assert!(keyboard.available_joysticks().is_empty());
+            keyboard.run(&mut on_keyboard, script.len());
+
+            assert!(world_bytes(&on_keyboard.world) == world_bytes(&on_joystick.world));
+            assert_eq!(on_keyboard.world.player_position(0), on_joystick.world.player_position(0));
+        }
+
+        #[test]
+        fn players_move_on_their_own_joysticks() {
+            let mut game = Walden::with_world(World::with_players(0, 2));
+            let starts = (game.world.player_position(0).unwrap(), game.world.player_position(1).unwrap());
+            let mut backend = Headless::new(1.0 / 60.0, Script::new().hold(Input::walk(Dpad::Left), 30))
+                .with_joystick(Script::new().hold(Input::walk(Dpad::Right), 30));
+            backend.run(&mut game, 30);
+            assert!(game.world.player_position(0).unwrap().x < starts.0.x - 10.0);
+            assert!(game.world.player_position(1).unwrap().x > starts.1.x + 10.0);
+        }
+
+        #[test]
+        fn pressing_a_on_another_joystick_joins_in() {
+            let mut game = Walden::with_world(World::from_seed(0));
+            let mut backend = Headless::new(1.0 / 60.0, Script::new())
+                .with_joystick(Script::new().hold(Input::walk(Dpad::Right), 30).tap(Input::a()).hold(Input::walk(Dpad::Right), 30));
+            backend.run(&mut game, 30);
+            assert_eq!(game.world.player_count(), 1, "Moving the d-pad was enough to join");
+
+            backend.run(&mut game, 4);
+            assert_eq!(game.world.player_count(), 2);
+            assert_eq!(game.seats.len(), 2);
+            assert_eq!(game.seats[1].selector.state, SelectorState::Idle);
+            let hands = game.world.player(1).unwrap().hands.clone();
+            let start = game.world.player_position(1).unwrap();
+            backend.run(&mut game, 30);
+            assert!(game.world.player_position(1).unwrap().x > start.x + 10.0, "The new player doesn't move on their joystick");
+            assert_eq!(game.world.player(1).unwrap().hands, hands, "Joining did something with the player's hands");
+        }
+
+        /// Frame rates the game has to play the same at.
+        const FRAME_RATES: [u32; 3] = [30, 60, 144];
+
+        #[test]
+        fn frame_rate_does_not_change_the_game() {
+            // In thirtieths of a second, which every frame rate can hit
+            let script = Script::new()
+                .hold(Input::walk(Dpad::Right), 45)
+                .hold(Input::walk(Dpad::Down), 30)
+                .tap(Input::a())
+                .hold(Input::walk(Dpad::Left), 60)
+                .hold(Input::a().with_d_pad(Dpad::Left), 15)
+                .hold(Input::walk(Dpad::Up), 90)
+                .tap(Input::b())
+                .wait(300);
+            let seconds = script.len() / 30;
+
+            let mut expected = None;
+            for &fps in FRAME_RATES.iter() {
+                let mut game = Walden::with_world(World::from_seed(0));
+                for f in 0..seconds * fps {
+                    let mut frame = Frame { inputs: vec![script.input_at(f * 30 / fps)], delta: 1.0 / fps as f32 };
+                    game.update(&mut frame);
+                }
+                let played = (world_bytes(&game.world), game.world.player_position(0));
+                match expected {
+                    None => expected = Some(played),
+                    Some(ref expected) => assert!(played == *expected, "Playing at {} fps changed how the game played out", fps),
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod raster {
+    use super::*;
+    use mursten::graphics::{Color, DrawMode, PushTransform};
+    use headless::{Headless, Script};
+    use controls::Input;
+    use std::path::{Path, PathBuf};
+
+    /// Draws on the CPU into an RGBA buffer, so frames can be saved and compared without a GPU.
+    pub struct Canvas {
+        width: u32,
+        height: u32,
+        pixels: Vec<u8>,
+        color: [f32; 4],
+        transform: Matrix3<f32>,
+    }
+
+    const CIRCLE_SEGMENTS: usize = 32;
+    const GLYPH_SCALE: f32 = 2.0;
+    const GLYPH_ADVANCE: f32 = 8.0;
+
+    /// 3x5 glyphs, one row per entry, most significant bit on the left.
+    fn glyph(c: char) -> Option<[u8; 5]> {
+        let rows = match c.to_ascii_uppercase() {
+            ' ' => return None,
+            'A' => [2, 5, 7, 5, 5], 'B' => [6, 5, 6, 5, 6], 'C' => [3, 4, 4, 4, 3], 'D' => [6, 5, 5, 5, 6],
+            'E' => [7, 4, 6, 4, 7], 'F' => [7, 4, 6, 4, 4], 'G' => [3, 4, 5, 5, 3], 'H' => [5, 5, 7, 5, 5],
+            'I' => [7, 2, 2, 2, 7], 'J' => [1, 1, 1, 5, 2], 'K' => [5, 5, 6, 5, 5], 'L' => [4, 4, 4, 4, 7],
+            'M' => [5, 7, 7, 5, 5], 'N' => [6, 5, 5, 5, 5], 'O' => [2, 5, 5, 5, 2], 'P' => [6, 5, 6, 4, 4],
+            'Q' => [2, 5, 5, 6, 3], 'R' => [6, 5, 6, 5, 5], 'S' => [3, 4, 2, 1, 6], 'T' => [7, 2, 2, 2, 2],
+            'U' => [5, 5, 5, 5, 7], 'V' => [5, 5, 5, 5, 2], 'W' => [5, 5, 7, 7, 5], 'X' => [5, 5, 2, 5, 5],
+            'Y' => [5, 5, 2, 2, 2], 'Z' => [7, 1, 2, 4, 7],
+            '0' => [7, 5, 5, 5, 7], '1' => [2, 6, 2, 2, 7], '2' => [6, 1, 2, 4, 7], '3' => [6, 1, 2, 1, 6],
+            '4' => [5, 5, 7, 1, 1], '5' => [7, 4, 6, 1, 6], '6' => [3, 4, 7, 5, 7], '7' => [7, 1, 1, 2, 2],
+            '8' => [7, 5, 7, 5, 7], '9' => [7, 5, 7, 1, 6],
+            ':' => [0, 2, 0, 2, 0], '!' => [2, 2, 2, 0, 2], '.' => [0, 0, 0, 0, 2], '\'' => [2, 2, 0, 0, 0],
+            '-' => [0, 0, 7, 0, 0], '?' => [6, 1, 2, 0, 2],
+            _ => [7, 7, 7, 7, 7],
+        };
+        Some(rows)
+    }
+
+    impl Canvas {
+        pub fn new(width: u32, height: u32) -> Self {
+            Self {
+                width,
+                height,
+                pixels: vec![0; (width * height * 4) as usize],
+                color: [1.0, 1.0, 1.0, 1.0],
+                transform: Matrix3::identity(),
+            }
+        }
+
+        fn to_screen(&self, p: Point2<f32>) -> Point2<f32> {
+            let v = self.transform * Vector3::new(p.x, p.y, 1.0);
+            Point2::new(v.x, v.y)
+        }
+
+        /// How much the current transform stretches lengths, used for line widths.
+        fn scale(&self) -> f32 {
+            let m = &self.transform;
+            (m[(0, 0)] * m[(1, 1)] - m[(0, 1)] * m[(1, 0)]).abs().sqrt()
+        }
+
+        fn blend(&mut self, x: i32, y: i32) {
+            if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+                return;
+            }
+            let i = ((y as u32 * self.width + x as u32) * 4) as usize;
+            let alpha = self.color[3].clamp(0.0, 1.0);
+            for c in 0..3 {
+                let src = self.color[c].clamp(0.0, 1.0) * 255.0;
+                let dst = self.pixels[i + c] as f32;
+                self.pixels[i + c] = (src * alpha + dst * (1.0 - alpha)).round() as u8;
+            }
+            let dst = self.pixels[i + 3] as f32 / 255.0;
+            self.pixels[i + 3] = ((alpha + dst * (1.0 - alpha)) * 255.0).round() as u8;
+        }
+
+        /// Even-odd scanline fill of a polygon already in screen space, sampling pixel centers.
+        fn fill(&mut self, points: &[Point2<f32>]) {
+            if points.len() < 3 {
+                return;
+            }
+            let top = points.iter().map(|p| p.y).fold(f32::INFINITY, f32::min).floor().max(0.0) as i32;
+            let bottom = points.iter().map(|p| p.y).fold(f32::NEG_INFINITY, f32::max).ceil().min(self.height as f32) as i32;
+            let mut crossings = vec![];
+            for y in top..bottom {
+                let sample = y as f32 + 0.5;
+                crossings.clear();
+                for i in 0..points.len() {
+                    let (a, b) = (points[i], points[(i + 1) % points.len()]);
+                    if (a.y <= sample) != (b.y <= sample) {
+                        crossings.push(a.x + (sample - a.y) / (b.y - a.y) * (b.x - a.x));
+                    }
+                }
+                crossings.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                for pair in crossings.chunks(2) {
+                    if pair.len() < 2 {
+                        continue;
+                    }
+                    let from = (pair[0] - 0.5).ceil().max(0.0) as i32;
+                    let to = (pair[1] - 0.5).ceil().min(self.width as f32) as i32;
+                    for x in from..to {
+                        self.blend(x, y);
+                    }
+                }
+            }
+        }
+
+        /// Outline of a closed shape given in local space, as one quad per side.
+        fn stroke(&mut self, points: &[Point2<f32>], width: f32) {
+            let points: Vec<Point2<f32>> = points.iter().map(|p| self.to_screen(*p)).collect();
+            let half = width * self.scale() / 2.0;
+            for i in 0..points.len() {
+                let (a, b) = (points[i], points[(i + 1) % points.len()]);
+                let along = b - a;
+                if along.norm() == 0.0 {
+                    continue;
+                }
+                let normal = Vector2::new(-along.y, along.x).normalize() * half;
+                let extend = along.normalize() * half;
+                self.fill(&[a - extend + normal, b + extend + normal, b + extend - normal, a - extend - normal]);
+            }
+        }
+
+        fn shape(&mut self, mode: DrawMode, points: &[Point2<f32>]) {
+            match mode {
+                DrawMode::Fill => {
+                    let points: Vec<Point2<f32>> = points.iter().map(|p| self.to_screen(*p)).collect();
+                    self.fill(&points);
+                },
+                DrawMode::Line(width) => self.stroke(points, width),
+            }
+        }
+
+        pub fn save(&self, path: &Path) -> std::io::Result<()> {
+            image::RgbaImage::from_raw(self.width, self.height, self.pixels.clone())
+                .expect("the buffer always matches the canvas size")
+                .save(path)
+        }
+
+        pub fn load(path: &Path) -> image::ImageResult<Self> {
+            let image = image::open(path)?.to_rgba();
+            let mut canvas = Canvas::new(image.width(), image.height());
+            canvas.pixels = image.into_raw();
+            Ok(canvas)
+        }
+
+        /// Pixels with any channel further apart than `tolerance`, or None if the sizes differ.
+        pub fn differing_pixels(&self, other: &Canvas, tolerance: u8) -> Option<usize> {
+            if (self.width, self.height) != (other.width, other.height) {
+                return None;
+            }
+            let count = self.pixels.chunks(4).zip(other.pixels.chunks(4))
+                .filter(|&(a, b)| a.iter().zip(b.iter()).any(|(a, b)| (*a as i16 - *b as i16).abs() > tolerance as i16))
+                .count();
+            Some(count)
+        }
+    }
+
+    impl DrawPrimitives for Canvas {
+        fn set_color<C: Color>(&mut self, color: C) {
+            self.color = color.into_rgba();
+        }
+        fn clear<C: Color>(&mut self, color: C) {
+            let rgba = color.into_rgba();
+            let bytes: Vec<u8> = rgba.iter().map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8).collect();
+            for pixel in self.pixels.chunks_mut(4) {
+                pixel.copy_from_slice(&bytes);
+            }
+        }
+        fn present(&mut self) {}
+        fn circle(&mut self, mode: DrawMode, origin: Point2<f32>, radius: f32) {
+            self.ellipse(mode, origin, radius, radius);
+        }
+        fn ellipse(&mut self, mode: DrawMode, origin: Point2<f32>, width: f32, height: f32) {
+            let points: Vec<Point2<f32>> = (0..CIRCLE_SEGMENTS)
+                .map(|i| {
+                    let angle = i as f32 / CIRCLE_SEGMENTS as f32 * 2.0 * std::f32::consts::PI;
+                    origin + Vector2::new(angle.cos() * width, angle.sin() * height)
+                })
+                .collect();
+            self.shape(mode, &points);
+        }
+        fn rectangle(&mut self, mode: DrawMode, origin: Point2<f32>, width: f32, height: f32) {
+            self.shape(mode, &[
+                origin,
+                origin + Vector2::new(width, 0.0),
+                origin + Vector2::new(width, height),
+                origin + Vector2::new(0.0, height),
+            ]);
+        }
+        fn square_centered(&mut self, mode: DrawMode, origin: Point2<f32>, size: f32) {
+            let half = size / 2.0;
+            self.rectangle(mode, origin - Vector2::new(half, half), size, size);
+        }
+        fn polygon(&mut self, mode: DrawMode, points: &Vec<Point2<f32>>) {
+            self.shape(mode, points);
+        }
+        fn text(&mut self, origin: Point2<f32>, text: &str) {
+            for (i, c) in text.chars().enumerate() {
+                let rows = match glyph(c) {
+                    Some(rows) => rows,
+                    None => continue,
+                };
+                let corner = origin + Vector2::new(i as f32 * GLYPH_ADVANCE + 1.0, 3.0);
+                for (y, row) in rows.iter().enumerate() {
+                    for x in 0..3 {
+                        if row & (4 >> x) != 0 {
+                            let at = corner + Vector2::new(x as f32, y as f32) * GLYPH_SCALE;
+                            self.rectangle(DrawMode::Fill, at, GLYPH_SCALE, GLYPH_SCALE);
+                        }
+                    }
+                }
+            }
+        }
+        fn get_transform(&self) -> Matrix3<f32> {
+            self.transform
+        }
+        fn set_transform(&mut self, transform: &Matrix3<f32>) {
+            self.transform = *transform;
+        }
+    }
+
+    /// Channel difference that still counts as the same color.
+    const TOLERANCE: u8 = 8;
+    /// Share of pixels allowed to go over the tolerance before a golden image counts as broken.
+    const MAX_DIFFERING: f32 = 0.002;
+
+    fn golden_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("golden")
+    }
+
+    fn entities() -> Canvas {
+        let kinds = vec![
+            EntityKind::Player(Player::new()),
+            EntityKind::Bag(Inventory::bag()),
+            EntityKind::Tent(Inventory::tent()),
+            EntityKind::Grass,
+            EntityKind::Stone,
+            EntityKind::Tree(TREE_HITS),
+            EntityKind::Stump(0.0),
+            EntityKind::Bush(Bush::new()),
+            EntityKind::Mushroom,
+            EntityKind::Axe,
+            EntityKind::Pond(20.0),
+            EntityKind::Dropped(Item::Berry),
+            EntityKind::Monster(Monster::Mouse, Brain::new()),
+            EntityKind::Campfire(Fire::new()),
+        ];
+        let mut canvas = Canvas::new(320, 240);
+        canvas.clear(Palette::Grass);
+        for (i, kind) in kinds.into_iter().enumerate() {
+            let position = Point2::new(32.0 + 64.0 * (i % 5) as f32, 60.0 + 80.0 * (i / 5) as f32);
+            Entity::new(kind, position).draw(&mut canvas);
+        }
+        canvas
+    }
+
+    fn items() -> Canvas {
+        let items = [
+            Item::Axe, Item::Berry, Item::Bottle(false), Item::Bottle(true), Item::Log, Item::Mushroom(false), Item::Mushroom(true),
+            Item::Stone, Item::Grass, Item::Rope,
+        ];
+        let mut canvas = Canvas::new(240, 40);
+        canvas.clear(Palette::Grass);
+        for (i, item) in items.iter().enumerate() {
+            item.draw(&mut PushTransform::new(&mut canvas, convert(Translation2::new(12.0 + 22.0 * i as f32, 24.0))));
+        }
+        canvas
+    }
+
+    fn game_over() -> Canvas {
+        let mut canvas = Canvas::new(320, 240);
+        canvas.clear(Palette::Void);
+        GameOverScreen { days: 3 }.draw(&mut PushTransform::new(&mut canvas, convert(Translation2::new(160.0, 120.0))));
+        canvas
+    }
+
+    /// A whole frame of the game after a scripted walk, HUD and selector included.
+    fn walden(frames: u32, script: Script) -> Canvas {
+        walden_together(frames, vec![script])
+    }
+
+    /// Same with a player and a joystick for each script.
+    fn walden_together(frames: u32, scripts: Vec<Script>) -> Canvas {
+        let mut scripts = scripts.into_iter();
+        let mut backend = Headless::new(1.0 / 60.0, scripts.next().unwrap_or_default());
+        let mut players = 1;
+        for script in scripts {
+            backend = backend.with_joystick(script);
+            players += 1;
+        }
+        let mut game = Walden::with_world(World::with_players(1, players));
+        backend.run(&mut game, frames);
+        let mut canvas = Canvas::new(320, 240);
+        game.draw(&mut canvas);
+        canvas
+    }
+
+    /// The selector open with a stone and a log in hand, offering to make an axe.
+    fn walden_crafting() -> Canvas {
+        let mut world = World::from_seed(1);
+        if let Some(player) = world.player_mut(0) {
+            player.hands.insert(Dpad::Up, Item::Stone);
+            player.hands.insert(Dpad::Right, Item::Log);
+        }
+        let mut game = Walden::with_world(world);
+        Headless::new(1.0 / 60.0, Script::new().hold(Input::a().with_d_pad(Dpad::Right), 30)).run(&mut game, 30);
+        let mut canvas = Canvas::new(320, 240);
+        game.draw(&mut canvas);
+        canvas
+    }
+
+    /// Midnight by a campfire with a mushroom on it, lighting up the dark around the player.
+    fn walden_campfire() -> Canvas {
+        let mut world = World::from_seed(1);
+        let midnight = (24.0 - Clock::DAWN) / 24.0 * world.clock.day_length();
+        world.clock.advance(midnight);
+        if let Some(position) = world.player_position(0) {
+            let fire = Fire { cooking: Some((Item::Mushroom(false), 0.0)), ..Fire::new() };
+            world.add_entity(Entity::new(EntityKind::Campfire(fire), position + Vector2::new(30.0, 0.0)));
+        }
+        let mut game = Walden::with_world(world);
+        Headless::new(1.0 / 60.0, Script::new()).run(&mut game, 1);
+        let mut canvas = Canvas::new(320, 240);
+        game.draw(&mut canvas);
+        canvas
+    }
+
+    fn scenes() -> Vec<(&'static str, Canvas)> {
+        vec![
+            ("entities", entities()),
+            ("items", items()),
+            ("game_over", game_over()),
+            ("walden_start", walden(1, Script::new())),
+            ("walden_walk", walden(120, Script::new().hold(Input::walk(Dpad::Right), 60).hold(Input::walk(Dpad::Down), 60))),
+            ("walden_selector", walden(40, Script::new().wait(10).hold(Input::a().with_d_pad(Dpad::Left), 30))),
+            ("walden_crafting", walden_crafting()),
+            ("walden_campfire", walden_campfire()),
+            ("walden_twins", walden_together(150, vec![
+                Script::new().hold(Input::walk(Dpad::Left), 120).hold(Input::a().with_d_pad(Dpad::Up), 30),
+                Script::new().hold(Input::walk(Dpad::Down), 150),
+            ])),
+        ]
+    }
+
+    /// Renders every scene and compares it with its golden image. With WALDEN_BLESS=1 the golden
+    /// images get overwritten instead.
+    #[test]
+    fn golden_images() {
+        let bless = std::env::var("WALDEN_BLESS").map(|bless| bless == "1").unwrap_or(false);
+        let dir = golden_dir();
+        let mut failed = vec![];
+        for (name, canvas) in scenes() {
+            let path = dir.join(format!("{}.png", name));
+            if bless {
+                std::fs::create_dir_all(&dir).and_then(|_| canvas.save(&path))
+                    .unwrap_or_else(|error| panic!("Can't write {}: {}", path.display(), error));
+                continue;
+            }
+            let expected = Canvas::load(&path)
+                .unwrap_or_else(|error| panic!("Can't read {}: {}", path.display(), error));
+            let total = (canvas.width * canvas.height) as f32;
+            match canvas.differing_pixels(&expected, TOLERANCE) {
+                Some(differing) if differing as f32 / total <= MAX_DIFFERING => {},
+                result => {
+                    let actual = std::env::temp_dir().join(format!("{}.actual.png", name));
+                    let _ = canvas.save(&actual);
+                    match result {
+                        Some(differing) => failed.push(format!("{}: {} pixels differ, see {}", name, differing, actual.display())),
+                        None => failed.push(format!("{}: size changed, see {}", name, actual.display())),
+                    }
+                },
+            }
+        }
+        assert!(failed.is_empty(), "Golden images changed:\n{}", failed.join("\n"));
+    }
+}
+
+mod save {
+    use super::*;
+    use codec::{Encode, Writer, Reader, DecodeError};
+    use petgraph::visit::NodeIndexable;
+    use std::fmt;
+    use std::fs;
+    use std::io;
+    use std::path::{Path, PathBuf};
+
+    const MAGIC: &[u8] = b"WALDEN";
+    /// Goes up every time the layout of a save changes. Older saves are refused rather than
+    /// guessed at.
+    pub const VERSION: u32 = 8;
+
+    /// Why a save couldn't be loaded.
+    #[derive(Debug)]
+    pub enum LoadError {
+        Io(io::Error),
+        NotASave,
+        UnsupportedVersion(u32),
+        Corrupt,
+        Invalid(DecodeError),
+        /// Something was left over after the whole game was read.
+        TrailingBytes,
+        /// The UI was saved for a different number of players than the world has.
+        SeatsMismatch { seats: usize, players: usize },
+    }
+
+    impl fmt::Display for LoadError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                LoadError::Io(error) => write!(f, "can't read the file: {}", error),
+                LoadError::NotASave => write!(f, "not a Walden save"),
+                LoadError::UnsupportedVersion(version) => write!(f, "saved by version {} of the format, this game reads version {}", version, VERSION),
+                LoadError::Corrupt => write!(f, "the save is damaged, its checksum doesn't match"),
+                LoadError::Invalid(error) => write!(f, "the save doesn't describe a valid game: {:?}", error),
+                LoadError::TrailingBytes => write!(f, "the save goes on after the end of the game"),
+                LoadError::SeatsMismatch { seats, players } => write!(f, "the save has {} seat(s) for {} player(s)", seats, players),
+            }
+        }
+    }
+
+    impl From<io::Error> for LoadError {
+        fn from(error: io::Error) -> Self {
+            LoadError::Io(error)
+        }
+    }
+
+    impl From<DecodeError> for LoadError {
+        fn from(error: DecodeError) -> Self {
+            LoadError::Invalid(error)
+        }
+    }
+
+    /// FNV-1a, enough to tell a damaged file from a good one.
+    pub fn checksum(bytes: &[u8]) -> u32 {
+        bytes.iter().fold(0x811c9dc5, |hash, b| (hash ^ *b as u32).wrapping_mul(0x01000193))
+    }
+
+    impl Encode for GameState {
+        fn encode(&self, w: &mut Writer) {
+            match *self {
+                GameState::Playing => w.u8(0),
+                GameState::GameOver { days } => {
+                    w.u8(1);
+                    w.u32(days);
+                },
+            }
+        }
+        fn decode(r: &mut Reader) -> Result<Self, DecodeError> {
+            match r.u8()? {
+                0 => Ok(GameState::Playing),
+                1 => Ok(GameState::GameOver { days: r.u32()? }),
+                t => Err(DecodeError::InvalidTag("game state", t)),
+            }
+        }
+    }
+
+    /// Where the game is kept between runs, WALDEN_SAVE or walden.save in the working directory.
+    pub fn save_path() -> PathBuf {
+        std::env::var("WALDEN_SAVE").map(PathBuf::from).unwrap_or_else(|_| PathBuf::from("walden.save"))
+    }
+
+    /// Slots of the graph with no node in them, in the order new nodes will fill them.
+    fn vacancies(graph: &StableGraph<Entity, Edge>) -> Vec<NodeIndex> {
+        let bound = graph.node_bound();
+        let mut probe = graph.map(|_, _| (), |_, _| ());
+        let mut vacant = vec![];
+        loop {
+            let id = probe.add_node(());
+            if id.index() >= bound {
+                return vacant;
+            }
+            vacant.push(id);
+        }
+    }
+
+    impl World {
+        /// Nodes keep their ids, holes and all, and the holes are written in the order they get
+        /// filled, since where new entities land decides the order everything updates in. Edges
+        /// only keep what they link, they are always looked at in id order anyway.
+        fn encode_state(&self, w: &mut Writer) {
+            w.u64(self.seed);
+            w.u64(self.rng.draws);
+            w.u32(self.content.node_bound() as u32);
+            let vacant = vacancies(&self.content);
+            w.u32(vacant.len() as u32);
+            for id in vacant {
+                w.u32(id.index() as u32);
+            }
+            w.u32(self.content.node_count() as u32);
+            for id in self.content.node_indices() {
+                self.content[id].encode(w);
+            }
+            w.u32(self.players.len() as u32);
+            for id in self.players.iter() {
+                w.u32(id.index() as u32);
+            }
+            let mut edges: Vec<(u32, u32, Edge)> = self.content.edge_indices()
+                .filter_map(|e| self.content.edge_endpoints(e).map(|(from, to)| (from.index() as u32, to.index() as u32, self.content[e])))
+                .collect();
+            edges.sort_by_key(|&(from, to, edge)| (from, to, edge as u8));
+            w.u32(edges.len() as u32);
+            for (from, to, edge) in edges {
+                w.u32(from);
+                w.u32(to);
+                edge.encode(w);
+            }
+            self.chunks.encode(w);
+            w.f32(self.spawn_cooldown);
+            w.f32(self.speed);
+            w.f32(self.delta);
+            self.clock.encode(w);
+        }
+
+        fn decode_state(r: &mut Reader) -> Result<Self, DecodeError> {
+            let seed = r.u64()?;
+            let draws = r.u64()?;
+            let mut world = World { rng: WorldRng::resume(seed, draws), ..World::empty(seed) };
+
+            let bound = r.u32()? as usize;
+            let mut vacant = vec![];
+            for _ in 0..r.u32()? {
+                vacant.push(NodeIndex::new(r.u32()? as usize));
+            }
+            let entities = Vec::<Entity>::decode(r)?;
+            if vacant.len() + entities.len() != bound {
+                return Err(DecodeError::BadReference("node"));
+            }
+            // Every slot gets filled, then the holes get dug again last to first so the first
+            // one is the next to be filled
+            let mut entities = entities.into_iter();
+            for i in 0..bound {
+                let id = if vacant.contains(&NodeIndex::new(i)) {
+                    world.content.add_node(Entity::new(EntityKind::Grass, Point2::origin()))
+                }
+                else {
+                    match entities.next() {
+                        Some(entity) => world.add_entity(entity),
+                        None => return Err(DecodeError::BadReference("node")),
+                    }
+                };
+                debug_assert_eq!(id.index(), i);
+            }
+            for id in vacant.iter().rev() {
+                world.content.remove_node(*id).ok_or(DecodeError::BadReference("node"))?;
+            }
+
+            for _ in 0..r.u32()? {
+                let id = NodeIndex::new(r.u32()? as usize);
+                match world.content.node_weight(id).map(|e| &e.kind) {
+                    Some(EntityKind::Player(_)) => world.players.push(id),
+                    _ => return Err(DecodeError::BadReference("player")),
+                }
+            }
+            if world.players.is_empty() {
+                return Err(DecodeError::BadReference("player"));
+            }
+            for _ in 0..r.u32()? {
+                let (from, to) = (NodeIndex::new(r.u32()? as usize), NodeIndex::new(r.u32()? as usize));
+                let edge = Edge::decode(r)?;
+                if !world.content.contains_node(from) || !world.content.contains_node(to) {
+                    return Err(DecodeError::BadReference("edge end"));
+                }
+                world.content.add_edge(from, to, edge);
+            }
+            world.chunks = Chunks::decode(r)?;
+            world.spawn_cooldown = r.f32()?;
+            world.speed = r.f32()?;
+            world.delta = r.f32()?;
+            world.clock = Clock::decode(r)?;
+            Ok(world)
+        }
+    }
+
+    /// Wraps a payload with the header and checksum every save starts and ends with.
+    fn seal(payload: &[u8]) -> Vec<u8> {
+        let mut w = Writer::new();
+        for b in MAGIC {
+            w.u8(*b);
+        }
+        w.u32(VERSION);
+        w.bytes(payload);
+        w.u32(checksum(payload));
+        w.into_bytes()
+    }
+
+    impl Walden {
+        /// The whole game in the save format.
+        pub fn to_bytes(&self) -> Vec<u8> {
+            seal(&self.payload())
+        }
+
+        fn payload(&self) -> Vec<u8> {
+            let mut payload = Writer::new();
+            self.state.encode(&mut payload);
+            payload.u32(self.seats.len() as u32);
+            for seat in self.seats.iter() {
+                payload.bool(seat.button_a.pressed);
+                payload.bool(seat.button_b.pressed);
+                seat.selector.encode(&mut payload);
+            }
+            payload.vector(self.camera_pos);
+            payload.f32(self.camera_zoom);
+            let mut world = Writer::new();
+            self.world.encode_state(&mut world);
+            payload.bytes(&world.into_bytes());
+            payload.into_bytes()
+        }
+
+        pub fn from_bytes(bytes: &[u8]) -> Result<Self, LoadError> {
+            if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+                return Err(LoadError::NotASave);
+            }
+            let mut r = Reader::new(&bytes[MAGIC.len()..]);
+            let version = r.u32().map_err(|_| LoadError::NotASave)?;
+            if version != VERSION {
+                return Err(LoadError::UnsupportedVersion(version));
+            }
+            let payload = r.bytes().map_err(|_| LoadError::Corrupt)?;
+            if r.u32().map_err(|_| LoadError::Corrupt)? != checksum(payload) || !r.is_empty() {
+                return Err(LoadError::Corrupt);
+            }
+
+            let mut r = Reader::new(payload);
+            let state = GameState::decode(&mut r)?;
+            let mut seats = vec![];
+            for _ in 0..r.u32()? {
+                let button_a = r.bool()?;
+                let button_b = r.bool()?;
+                seats.push((button_a, button_b, Selector::decode(&mut r)?));
+            }
+            let camera_pos = r.vector()?;
+            let camera_zoom = r.f32()?;
+            let mut world_bytes = Reader::new(r.bytes()?);
+            let world = World::decode_state(&mut world_bytes)?;
+            if !r.is_empty() || !world_bytes.is_empty() {
+                return Err(LoadError::TrailingBytes);
+            }
+
+            if seats.len() != world.player_count() {
+                return Err(LoadError::SeatsMismatch { seats: seats.len(), players: world.player_count() });
+            }
+
+            let mut game = Walden::with_world(world);
+            game.state = state;
+            for (seat, (button_a, button_b, selector)) in game.seats.iter_mut().zip(seats) {
+                seat.button_a.pressed = button_a;
+                seat.button_b.pressed = button_b;
+                for indicator in seat.indicators.iter_mut() {
+                    indicator.set_player(&selector.player);
+                }
+                seat.selector = selector;
+            }
+            game.camera_pos = camera_pos;
+            game.camera_zoom = camera_zoom;
+            Ok(game)
+        }
+
+        pub fn save(&self, path: &Path) -> io::Result<()> {
+            // Written next to the old save first, so a crash halfway never leaves a broken one
+            let temporary = path.with_extension("tmp");
+            fs::write(&temporary, self.to_bytes())?;
+            fs::rename(&temporary, path)
+        }
+
+        pub fn load(path: &Path) -> Result<Self, LoadError> {
+            Self::from_bytes(&fs::read(path)?)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use headless::{Headless, Script};
+        use controls::Input;
+
+        fn before() -> Script {
+            Script::new()
+                .hold(Input::walk(Dpad::Right), 90)
+                .hold(Input::a(), 40)
+                .hold(Input::walk(Dpad::Down), 60)
+        }
+
+        fn after() -> Script {
+            Script::new()
+                .hold(Input::walk(Dpad::Left), 120)
+                .tap(Input::a())
+                .hold(Input::walk(Dpad::Up), 200)
+                .tap(Input::b())
+                .wait(600)
+        }
+
+        fn backend(players: usize, script: &Script) -> Headless {
+            (1..players).fold(Headless::new(1.0 / 60.0, script.clone()), |backend, _| backend.with_joystick(script.clone()))
+        }
+
+        fn played(players: usize) -> Walden {
+            let mut game = Walden::with_world(World::with_players(0, players));
+            backend(players, &before()).run(&mut game, before().len());
+            game
+        }
+
+        /// Saves a game halfway through, loads it and plays on both the original and the copy.
+        fn round_trip(players: usize) {
+            let mut original = played(players);
+            let bytes = original.to_bytes();
+            assert_eq!(original.to_bytes(), bytes, "Saving changed the game");
+            let mut loaded = Walden::from_bytes(&bytes).expect("Can't load a save that was just made");
+            assert_eq!(loaded.to_bytes(), bytes, "A loaded game doesn't save back to the same bytes");
+
+            let after = after();
+            backend(players, &after).run(&mut original, after.len());
+            backend(players, &after).run(&mut loaded, after.len());
+            assert!(loaded.to_bytes() == original.to_bytes(), "The loaded game went its own way after {} frames", after.len());
+        }
+
+        #[test]
+        fn loaded_game_plays_on_the_same() {
+            round_trip(1);
+        }
+
+        #[test]
+        fn loaded_game_plays_on_the_same_together() {
+            round_trip(2);
+        }
+
+        #[test]
+        fn rng_picks_up_where_it_was() {
+            use rand::{Rng, RngCore};
+
+            // Counting doesn't change the numbers, however they get asked for
+            let mut rng = WorldRng::new(7);
+            let mut plain = rng_from_seed(7);
+            let draw = |rng: &mut dyn RngCore| {
+                let mut bytes = [0; 13];
+                rng.fill_bytes(&mut bytes);
+                (rng.gen::<f32>(), rng.next_u64(), bytes, rng.gen_range(0, 10))
+            };
+            assert_eq!(draw(&mut rng), draw(&mut plain));
+
+            let mut copy = WorldRng::resume(7, rng.draws);
+            let expected: Vec<u32> = (0..16).map(|_| rng.next_u32()).collect();
+            let actual: Vec<u32> = (0..16).map(|_| copy.next_u32()).collect();
+            assert_eq!(actual, expected);
+        }
+
+        #[test]
+        fn refuses_damaged_saves() {
+            let bytes = played(1).to_bytes();
+
+            let mut flipped = bytes.clone();
+            let middle = flipped.len() / 2;
+            flipped[middle] ^= 0x40;
+            assert!(matches!(Walden::from_bytes(&flipped), Err(LoadError::Corrupt)));
+
+            assert!(matches!(Walden::from_bytes(&bytes[..bytes.len() - 10]), Err(LoadError::Corrupt)));
+            assert!(matches!(Walden::from_bytes(b"not a save at all"), Err(LoadError::NotASave)));
+
+            let mut newer = bytes.clone();
+            newer[MAGIC.len()] = (VERSION + 1) as u8;
+            assert!(matches!(Walden::from_bytes(&newer), Err(LoadError::UnsupportedVersion(v)) if v == VERSION + 1));
+        }
+
+        #[test]
+        fn refuses_trailing_bytes() {
+            let mut payload = played(1).payload();
+            payload.push(0);
+            assert!(matches!(Walden::from_bytes(&seal(&payload)), Err(LoadError::TrailingBytes)));
+        }
+
+        #[test]
+        fn refuses_seats_for_missing_players() {
+            let mut game = played(2);
+            game.seats.truncate(1);
+            assert!(matches!(Walden::from_bytes(&game.to_bytes()), Err(LoadError::SeatsMismatch { seats: 1, players: 2 })));
+        }
+    }
+}
+
+mod replay {
+    use super::*;
+    use codec::{Encode, Writer, Reader, DecodeError};
+    use controls::Input;
+    use mursten::Scene;
+    use controls::{Key, MouseButton};
+    use mursten::input::{JoystickState, JoystickId};
+    use std::fmt;
+    use std::fs;
+    use std::io;
+    use std::path::{Path, PathBuf};
+
+    const MAGIC: &[u8] = b"WREPLAY";
+    const VERSION: u32 = 2;
+    /// Frames between writes of the replay file while recording.
+    const FLUSH_FRAMES: usize = 600;
+    /// Longest replay that gets read back, a day of play at 60 frames a second.
+    const MAX_FRAMES: usize = 60 * 60 * 60 * 24;
+    /// Fewest bytes a run of frames takes: the count, how many joysticks and the delta.
+    const RUN_BYTES: usize = 4 + 4 + 4;
+
+    /// Everything the game got to see from the backend during one update. Keyboard and mouse
+    /// are folded into the first player's input, so a replay plays back as joysticks.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Frame {
+        /// One for each joystick, the seated players and anyone about to join. Empty when there
+        /// was nothing to play with.
+        pub inputs: Vec<Input>,
+        pub delta: f32,
+    }
+
+    impl Frame {
+        /// What the game gets from the backend this update, for each joystick there is. Without
+        /// any the first player still plays on the keyboard and mouse.
+        pub fn read<C>(context: &C) -> Self
+        where
+            C: ElapsedDelta + JoystickProvider + KeyboardProvider + MouseProvider,
+        {
+            let joysticks = context.available_joysticks().len().clamp(1, chunks::MAX_PLAYERS as usize);
+            Frame { inputs: (0..joysticks).map(|p| controls::read(context, p)).collect(), delta: context.delta() }
+        }
+    }
+
+    impl ElapsedDelta for Frame {
+        fn delta(&self) -> f32 {
+            self.delta
+        }
+    }
+
+    impl JoystickProvider for Frame {
+        fn available_joysticks(&self) -> Vec<JoystickId> {
+            (0..self.inputs.len()).collect()
+        }
+        fn joystick(&self, id: JoystickId) -> JoystickState {
+            self.inputs.get(id).cloned().unwrap_or_default().state()
+        }
+    }
+
+    impl KeyboardProvider for Frame {
+        fn is_key_pressed(&self, _key: Key) -> bool {
+            false
+        }
+    }
+
+    impl MouseProvider for Frame {
+        fn mouse_position(&self) -> Point2<f32> {
+            Point2::new(160.0, 120.0)
+        }
+        fn is_mouse_button_pressed(&self, _button: MouseButton) -> bool {
+            false
+        }
+    }
+
+    impl Encode for Frame {
+        fn encode(&self, w: &mut Writer) {
+            w.u32(self.inputs.len() as u32);
+            for input in self.inputs.iter() {
+                input.encode(w);
+            }
+            w.f32(self.delta);
+        }
+        fn decode(r: &mut Reader) -> Result<Self, DecodeError> {
+            let joysticks = r.u32()?;
+            if joysticks > chunks::MAX_PLAYERS {
+                return Err(DecodeError::BadReference("joystick"));
+            }
+            let inputs = (0..joysticks).map(|_| Input::decode(r)).collect::<Result<_, _>>()?;
+            Ok(Frame { inputs, delta: r.f32()? })
+        }
+    }
+
+    /// The inputs of a game started from a seed, and what the world looked like at the end.
+    #[derive(Clone, Debug)]
+    pub struct Replay {
+        pub seed: u64,
+        pub frames: Vec<Frame>,
+        /// The world as `world_bytes` wrote it once the frames were played.
+        pub ending: Vec<u8>,
+    }
+
+    /// Why a replay couldn't be read.
+    #[derive(Debug)]
+    pub enum ReplayError {
+        Io(io::Error),
+        NotAReplay,
+        UnsupportedVersion(u32),
+        /// More frames than anyone would have recorded, or more runs than there are bytes for.
+        TooLong,
+        TrailingBytes,
+        Invalid(DecodeError),
+    }
+
+    impl fmt::Display for ReplayError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                ReplayError::Io(error) => write!(f, "can't read the file: {}", error),
+                ReplayError::NotAReplay => write!(f, "not a Walden replay"),
+                ReplayError::UnsupportedVersion(version) => write!(f, "recorded with version {} of the format, this game reads version {}", version, VERSION),
+                ReplayError::TooLong => write!(f, "the replay claims more frames than it can hold"),
+                ReplayError::TrailingBytes => write!(f, "the replay goes on after its last frame"),
+                ReplayError::Invalid(error) => write!(f, "the replay is damaged: {:?}", error),
+            }
+        }
+    }
+
+    impl From<io::Error> for ReplayError {
+        fn from(error: io::Error) -> Self {
+            ReplayError::Io(error)
+        }
+    }
+
+    impl From<DecodeError> for ReplayError {
+        fn from(error: DecodeError) -> Self {
+            ReplayError::Invalid(error)
+        }
+    }
+
+    impl Replay {
+        /// Same frames in a row are stored once with a count, which is most of them with a fixed
+        /// frame rate.
+        pub fn to_bytes(&self) -> Vec<u8> {
+            let mut w = Writer::new();
+            for b in MAGIC {
+                w.u8(*b);
+            }
+            w.u32(VERSION);
+            w.u64(self.seed);
+            w.bytes(&self.ending);
+            let mut runs: Vec<(u32, &Frame)> = vec![];
+            for frame in self.frames.iter() {
+                match runs.last_mut() {
+                    Some(&mut (ref mut count, last)) if last == frame => *count += 1,
+                    _ => runs.push((1, frame)),
+                }
+            }
+            w.u32(runs.len() as u32);
+            for (count, frame) in runs {
+                w.u32(count);
+                frame.encode(&mut w);
+            }
+            w.into_bytes()
+        }
+
+        pub fn from_bytes(bytes: &[u8]) -> Result<Self, ReplayError> {
+            if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+                return Err(ReplayError::NotAReplay);
+            }
+            let mut r = Reader::new(&bytes[MAGIC.len()..]);
+            let version = r.u32()?;
+            if version != VERSION {
+                return Err(ReplayError::UnsupportedVersion(version));
+            }
+            let seed = r.u64()?;
+            let ending = r.bytes()?.to_vec();
+            let runs = r.u32()? as usize;
+            if runs > r.remaining() / RUN_BYTES {
+                return Err(ReplayError::TooLong);
+            }
+            let mut frames = vec![];
+            for _ in 0..runs {
+                let count = r.u32()? as usize;
+                let frame = Frame::decode(&mut r)?;
+                if count > MAX_FRAMES - frames.len() {
+                    return Err(ReplayError::TooLong);
+                }
+                frames.extend((0..count).map(|_| frame.clone()));
+            }
+            if !r.is_empty() {
+                return Err(ReplayError::TrailingBytes);
+            }
+            Ok(Replay { seed, frames, ending })
+        }
+
+        pub fn save(&self, path: &Path) -> io::Result<()> {
+            fs::write(path, self.to_bytes())
+        }
+
+        pub fn load(path: &Path) -> Result<Self, ReplayError> {
+            Self::from_bytes(&fs::read(path)?)
+        }
+    }
+
+    /// Everything in the world that the simulation decides, written down without touching it.
+    /// Two games that played out the same give the same bytes.
+    pub fn world_bytes(world: &World) -> Vec<u8> {
+        let mut w = Writer::new();
+        for id in world.content.node_indices() {
+            w.u32(id.index() as u32);
+            world[id].encode(&mut w);
+        }
+        for e in world.content.edge_indices() {
+            if let Some((from, to)) = world.content.edge_endpoints(e) {
+                w.u32(from.index() as u32);
+                w.u32(to.index() as u32);
+                world.content[e].encode(&mut w);
+            }
+        }
+        w.u32(world.players.len() as u32);
+        for id in world.players.iter() {
+            w.u32(id.index() as u32);
+        }
+        world.chunks.encode(&mut w);
+        world.clock.encode(&mut w);
+        w.f32(world.spawn_cooldown);
+        w.into_bytes()
+    }
+
+    /// Wraps a game and writes down every frame it gets. The game sees each frame exactly as it
+    /// was recorded, even if the joysticks change halfway through an update.
+    pub struct Recorder {
+        game: Walden,
+        replay: Replay,
+        path: PathBuf,
+        /// Frames in the file as of the last write.
+        flushed: usize,
+        done: bool,
+    }
+
+    impl Recorder {
+        pub fn new(seed: u64, path: PathBuf) -> Self {
+            Self {
+                game: Walden::with_world(World::from_seed(seed)),
+                replay: Replay { seed, frames: vec![], ending: vec![] },
+                path,
+                flushed: 0,
+                done: false,
+            }
+        }
+
+        fn flush(&mut self) {
+            self.replay.ending = world_bytes(&self.game.world);
+            self.flushed = self.replay.frames.len();
+            if let Err(error) = self.replay.save(&self.path) {
+                eprintln!("Can't write replay {}: {}", self.path.display(), error);
+            }
+        }
+    }
+
+    impl<C> Update<C> for Recorder
+    where
+        C: ElapsedDelta + JoystickProvider + KeyboardProvider + MouseProvider,
+    {
+        fn update(&mut self, context: &mut C) {
+            let mut frame = Frame::read(context);
+            self.game.update(&mut frame);
+            // Starting over picks a new seed, so the recording ends with the game
+            if self.done {
+                return;
+            }
+            self.replay.frames.push(frame);
+            if self.game.is_game_over() {
+                self.flush();
+                self.done = true;
+                eprintln!("Recorded {} frames to {}", self.replay.frames.len(), self.path.display());
+            }
+            else if self.replay.frames.len() >= self.flushed + FLUSH_FRAMES {
+                self.flush();
+            }
+        }
+    }
+
+    impl<S> Draw<S> for Recorder
+    where
+        S: DrawPrimitives,
+    {
+        fn draw(&self, surface: &mut S) {
+            self.game.draw(surface);
+        }
+    }
+
+    impl Scene for Recorder {}
+
+    /// Plays a replay on a new game from the same seed, one recorded frame each update whatever
+    /// the backend says, and tells whether it ended where the recording did.
+    pub struct Playback {
+        game: Walden,
+        replay: Replay,
+        played: usize,
+    }
+
+    impl Playback {
+        pub fn new(replay: Replay) -> Self {
+            Self { game: Walden::with_world(World::from_seed(replay.seed)), replay, played: 0 }
+        }
+
+        /// Plays the next frame, telling whether there was one.
+        pub fn step(&mut self) -> bool {
+            let mut frame = match self.replay.frames.get(self.played) {
+                Some(frame) => frame.clone(),
+                None => return false,
+            };
+            self.game.update(&mut frame);
+            self.played += 1;
+            true
+        }
+
+        /// Whether the game is where the recording left it.
+        pub fn ended_as_recorded(&self) -> bool {
+            world_bytes(&self.game.world) == self.replay.ending
+        }
+    }
+
+    impl<C> Update<C> for Playback {
+        fn update(&mut self, _context: &mut C) {
+            if self.step() && self.played == self.replay.frames.len() {
+                if self.ended_as_recorded() {
+                    eprintln!("The replay ended where it was recorded");
+                }
+                else {
+                    eprintln!("The replay went another way than it was recorded");
+                }
+            }
+        }
+    }
+
+    impl<S> Draw<S> for Playback
+    where
+        S: DrawPrimitives,
+    {
+        fn draw(&self, surface: &mut S) {
+            self.game.draw(surface);
+        }
+    }
+
+    impl Scene for Playback {}
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// Plays every replay in replays/ and checks it ends up where the recording did. With
+        /// WALDEN_BLESS=1 the replays take the new ending instead.
+        #[test]
+        fn replays_end_where_they_were_recorded() {
+            let bless = std::env::var("WALDEN_BLESS").map(|bless| bless == "1").unwrap_or(false);
+            let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("replays");
+            let mut paths: Vec<_> = fs::read_dir(&dir).expect("replays/ is missing")
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().map(|e| e == "replay").unwrap_or(false))
+                .collect();
+            paths.sort();
+            assert!(!paths.is_empty(), "No replays in {}", dir.display());
+            let mut failed = vec![];
+            for path in paths {
+                let replay = Replay::load(&path).unwrap_or_else(|error| panic!("Can't load {}: {}", path.display(), error));
+                let mut playback = Playback::new(replay);
+                while playback.step() {}
+                let ending = world_bytes(&playback.game.world);
+                if bless {
+                    playback.replay.ending = ending;
+                    playback.replay.save(&path).unwrap_or_else(|error| panic!("Can't write {}: {}", path.display(), error));
+                }
+                else if ending != playback.replay.ending {
+                    let recorded = &playback.replay.ending;
+                    let at = ending.iter().zip(recorded.iter()).position(|(a, b)| a != b).unwrap_or_else(|| ending.len().min(recorded.len()));
+                    failed.push(format!("{} ended {} bytes long and differs from the recorded {} at byte {}", path.display(), ending.len(), recorded.len(), at));
+                }
+            }
+            assert!(failed.is_empty(), "Replays went another way:\n{}", failed.join("\n"));
+        }
+
+        #[test]
+        fn refuses_more_frames_than_bytes() {
+            let mut replay = Replay { seed: 0, frames: vec![Frame { inputs: vec![], delta: STEP }], ending: vec![] }.to_bytes();
+            // A single run that claims to go on for four billion frames
+            let count = MAGIC.len() + 4 + 8 + 4 + 4;
+            for b in replay[count..count + 4].iter_mut() {
+                *b = 0xff;
+            }
+            assert!(matches!(Replay::from_bytes(&replay), Err(ReplayError::TooLong)));
+            // and a header that claims runs the file doesn't have
+            let runs = count - 4;
+            replay[runs] = 2;
+            assert!(matches!(Replay::from_bytes(&replay), Err(ReplayError::TooLong)));
+        }
+
+        #[test]
+        fn reads_back_what_it_wrote() {
+            let frames = vec![
+                Frame { inputs: vec![], delta: STEP },
+                Frame { inputs: vec![Input::walk(Dpad::Left)], delta: STEP },
+                Frame { inputs: vec![Input::walk(Dpad::Left)], delta: STEP },
+                Frame { inputs: vec![Input::a(), Input::b()], delta: 0.02 },
+            ];
+            let replay = Replay { seed: 3, frames: frames.clone(), ending: vec![1, 2, 3] };
+            let read = Replay::from_bytes(&replay.to_bytes()).expect("Can't read a replay back");
+            assert_eq!((read.seed, read.frames, read.ending), (3, frames, vec![1, 2, 3]));
+            let mut longer = replay.to_bytes();
+            longer.push(0);
+            assert!(matches!(Replay::from_bytes(&longer), Err(ReplayError::TrailingBytes)));
+            assert!(matches!(Replay::from_bytes(b"WALDEN"), Err(ReplayError::NotAReplay)));
+        }
+
+        /// Three joysticks: the first player's, and two more whose players join a second apart,
+        /// let go of A and walk off.
+        struct Seats {
+            frame: usize,
+        }
+
+        impl ElapsedDelta for Seats {
+            fn delta(&self) -> f32 {
+                STEP
+            }
+        }
+
+        impl JoystickProvider for Seats {
+            fn available_joysticks(&self) -> Vec<JoystickId> {
+                vec![0, 1, 2]
+            }
+            fn joystick(&self, id: JoystickId) -> JoystickState {
+                let (joins, walks) = match id {
+                    0 => return Input::walk(Dpad::Left).state(),
+                    1 => (30, Dpad::Right),
+                    _ => (90, Dpad::Up),
+                };
+                let input = if self.frame == joins {
+                    Input::a()
+                }
+                else if self.frame > joins + 1 {
+                    Input::walk(walks)
+                }
+                else {
+                    Input::idle()
+                };
+                input.state()
+            }
+        }
+
+        impl KeyboardProvider for Seats {
+            fn is_key_pressed(&self, _key: Key) -> bool {
+                false
+            }
+        }
+
+        impl MouseProvider for Seats {
+            fn mouse_position(&self) -> Point2<f32> {
+                Point2::new(160.0, 120.0)
+            }
+            fn is_mouse_button_pressed(&self, _button: MouseButton) -> bool {
+                false
+            }
+        }
+
+        #[test]
+        fn players_that_join_while_recording_play_back() {
+            let path = std::env::temp_dir().join(format!("walden-{}.replay", std::process::id()));
+            let mut recorder = Recorder::new(5, path.clone());
+            let mut seats = Seats { frame: 0 };
+            for frame in 0..150 {
+                seats.frame = frame;
+                recorder.update(&mut seats);
+            }
+            assert_eq!(recorder.game.world.player_count(), 3);
+            assert!(recorder.replay.frames.iter().all(|frame| frame.inputs.len() == 3));
+            recorder.flush();
+
+            let mut playback = Playback::new(Replay::load(&path).expect("Can't load the recording"));
+            let _ = fs::remove_file(&path);
+            while playback.step() {}
+            assert!(playback.ended_as_recorded());
+            let world = &playback.game.world;
+            assert_eq!(world.player_count(), 3);
+            assert!(world.player_position(0).unwrap().x < START.0 - 10.0);
+            assert!(world.player_position(1).unwrap().x > START.0 + 30.0, "The second player didn't walk right after joining");
+            assert!(world.player_position(2).unwrap().y < START.1 - 10.0, "The third player didn't walk up after joining");
+        }
+    }
+}
+
+mod net {
+    use super::*;
+    use codec::{Encode, Writer, Reader, DecodeError};
+    use controls::Input;
+    use mursten::Scene;
+    use controls::{Key, MouseButton};
+    use mursten::input::{JoystickState, JoystickId};
+    use petgraph::Direction;
+    use petgraph::visit::EdgeRef;
+    use std::collections::{BTreeMap, BTreeSet, VecDeque};
+    use std::io;
+    use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Steps of the game the server plays each tick. Clients sample their input at the same rate.
+    const STEPS_PER_TICK: u32 = 2;
+    pub const TICK: f32 = STEP * STEPS_PER_TICK as f32;
+    const MAGIC: &[u8] = b"WNET";
+    /// Goes up every time a message changes. Packets from other versions are ignored.
+    const VERSION: u32 = 5;
+    /// Largest datagram sent. Changes that don't fit in a snapshot go in the next one.
+    const MAX_PACKET: usize = 60000;
+    /// Room kept in a snapshot for everything besides the records and the keys it lists.
+    const SNAPSHOT_HEADER: usize = 4096;
+    /// Inputs a client sends again in every packet until the server has played them, so a
+    /// lost packet doesn't lose a button press.
+    const REDUNDANT_INPUTS: usize = 16;
+    /// Inputs the server holds for a client that runs ahead before it drops the oldest.
+    const MAX_QUEUED_INPUTS: usize = 8;
+    /// Snapshots remembered to take acknowledgements for, and inputs a client keeps to guess
+    /// with, about two seconds.
+    const HISTORY: usize = 64;
+    /// Most clients a server plays with at once. Each one needs a player of its own, and a
+    /// world can't have more than `chunks::MAX_PLAYERS`.
+    pub const MAX_CLIENTS: usize = 16;
+    /// Ticks without a word from a client before the server gives up on it, five seconds.
+    const TIMEOUT_TICKS: u32 = 150;
+
+    /// The world as a client sees it: each entity with the edges going out of it, encoded, by
+    /// node index on the server.
+    type Records = BTreeMap<u32, Vec<u8>>;
+
+    /// An entity and the edges going out of it, in the order of the keys `key` gives the nodes
+    /// they point to. Edges to nodes without a key are left out.
+    fn record<F>(world: &World, id: NodeIndex, key: F) -> Vec<u8>
+    where
+        F: Fn(NodeIndex) -> Option<u32>,
+    {
+        let mut w = Writer::new();
+        world[id].encode(&mut w);
+        let mut edges: Vec<(u32, Edge)> = world.content.edges_directed(id, Direction::Outgoing)
+            .filter_map(|e| key(e.target()).map(|to| (to, *e.weight())))
+            .collect();
+        edges.sort_by_key(|&(to, edge)| (to, edge as u8));
+        w.u32(edges.len() as u32);
+        for (to, edge) in edges {
+            w.u32(to);
+            edge.encode(&mut w);
+        }
+        w.into_bytes()
+    }
+
+    fn records(world: &World) -> Records {
+        world.content.node_indices()
+            .map(|id| (id.index() as u32, record(world, id, |to| Some(to.index() as u32))))
+            .collect()
+    }
+
+    /// FNV-1a in 64 bits, to tell whether a record changed without keeping it around.
+    fn digest(bytes: &[u8]) -> u64 {
+        bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| (hash ^ *b as u64).wrapping_mul(0x0100_0000_01b3))
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum ToServer {
+        /// Asks for a player. Sent every tick until the first snapshot arrives.
+        Join,
+        /// The client's inputs numbered from `first`, and the newest snapshot it got.
+        Inputs { first: u32, inputs: Vec<Input>, ack: u32 },
+        /// The client is going away, its player is free for someone else.
+        Leave,
+    }
+
+    /// Everything one client needs to catch up with the server. The changes go on top of
+    /// whatever snapshots the client got before, in any order and with any of them lost, so
+    /// anything sent since the last snapshot the client acknowledged gets sent again.
+    #[derive(Clone, Debug)]
+    struct Snapshot {
+        tick: u32,
+        player: PlayerIndex,
+        /// Number of the last input of this client that went into the world.
+        ack: u32,
+        state: GameState,
+        clock: Clock,
+        /// Node index of each player.
+        players: Vec<u32>,
+        button_a: bool,
+        button_b: bool,
+        selector: Selector,
+        removed: Vec<u32>,
+        changed: Vec<(u32, Vec<u8>)>,
+    }
+
+    fn header(w: &mut Writer) {
+        for b in MAGIC {
+            w.u8(*b);
+        }
+        w.u32(VERSION);
+    }
+
+    fn read_header<'a>(bytes: &'a [u8]) -> Option<Reader<'a>> {
+        if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+            return None;
+        }
+        let mut r = Reader::new(&bytes[MAGIC.len()..]);
+        if r.u32().ok()? != VERSION {
+            return None;
+        }
+        Some(r)
+    }
+
+    impl Encode for ToServer {
+        fn encode(&self, w: &mut Writer) {
+            match *self {
+                ToServer::Join => w.u8(0),
+                ToServer::Inputs { first, ref inputs, ack } => {
+                    w.u8(1);
+                    w.u32(first);
+                    inputs.encode(w);
+                    w.u32(ack);
+                },
+                ToServer::Leave => w.u8(2),
+            }
+        }
+        fn decode(r: &mut Reader) -> Result<Self, DecodeError> {
+            match r.u8()? {
+                0 => Ok(ToServer::Join),
+                1 => Ok(ToServer::Inputs { first: r.u32()?, inputs: Vec::decode(r)?, ack: r.u32()? }),
+                2 => Ok(ToServer::Leave),
+                t => Err(DecodeError::InvalidTag("message", t)),
+            }
+        }
+    }
+
+    impl Encode for Snapshot {
+        fn encode(&self, w: &mut Writer) {
+            w.u32(self.tick);
+            w.u32(self.player as u32);
+            w.u32(self.ack);
+            self.state.encode(w);
+            self.clock.encode(w);
+            w.u32(self.players.len() as u32);
+            for key in self.players.iter() {
+                w.u32(*key);
+            }
+            w.bool(self.button_a);
+            w.bool(self.button_b);
+            self.selector.encode(w);
+            w.u32(self.removed.len() as u32);
+            for key in self.removed.iter() {
+                w.u32(*key);
+            }
+            w.u32(self.changed.len() as u32);
+            for &(key, ref bytes) in self.changed.iter() {
+                w.u32(key);
+                w.bytes(bytes);
+            }
+        }
+        fn decode(r: &mut Reader) -> Result<Self, DecodeError> {
+            let tick = r.u32()?;
+            let player = r.u32()? as PlayerIndex;
+            let ack = r.u32()?;
+            let state = GameState::decode(r)?;
+            let clock = Clock::decode(r)?;
+            let players = (0..r.u32()?).map(|_| r.u32()).collect::<Result<_, _>>()?;
+            let button_a = r.bool()?;
+            let button_b = r.bool()?;
+            let selector = Selector::decode(r)?;
+            let removed = (0..r.u32()?).map(|_| r.u32()).collect::<Result<_, _>>()?;
+            let mut changed = vec![];
+            for _ in 0..r.u32()? {
+                changed.push((r.u32()?, r.bytes()?.to_vec()));
+            }
+            Ok(Snapshot { tick, player, ack, state, clock, players, button_a, button_b, selector, removed, changed })
+        }
+    }
+
+    fn to_packet<T: Encode>(message: &T) -> Vec<u8> {
+        let mut w = Writer::new();
+        header(&mut w);
+        message.encode(&mut w);
+        w.into_bytes()
+    }
+
+    /// Packets that aren't ours, come from another version or got mangled are None.
+    fn from_packet<T: Encode>(bytes: &[u8]) -> Option<T> {
+        let mut r = read_header(bytes)?;
+        let message = T::decode(&mut r).ok()?;
+        if r.is_empty() { Some(message) } else { None }
+    }
+
+    /// What the server plays in one tick: one input per player, as if each had a joystick.
+    struct Tick {
+        inputs: Vec<Input>,
+    }
+
+    impl ElapsedDelta for Tick {
+        fn delta(&self) -> f32 {
+            TICK
+        }
+    }
+
+    impl JoystickProvider for Tick {
+        fn available_joysticks(&self) -> Vec<JoystickId> {
+            (0..self.inputs.len()).collect()
+        }
+        fn joystick(&self, id: JoystickId) -> JoystickState {
+            self.inputs.get(id).cloned().unwrap_or_default().state()
+        }
+    }
+
+    impl KeyboardProvider for Tick {
+        fn is_key_pressed(&self, _key: Key) -> bool {
+            false
+        }
+    }
+
+    impl MouseProvider for Tick {
+        fn mouse_position(&self) -> Point2<f32> {
+            Point2::new(160.0, 120.0)
+        }
+        fn is_mouse_button_pressed(&self, _button: MouseButton) -> bool {
+            false
+        }
+    }
+
+    /// What went out in one snapshot, by digest.
+    struct Sent {
+        tick: u32,
+        changed: Vec<(u32, u64)>,
+        removed: Vec<u32>,
+    }
+
+    /// A client as the server knows it.
+    struct Remote {
+        address: SocketAddr,
+        player: PlayerIndex,
+        /// Tick the client was last heard from.
+        heard: u32,
+        /// Inputs that arrived and haven't been played yet, with their numbers.
+        queued: VecDeque<(u32, Input)>,
+        /// Number the next new input will have.
+        next: u32,
+        /// The last input played and its number. It's played again while nothing new arrives.
+        played: (u32, Input),
+        /// The newest snapshot the client acknowledged, and the digest of each record it has
+        /// since then.
+        acked: (u32, BTreeMap<u32, u64>),
+        /// Snapshots sent after that one.
+        sent: VecDeque<Sent>,
+    }
+
+    impl Remote {
+        fn new(address: SocketAddr, player: PlayerIndex, tick: u32) -> Self {
+            Self {
+                address,
+                player,
+                heard: tick,
+                queued: VecDeque::new(),
+                next: 1,
+                played: (0, Input::idle()),
+                acked: (0, BTreeMap::new()),
+                sent: VecDeque::new(),
+            }
+        }
+
+        fn receive(&mut self, first: u32, inputs: Vec<Input>, ack: u32) {
+            for (i, input) in inputs.into_iter().enumerate() {
+                let number = first + i as u32;
+                // Anything before was played or is lost for good
+                if number >= self.next {
+                    self.queued.push_back((number, input));
+                    self.next = number + 1;
+                }
+            }
+            while self.queued.len() > MAX_QUEUED_INPUTS {
+                self.played = self.queued.pop_front().unwrap();
+            }
+            // Every snapshot carries whatever the ones before it since the last acknowledged
+            // did, so the one acknowledged is all it takes to catch up
+            if let Some(i) = self.sent.iter().position(|sent| sent.tick == ack) {
+                let sent = self.sent.drain(..i + 1).next_back().unwrap();
+                for key in sent.removed {
+                    self.acked.1.remove(&key);
+                }
+                self.acked.1.extend(sent.changed);
+                self.acked.0 = sent.tick;
+            }
+        }
+
+        fn next_input(&mut self) -> Input {
+            if let Some(input) = self.queued.pop_front() {
+                self.played = input;
+            }
+            self.played.1
+        }
+
+        /// Whatever the client may not have yet, as much of it as fits in a packet. Players go
+        /// first so a client always has them all as the server does, then whatever went out
+        /// in snapshots that may not have arrived.
+        fn snapshot(&mut self, tick: u32, game: &Walden, records: &Records) -> Snapshot {
+            let unconfirmed: BTreeSet<u32> = self.sent.iter()
+                .flat_map(|sent| sent.changed.iter().map(|&(key, _)| key).chain(sent.removed.iter().cloned()))
+                .collect();
+            let removed: Vec<u32> = self.acked.1.keys().chain(unconfirmed.iter())
+                .filter(|key| !records.contains_key(key))
+                .cloned()
+                .collect::<BTreeSet<u32>>()
+                .into_iter()
+                .collect();
+            let players: Vec<u32> = game.world.players.iter().map(|id| id.index() as u32).collect();
+
+            let mut candidates: Vec<(u8, u32, &Vec<u8>, u64)> = records.iter()
+                .filter_map(|(key, bytes)| {
+                    let digest = digest(bytes);
+                    if players.contains(key) {
+                        Some((0, *key, bytes, digest))
+                    }
+                    else if unconfirmed.contains(key) {
+                        Some((1, *key, bytes, digest))
+                    }
+                    else if self.acked.1.get(key) != Some(&digest) {
+                        Some((2, *key, bytes, digest))
+                    }
+                    else {
+                        None
+                    }
+                })
+                .collect();
+            candidates.sort_by_key(|&(priority, key, _, _)| (priority, key));
+            let mut changed = vec![];
+            let mut digests = vec![];
+            let mut size = 0;
+            let budget = (MAX_PACKET - SNAPSHOT_HEADER).saturating_sub(4 * (players.len() + removed.len()));
+            for (priority, key, bytes, digest) in candidates {
+                if size + bytes.len() + 8 > budget {
+                    // What may be out there already still counts as sent, with a digest nothing
+                    // has, so it goes again until the client has it or gets removed if it's gone
+                    if priority < 2 {
+                        digests.push((key, 0));
+                    }
+                    continue;
+                }
+                size += bytes.len() + 8;
+                changed.push((key, bytes.clone()));
+                digests.push((key, digest));
+            }
+            self.sent.push_back(Sent { tick, changed: digests, removed: removed.clone() });
+            if self.sent.len() > HISTORY {
+                // Nobody knows what the client made of it, whatever it touched goes again
+                let forgotten = self.sent.pop_front().unwrap();
+                for key in forgotten.changed.into_iter().map(|(key, _)| key).chain(forgotten.removed) {
+                    self.acked.1.insert(key, 0);
+                }
+            }
+
+            let seat = &game.seats[self.player];
+            Snapshot {
+                tick,
+                player: self.player,
+                ack: self.played.0,
+                state: game.state.clone(),
+                clock: game.world.clock.clone(),
+                players,
+                button_a: seat.button_a.pressed,
+                button_b: seat.button_b.pressed,
+                selector: seat.selector.clone(),
+                removed,
+                changed,
+            }
+        }
+    }
+
+    /// Plays the game for everyone. The server's world is the real one, clients only get to
+    /// see it and send what they are pressing.
+    pub struct Server {
+        socket: UdpSocket,
+        game: Walden,
+        remotes: Vec<Remote>,
+        tick: u32,
+    }
+
+    impl Server {
+        pub fn bind<A: ToSocketAddrs>(address: A, seed: u64) -> io::Result<Self> {
+            let socket = UdpSocket::bind(address)?;
+            socket.set_nonblocking(true)?;
+            Ok(Server {
+                socket,
+                game: Walden::with_world(World::from_seed(seed)),
+                remotes: vec![],
+                tick: 0,
+            })
+        }
+
+        pub fn local_addr(&self) -> io::Result<SocketAddr> {
+            self.socket.local_addr()
+        }
+
+        fn receive(&mut self) {
+            let mut buffer = [0; 65536];
+            loop {
+                let (len, from) = match self.socket.recv_from(&mut buffer) {
+                    Ok(received) => received,
+                    Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                    // A client that went away can bounce back as an error, there are others
+                    Err(_) => continue,
+                };
+                match from_packet(&buffer[..len]) {
+                    Some(ToServer::Join) => {
+                        self.join(from);
+                    },
+                    Some(ToServer::Inputs { first, inputs, ack }) => {
+                        let tick = self.tick;
+                        if let Some(remote) = self.remotes.iter_mut().find(|remote| remote.address == from) {
+                            remote.heard = tick;
+                            remote.receive(first, inputs, ack);
+                        }
+                    },
+                    Some(ToServer::Leave) => {
+                        if let Some(i) = self.remotes.iter().position(|remote| remote.address == from) {
+                            eprintln!("Player {} left", self.remotes[i].player + 1);
+                            self.remotes.remove(i);
+                        }
+                    },
+                    None => {},
+                }
+            }
+        }
+
+        /// Gives whoever asks from `address` a player: one whose client went away if there is
+        /// one, or a new one. Returns whether they got one, which they don't once the server
+        /// is full.
+        fn join(&mut self, address: SocketAddr) -> bool {
+            if self.remotes.iter().any(|remote| remote.address == address) {
+                return true;
+            }
+            if self.remotes.len() >= MAX_CLIENTS {
+                return false;
+            }
+            let free = (0..self.game.world.player_count())
+                .find(|p| self.remotes.iter().all(|remote| remote.player != *p));
+            let player = match free {
+                Some(p) => p,
+                None => self.game.add_player(),
+            };
+            eprintln!("Player {} joined from {}", player + 1, address);
+            self.remotes.push(Remote::new(address, player, self.tick));
+            true
+        }
+
+        /// Plays one tick with the inputs that arrived and tells every client how it went.
+        pub fn step(&mut self) {
+            self.receive();
+
+            // Clients that went quiet leave their player behind for whoever joins next
+            let tick = self.tick;
+            self.remotes.retain(|remote| {
+                let quiet = tick - remote.heard > TIMEOUT_TICKS;
+                if quiet {
+                    eprintln!("Player {} timed out", remote.player + 1);
+                }
+                !quiet
+            });
+
+            let mut inputs = vec![Input::idle(); self.game.seats.len()];
+            for remote in self.remotes.iter_mut() {
+                let input = remote.next_input();
+                if let Some(slot) = inputs.get_mut(remote.player) {
+                    *slot = input;
+                }
+            }
+            self.game.update(&mut Tick { inputs });
+            self.tick += 1;
+            // Starting over makes a world for one, everyone who joined needs a player again
+            while self.remotes.iter().any(|remote| remote.player >= self.game.world.player_count()) {
+                self.game.add_player();
+            }
+
+            let records = records(&self.game.world);
+            for remote in self.remotes.iter_mut() {
+                let packet = to_packet(&remote.snapshot(self.tick, &self.game, &records));
+                if let Err(error) = self.socket.send_to(&packet, remote.address) {
+                    eprintln!("Can't send to {}: {}", remote.address, error);
+                }
+            }
+        }
+    }
+
+    /// Plays the game on someone else's server. Their own player moves as soon as they press
+    /// anything, the rest of the world shows up when the server says so.
+    pub struct Client {
+        socket: UdpSocket,
+        address: SocketAddr,
+        /// Nothing to show until the first snapshot arrives.
+        game: Option<Walden>,
+        /// The newest snapshot applied.
+        tick: u32,
+        /// The node in the client's world of each node on the server.
+        nodes: BTreeMap<u32, NodeIndex>,
+        /// Edges to nodes that haven't arrived yet, by the key of the node they point to.
+        dangling: Vec<(NodeIndex, u32, Edge)>,
+        /// Inputs sent that the server hasn't played yet, with their numbers.
+        pending: VecDeque<(u32, Input)>,
+        sequence: u32,
+        /// Time left over that isn't a whole tick yet.
+        accumulator: f32,
+        /// How many times a snapshot moved the player somewhere else than where it was guessed.
+        corrections: u32,
+    }
+
+    impl Client {
+        pub fn connect<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+            let address = address.to_socket_addrs()?.next()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to"))?;
+            let socket = UdpSocket::bind(if address.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+            socket.connect(address)?;
+            socket.set_nonblocking(true)?;
+            let client = Client {
+                socket,
+                address,
+                game: None,
+                tick: 0,
+                nodes: BTreeMap::new(),
+                dangling: vec![],
+                pending: VecDeque::new(),
+                sequence: 0,
+                accumulator: 0.0,
+                corrections: 0,
+            };
+            client.send(&ToServer::Join);
+            Ok(client)
+        }
+
+        fn send(&self, message: &ToServer) {
+            if let Err(error) = self.socket.send(&to_packet(message)) {
+                eprintln!("Can't send to {}: {}", self.address, error);
+            }
+        }
+
+        fn player(&self) -> Option<PlayerIndex> {
+            self.game.as_ref().and_then(|game| game.focus)
+        }
+
+        /// One tick of input: sends it along with the ones the server may not have yet, and
+        /// guesses where it takes the player.
+        pub fn tick(&mut self, input: Input) {
+            self.receive();
+            if self.game.is_none() {
+                self.send(&ToServer::Join);
+                return;
+            }
+            self.sequence += 1;
+            self.pending.push_back((self.sequence, input));
+            if self.pending.len() > HISTORY {
+                self.pending.pop_front();
+            }
+            let unsent = self.pending.len().saturating_sub(REDUNDANT_INPUTS);
+            let first = self.pending.get(unsent).map(|&(number, _)| number).unwrap_or(self.sequence);
+            self.send(&ToServer::Inputs {
+                first,
+                inputs: self.pending.iter().skip(unsent).map(|&(_, input)| input).collect(),
+                ack: self.tick,
+            });
+            if let (Some(p), Some(game)) = (self.player(), self.game.as_mut()) {
+                predict(game, p, input);
+                game.update_camera();
+            }
+        }
+
+        pub fn receive(&mut self) {
+            let mut buffer = [0; 65536];
+            loop {
+                let len = match self.socket.recv(&mut buffer) {
+                    Ok(len) => len,
+                    Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                    // Nobody listening on the other side yet, Join gets sent again
+                    Err(_) => continue,
+                };
+                if let Some(snapshot) = from_packet(&buffer[..len]) {
+                    if let Err(error) = self.apply(snapshot) {
+                        eprintln!("Got a snapshot that doesn't add up: {:?}", error);
+                    }
+                }
+            }
+        }
+
+        /// Puts the changes in a snapshot into the world, then guesses again what the inputs
+        /// the server hasn't played yet do on top of it.
+        fn apply(&mut self, snapshot: Snapshot) -> Result<(), DecodeError> {
+            // Late ones have nothing new
+            if snapshot.tick <= self.tick {
+                return Ok(());
+            }
+            // Everything gets read before anything changes, so a bad snapshot changes nothing
+            let mut changed = vec![];
+            for &(key, ref bytes) in snapshot.changed.iter() {
+                let mut r = Reader::new(bytes);
+                let entity = Entity::decode(&mut r)?;
+                let edges = (0..r.u32()?).map(|_| Ok((r.u32()?, Edge::decode(&mut r)?))).collect::<Result<Vec<_>, _>>()?;
+                changed.push((key, entity, edges));
+            }
+            let sent: BTreeSet<u32> = changed.iter().map(|&(key, _, _)| key).collect();
+            if snapshot.players.iter().any(|key| !sent.contains(key)) {
+                return Err(DecodeError::BadReference("player"));
+            }
+
+            let p = snapshot.player;
+            let guessed = self.game.as_ref().and_then(|game| game.world.player_position(p));
+            let mut game = self.game.take().unwrap_or_else(|| Walden::with_world(World::empty(0)));
+            for key in snapshot.removed.iter() {
+                if let Some(id) = self.nodes.remove(key) {
+                    game.world.detach_entity(id);
+                    self.dangling.retain(|&(from, to, _)| from != id && to != *key);
+                }
+            }
+            let mut edges = vec![];
+            for (key, entity, out) in changed {
+                let id = match self.nodes.get(&key).cloned() {
+                    Some(id) => {
+                        game.world.replace_entity(id, entity);
+                        let old: Vec<_> = game.world.content.edges_directed(id, Direction::Outgoing).map(|e| e.id()).collect();
+                        for e in old {
+                            game.world.content.remove_edge(e);
+                        }
+                        id
+                    },
+                    None => {
+                        let id = game.world.add_entity(entity);
+                        self.nodes.insert(key, id);
+                        id
+                    },
+                };
+                self.dangling.retain(|&(from, _, _)| from != id);
+                edges.extend(out.into_iter().map(|(to, edge)| (id, to, edge)));
+            }
+            // Whatever the edge points to may not have been sent yet
+            edges.append(&mut self.dangling);
+            for (from, to, edge) in edges {
+                match self.nodes.get(&to) {
+                    Some(to) => { game.world.content.add_edge(from, *to, edge); },
+                    None => self.dangling.push((from, to, edge)),
+                }
+            }
+            game.world.players = snapshot.players.iter().map(|key| self.nodes[key]).collect();
+            game.world.clock = snapshot.clock;
+            self.tick = snapshot.tick;
+
+            let count = game.world.player_count();
+            game.seats.truncate(count);
+            while game.seats.len() < count {
+                let player = game.world.player(game.seats.len()).cloned().unwrap_or_else(Player::new);
+                game.seats.push(Seat::new(player));
+            }
+            game.state = snapshot.state;
+            game.focus = Some(p);
+            if let Some(seat) = game.seats.get_mut(p) {
+                seat.button_a.pressed = snapshot.button_a;
+                seat.button_b.pressed = snapshot.button_b;
+                for indicator in seat.indicators.iter_mut() {
+                    indicator.set_player(&snapshot.selector.player);
+                }
+                seat.selector = snapshot.selector;
+            }
+
+            // What the server played is settled, the rest is guessed again on top of it
+            let ack = snapshot.ack;
+            while self.pending.front().map(|&(number, _)| number <= ack).unwrap_or(false) {
+                self.pending.pop_front();
+            }
+            for &(_, input) in self.pending.iter() {
+                predict(&mut game, p, input);
+            }
+            if let (Some(guessed), Some(position)) = (guessed, game.world.player_position(p)) {
+                if (guessed - position).norm() > 0.01 {
+                    self.corrections += 1;
+                }
+            }
+            self.game = Some(game);
+            Ok(())
+        }
+    }
+
+    impl Drop for Client {
+        /// Lets the server know right away, rather than after it times out.
+        fn drop(&mut self) {
+            if self.game.is_some() {
+                self.send(&ToServer::Leave);
+            }
+        }
+    }
+
+    /// Guesses what the server makes of one tick of input from player `p`: the player turns
+    /// and the selector moves just as they do there, then the player walks. Anything else the
+    /// player asked for waits for the server.
+    fn predict(game: &mut Walden, p: PlayerIndex, input: Input) {
+        for _ in 0..STEPS_PER_TICK {
+            let asleep_or_dead = game.world.player(p).map(|player| player.is_sleeping() || player.is_dead()).unwrap_or(true);
+            if asleep_or_dead || p >= game.seats.len() {
+                return;
+            }
+            game.steer_seat(p, input, false, &mut FixedStep);
+            game.world.walk_player(p, STEP);
+        }
+    }
+
+    impl<C> Update<C> for Client
+    where
+        C: ElapsedDelta + JoystickProvider + KeyboardProvider + MouseProvider,
+    {
+        fn update(&mut self, context: &mut C) {
+            let input = controls::read(context, 0);
+            self.accumulator += context.delta();
+            while self.accumulator >= TICK {
+                self.accumulator -= TICK;
+                self.tick(input);
+            }
+        }
+    }
+
+    impl<S> Draw<S> for Client
+    where
+        S: DrawPrimitives,
+    {
+        fn draw(&self, surface: &mut S) {
+            use mursten::graphics::PushTransform;
+
+            match self.game {
+                Some(ref game) => game.draw(surface),
+                None => {
+                    surface.clear(Palette::Void);
+                    ConnectingScreen { address: self.address.to_string() }
+                        .draw(&mut PushTransform::new(surface, convert(Translation2::from(Vector2::new(160.0, 120.0)))));
+                    surface.present();
+                },
+            }
+        }
+    }
+
+    impl Scene for Client {}
+
+    /// Runs a server until the process is stopped, one tick at a time on the clock.
+    pub fn serve(address: &str, seed: u64) {
+        let mut server = match Server::bind(address, seed) {
+            Ok(server) => server,
+            Err(error) => {
+                eprintln!("Can't listen on {}: {}", address, error);
+                return;
+            },
+        };
+        eprintln!("Serving seed {} on {}", seed, server.local_addr().map(|a| a.to_string()).unwrap_or_else(|_| address.to_string()));
+        let tick = Duration::from_micros((TICK * 1e6) as u64);
+        let mut next = Instant::now();
+        loop {
+            server.step();
+            next += tick;
+            let now = Instant::now();
+            if next > now {
+                thread::sleep(next - now);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use headless::Script;
+
+        fn loopback(seed: u64, clients: usize) -> (Server, Vec<Client>) {
+            let mut server = Server::bind("127.0.0.1:0", seed).expect("Can't listen on loopback");
+            let address = server.local_addr().unwrap();
+            let mut joined = vec![];
+            for _ in 0..clients {
+                // One at a time, so they get their players in order
+                let mut client = Client::connect(address).expect("Can't connect over loopback");
+                let deadline = Instant::now() + PATIENCE;
+                while client.game.is_none() {
+                    assert!(Instant::now() < deadline, "The client never joined");
+                    arrival(&server.socket);
+                    server.step();
+                    arrival(&client.socket);
+                    client.tick(Input::idle());
+                }
+                joined.push(client);
+            }
+            (server, joined)
+        }
+
+        /// Longest a packet gets to arrive on loopback, however slow the machine.
+        const PATIENCE: Duration = Duration::from_secs(2);
+
+        /// Waits until there is a packet on the socket, telling whether one came in time.
+        fn arrival(socket: &UdpSocket) -> bool {
+            let deadline = Instant::now() + PATIENCE;
+            let mut buffer = [0; 65536];
+            while Instant::now() < deadline {
+                if socket.peek_from(&mut buffer).is_ok() {
+                    return true;
+                }
+                thread::yield_now();
+            }
+            false
+        }
+
+        /// Throws away whatever is waiting on a socket, returning how many packets that was.
+        fn drain(socket: &UdpSocket) -> u32 {
+            let mut buffer = [0; 65536];
+            let mut count = 0;
+            while socket.recv_from(&mut buffer).is_ok() {
+                count += 1;
+            }
+            count
+        }
+
+        /// The client's world as the server would write it down.
+        fn seen_by(client: &Client) -> Records {
+            let game = client.game.as_ref().expect("The client never got a snapshot");
+            let keys: BTreeMap<NodeIndex, u32> = client.nodes.iter().map(|(key, id)| (*id, *key)).collect();
+            client.nodes.iter()
+                .map(|(key, id)| (*key, record(&game.world, *id, |to| keys.get(&to).cloned())))
+                .collect()
+        }
+
+        /// A server and two clients over loopback with scripted input and some packets lost on
+        /// the way. Both clients end up with the server's world and their players where the
+        /// server has them.
+        #[test]
+        fn clients_see_the_server_world() {
+            let (mut server, mut clients) = loopback(0, 2);
+            let scripts = [
+                Script::new()
+                    .hold(Input::walk(Dpad::Right), 60)
+                    .hold(Input::walk(Dpad::Down), 45)
+                    .wait(15)
+                    .hold(Input::a(), 10)
+                    .hold(Input::walk(Dpad::Left), 90),
+                Script::new()
+                    .wait(20)
+                    .hold(Input::walk(Dpad::Up), 80)
+                    .hold(Input::walk(Dpad::Left), 60)
+                    .tap(Input::b())
+                    .wait(30),
+            ];
+            // Then everyone lets go so the last snapshots settle
+            let ticks = scripts.iter().map(|script| script.len()).max().unwrap_or(0) + 30;
+
+            let mut lost = 0;
+            for t in 0..ticks {
+                for (client, script) in clients.iter_mut().zip(scripts.iter()) {
+                    client.tick(script.input_at(t));
+                }
+                assert!(arrival(&server.socket), "Nothing reached the server");
+                // Every so often a packet doesn't make it, one way or the other
+                if t % 17 == 5 {
+                    lost += drain(&server.socket);
+                }
+                server.step();
+                for client in clients.iter() {
+                    assert!(arrival(&client.socket), "Nothing reached a client");
+                }
+                if t % 13 == 7 {
+                    lost += drain(&clients[1].socket);
+                }
+            }
+            assert!(lost > 0, "No packets got lost on the way");
+
+            // A client whose inputs got held up is a few behind, the server catches up on them
+            // and the clients on the last snapshots
+            let deadline = Instant::now() + PATIENCE;
+            let mut expected = records(&server.game.world);
+            while clients.iter().any(|client| !client.pending.is_empty() || seen_by(client) != expected) {
+                assert!(Instant::now() < deadline, "The clients never caught up with the server");
+                server.step();
+                expected = records(&server.game.world);
+                for client in clients.iter_mut() {
+                    arrival(&client.socket);
+                    client.receive();
+                }
+            }
+
+            for (c, client) in clients.iter().enumerate() {
+                let p = client.player().expect("The client has no player");
+                assert_eq!(p, c);
+                assert!(client.pending.is_empty(), "Client {} has inputs the server never played", c + 1);
+                assert!(seen_by(client) == expected, "Client {} sees a different world than the server", c + 1);
+                let game = client.game.as_ref().unwrap();
+                assert_eq!(game.world.player_position(p), server.game.world.player_position(p));
+            }
+        }
+
+        #[test]
+        fn snapshots_fit_in_a_packet_and_lose_track_of_nothing() {
+            let game = Walden::with_world(World::from_seed(0));
+            let mut remote = Remote::new(SocketAddr::from(([127, 0, 0, 1], 20000)), 0, 0);
+            let records = |size: usize| -> Records { (0..20).map(|i| (100_000 + i, vec![i as u8; size])).collect() };
+            let first = remote.snapshot(1, &game, &records(5000));
+            assert!(!first.changed.is_empty());
+
+            // Whatever went out goes first until it's acknowledged, and now it has grown past
+            // what a packet takes
+            let second = remote.snapshot(2, &game, &records(20000));
+            let mut w = Writer::new();
+            second.encode(&mut w);
+            assert!(w.into_bytes().len() <= MAX_PACKET, "The snapshot doesn't fit in a packet");
+            assert!(second.changed.len() < first.changed.len());
+
+            // Even what was left out gets removed once it's gone
+            remote.receive(1, vec![], 2);
+            let third = remote.snapshot(3, &game, &Records::new());
+            for &(key, _) in first.changed.iter() {
+                assert!(third.removed.contains(&key), "Record {} stays with the client for good", key);
+            }
+        }
+
+        #[test]
+        fn server_turns_away_clients_past_the_cap() {
+            assert!(MAX_CLIENTS as u32 <= chunks::MAX_PLAYERS);
+            let mut server = Server::bind("127.0.0.1:0", 0).unwrap();
+            for port in 0..MAX_CLIENTS {
+                assert!(server.join(SocketAddr::from(([127, 0, 0, 1], 20000 + port as u16))));
+            }
+            assert!(!server.join(SocketAddr::from(([127, 0, 0, 1], 30000))));
+            assert_eq!(server.remotes.len(), MAX_CLIENTS);
+            assert_eq!(server.game.world.player_count(), MAX_CLIENTS);
+        }
+
+        #[test]
+        fn players_of_clients_that_go_away_are_taken_over() {
+            let (mut server, clients) = loopback(0, 1);
+            assert_eq!(server.remotes.len(), 1);
+            drop(clients);
+            assert!(arrival(&server.socket), "The client left without a word");
+            server.step();
+            assert!(server.remotes.is_empty(), "The client left and the server kept it");
+
+            let quiet = SocketAddr::from(([127, 0, 0, 1], 20000));
+            assert!(server.join(quiet));
+            assert_eq!(server.remotes[0].player, 0);
+            for _ in 0..TIMEOUT_TICKS + 2 {
+                server.step();
+            }
+            assert!(server.remotes.is_empty(), "A client that says nothing kept its player");
+            assert_eq!(server.game.world.player_count(), 1);
+        }
+    }
+}
+
+mod spawner {
+    use super::*;
+    use rand::Rng;
+
+    /// Describes how often something appears around the player and how crowded it can get.
+    pub struct SpawnRule {
+        pub name: &'static str,
+        pub spawn: fn() -> EntityKind,
+        pub day_weight: f32,
+        pub night_weight: f32,
+        /// Maximum amount in the whole world.
+        pub cap: usize,
+        /// Maximum amount inside a single region.
+        pub region_cap: usize,
+    }
+
+    impl SpawnRule {
+        pub fn weight(&self, clock: &Clock) -> f32 {
+            let daylight = clock.daylight();
+            self.day_weight * daylight + self.night_weight * (1.0 - daylight)
+        }
+    }
+
+    pub fn spawn_rules() -> Vec<SpawnRule> {
+        vec![
+            SpawnRule { name: "grass", spawn: || EntityKind::Grass, day_weight: 4.0, night_weight: 4.0, cap: 600, region_cap: 12 },
+            SpawnRule { name: "stone", spawn: || EntityKind::Stone, day_weight: 1.0, night_weight: 1.0, cap: 200, region_cap: 4 },
+            SpawnRule { name: "bush", spawn: || EntityKind::Bush(Bush::new()), day_weight: 1.5, night_weight: 0.5, cap: 150, region_cap: 3 },
+            SpawnRule { name: "mushroom", spawn: || EntityKind::Mushroom, day_weight: 0.3, night_weight: 2.0, cap: 60, region_cap: 2 },
+            SpawnRule { name: "mouse", spawn: || EntityKind::Monster(Monster::Mouse, Brain::new()), day_weight: 0.1, night_weight: 1.0, cap: 10, region_cap: 2 },
+            SpawnRule { name: "tree", spawn: || EntityKind::Tree(TREE_HITS), day_weight: 1.0, night_weight: 1.0, cap: 400, region_cap: 8 },
+        ]
+    }
+
+    /// Side of the square regions used to limit how dense the world gets.
+    pub const REGION_SIZE: f32 = 160.0;
+    /// Spawns happen out of sight, between these distances from the player.
+    const MIN_DISTANCE: f32 = 210.0;
+    const MAX_DISTANCE: f32 = 320.0;
+
+    pub fn region_of(position: Point2<f32>) -> (i32, i32) {
+        ((position.x / REGION_SIZE).floor() as i32, (position.y / REGION_SIZE).floor() as i32)
+    }
+
+    impl World {
+        /// Tries to add one entity somewhere around a player, returning it if it did.
+        pub fn spawn(&mut self, center: Point2<f32>) -> Option<NodeIndex> {
+            let rules = spawn_rules();
+            let angle = self.rng.gen_range(0.0, f32::two_pi());
+            let distance = self.rng.gen_range(MIN_DISTANCE, MAX_DISTANCE);
+            let position = center + Rotation2::new(angle) * Vector2::new(distance, 0.0);
+            let region = region_of(position);
+            if !self.chunks.is_loaded(chunk_of(position)) {
+                return None;
+            }
+
+            let available: Vec<&SpawnRule> = rules.iter()
+                .filter(|rule| {
+                    let same: Vec<&Entity> = self.content.node_indices()
+                        .map(|id| &self.content[id])
+                        .filter(|e| e.kind.name() == rule.name)
+                        .collect();
+                    same.len() < rule.cap
+                        && same.iter().filter(|e| region_of(e.position) == region).count() < rule.region_cap
+                })
+                .collect();
+
+            let total: f32 = available.iter().map(|rule| rule.weight(&self.clock)).sum();
+            if total <= 0.0 {
+                return None;
+            }
+            let mut pick = self.rng.gen_range(0.0, total);
+            let rule = available.iter().find(|rule| {
+                pick -= rule.weight(&self.clock);
+                pick < 0.0
+            })?;
+
+            let kind = (rule.spawn)();
+            if !self.entities_within(position, kind.size()).is_empty() {
+                return None;
+            }
+            Some(self.add_entity(Entity::new(kind, position)))
+        }
+    }
+}
+
+
+mod crafting {
+    use super::*;
+
+    /// What a recipe makes.
+    #[derive(Clone, Debug)]
+    pub enum Product {
+        /// Ends up in the player's hands.
+        Item(Item),
+        /// Gets built on the ground in front of the player.
+        Entity(EntityKind),
+    }
+
+    /// Items that can be turned into something else. Ingredients come out of the player's hands
+    /// and out of the container they are facing, if any.
+    #[derive(Clone, Debug)]
+    pub struct Recipe {
+        pub name: &'static str,
+        pub ingredients: &'static [(Item, u32)],
+        pub product: Product,
+    }
+
+    impl Recipe {
+        pub fn uses(&self, item: &Item) -> bool {
+            self.ingredients.iter().any(|(ingredient, _)| ingredient == item)
+        }
+    }
+
+    /// Every recipe there is, the ones that come first get picked first.
+    pub static RECIPES: [Recipe; 3] = [
+        Recipe { name: "Axe", ingredients: &[(Item::Stone, 1), (Item::Log, 1)], product: Product::Item(Item::Axe) },
+        Recipe { name: "Campfire", ingredients: &[(Item::Log, 3)], product: Product::Entity(EntityKind::Campfire(Fire::new())) },
+        Recipe { name: "Rope", ingredients: &[(Item::Grass, 3)], product: Product::Item(Item::Rope) },
+    ];
+
+    /// How far in front of the player built things go, so they don't end up on top of them.
+    const BUILD_DISTANCE: f32 = 20.0;
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum CraftError {
+        NoSuchPlayer,
+        MissingIngredients,
+    }
+
+    impl World {
+        pub fn can_craft(&self, p: PlayerIndex, recipe: &Recipe) -> bool {
+            let player = match self.player(p) {
+                Some(player) => player,
+                None => return false,
+            };
+            let inventory = self.container_in_front_of_player(p).and_then(|container| self[container].kind.inventory());
+            recipe.ingredients.iter().all(|&(ref item, count)| {
+                let held = player.hands.values().filter(|held| *held == item).count() as u32;
+                held + inventory.map(|inventory| inventory.count(item)).unwrap_or(0) >= count
+            })
+        }
+
+        /// The recipe player `p` would make right now. With a hand picked, the ones using what
+        /// is in it go first.
+        pub fn craftable(&self, p: PlayerIndex, hand: Option<Dpad>) -> Option<&'static Recipe> {
+            let held = self.player(p).and_then(|player| hand.and_then(|hand| player.hands.get(&hand)));
+            let mut available = RECIPES.iter().filter(|recipe| self.can_craft(p, recipe));
+            let preferred = held.and_then(|item| available.clone().find(|recipe| recipe.uses(item)));
+            preferred.or_else(|| available.next())
+        }
+
+        /// Uses up the ingredients, from the hands first, and makes the product. Items go in the
+        /// first free hand, or in the container if every hand is taken, or else on the ground.
+        pub fn craft(&mut self, p: PlayerIndex, recipe: &Recipe) -> Result<(), CraftError> {
+            let id = self.player_node(p).ok_or(CraftError::NoSuchPlayer)?;
+            if !self.can_craft(p, recipe) {
+                return Err(CraftError::MissingIngredients);
+            }
+            let container = self.container_in_front_of_player(p);
+            for &(ref item, count) in recipe.ingredients.iter() {
+                let mut missing = count;
+                if let Some(player) = self.player_mut(p) {
+                    for hand in Player::HANDS.iter() {
+                        if missing > 0 && player.hands.get(hand) == Some(item) {
+                            player.hands.remove(hand);
+                            missing -= 1;
+                        }
+                    }
+                }
+                if let Some(inventory) = container.and_then(|container| self.content[container].kind.inventory_mut()) {
+                    inventory.remove(item, missing);
+                }
+            }
+
+            // Players not facing anywhere build right below them
+            let facing = self.point_in_front_of_player(p)
+                .map(|in_front| in_front - self[id].position)
+                .filter(|facing| facing.norm() > 0.0)
+                .map_or(Vector2::new(0.0, 1.0), |facing| facing.normalize());
+            let position = self[id].position + facing * BUILD_DISTANCE;
+            match recipe.product.clone() {
+                Product::Item(item) => {
+                    let hand = self.player(p).and_then(|player| player.free_hand());
+                    match (hand, self.player_mut(p)) {
+                        (Some(hand), Some(player)) => {
+                            player.hands.insert(hand, item);
+                            player.current_hand = hand;
+                        },
+                        _ => {
+                            let leftover = match container.and_then(|container| self.content[container].kind.inventory_mut()) {
+                                Some(inventory) => inventory.insert(item).err(),
+                                None => Some(item),
+                            };
+                            if let Some(item) = leftover {
+                                self.add_entity(Entity::new(item.into_entity_kind(), position));
+                            }
+                        },
+                    }
+                },
+                Product::Entity(kind) => {
+                    self.add_entity(Entity::new(kind, position));
+                },
+            }
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn holding(items: &[(Dpad, Item)]) -> World {
+            let mut world = World::empty(0);
+            world.add_player();
+            let player = world.player_mut(0).unwrap();
+            player.hands.clear();
+            for &(hand, ref item) in items {
+                player.hands.insert(hand, item.clone());
+            }
+            world
+        }
+
+        #[test]
+        fn what_is_in_hand_picks_the_recipe() {
+            let mut world = holding(&[(Dpad::Up, Item::Stone), (Dpad::Right, Item::Log), (Dpad::Down, Item::Grass)]);
+            let front = world.point_in_front_of_player(0).unwrap();
+            let mut bag = Inventory::bag();
+            bag.insert(Item::Grass).unwrap();
+            bag.insert(Item::Grass).unwrap();
+            let bag = world.add_entity(Entity::new(EntityKind::Bag(bag), front));
+
+            assert_eq!(world.craftable(0, None).map(|recipe| recipe.name), Some("Axe"));
+            assert_eq!(world.craftable(0, Some(Dpad::Left)).map(|recipe| recipe.name), Some("Axe"));
+            let rope = world.craftable(0, Some(Dpad::Down)).unwrap();
+            assert_eq!(rope.name, "Rope");
+
+            assert_eq!(world.craft(0, rope), Ok(()));
+            let player = world.player(0).unwrap();
+            assert_eq!(player.hands.get(&player.current_hand), Some(&Item::Rope));
+            assert_eq!(world[bag].kind.inventory().unwrap().count(&Item::Grass), 0);
+            assert_eq!(world.craft(0, rope), Err(CraftError::MissingIngredients));
+        }
+
+        #[test]
+        fn players_facing_nowhere_build_below_them() {
+            let mut world = holding(&[(Dpad::Up, Item::Log), (Dpad::Right, Item::Log), (Dpad::Down, Item::Log)]);
+            world.player_mut(0).unwrap().log_speed = Vector2::new(0.0, 0.0);
+            let position = world.player_position(0).unwrap();
+            assert_eq!(world.craft(0, &RECIPES[1]), Ok(()));
+
+            let campfire = world.entities_within(position, BUILD_DISTANCE).into_iter()
+                .find(|id| matches!(world[*id].kind, EntityKind::Campfire(_)))
+                .expect("The campfire wasn't built");
+            assert_eq!(world[campfire].position, position + Vector2::new(0.0, BUILD_DISTANCE));
+            assert!(world.player(0).unwrap().hands.is_empty());
+        }
+    }
+}
+
+
+mod fire {
+    use super::*;
+    use codec::{Encode, Writer, Reader, DecodeError};
+    use rand::Rng;
+
+    /// Seconds a campfire burns when it gets built, and what each log adds.
+    pub const CAMPFIRE_FUEL: f32 = 90.0;
+    const LOG_FUEL: f32 = 45.0;
+    /// A fire holds no more fuel than this, extra logs are wasted.
+    const MAX_FUEL: f32 = 180.0;
+    const GRASS_FUEL: f32 = 8.0;
+    const TREE_FUEL: f32 = 40.0;
+    /// How far a fire with plenty of fuel lights up the night. Weaker fires light less.
+    const LIGHT_RADIUS: f32 = 90.0;
+    /// Players closer than this to a fire don't feel the cold, less so towards the edge.
+    const WARMTH_RADIUS: f32 = 50.0;
+    const COOK_TIME: f32 = 6.0;
+    /// Fires with at least this much fuel can catch grass and trees this close...
+    const SPREAD_FUEL: f32 = 5.0;
+    const SPREAD_RADIUS: f32 = 18.0;
+    /// ...with this chance a second for each of them at noon. Night dew keeps it down.
+    const SPREAD_CHANCE: f32 = 0.05;
+
+    /// What is burning, which decides what is left when it goes out.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum Burning {
+        /// Stays as a cold campfire that can be lit again.
+        Logs,
+        /// Leaves nothing.
+        Grass,
+        /// Leaves a stump that grows back.
+        Tree,
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct Fire {
+        pub fuel: f32,
+        pub burning: Burning,
+        /// Food on the fire and for how long it has been there.
+        pub cooking: Option<(Item, f32)>,
+    }
+
+    impl Fire {
+        pub const fn new() -> Self {
+            Self { fuel: CAMPFIRE_FUEL, burning: Burning::Logs, cooking: None }
+        }
+
+        pub fn is_lit(&self) -> bool {
+            self.fuel > 0.0
+        }
+
+        pub fn light_radius(&self) -> f32 {
+            if !self.is_lit() {
+                return 0.0;
+            }
+            LIGHT_RADIUS * (0.4 + 0.6 * (self.fuel / CAMPFIRE_FUEL).min(1.0))
+        }
+
+        /// How big the flames are, from nothing to twice a new campfire.
+        pub fn flames(&self) -> f32 {
+            if !self.is_lit() {
+                return 0.0;
+            }
+            let size = 0.5 + 0.5 * (self.fuel / CAMPFIRE_FUEL).min(1.0);
+            match self.burning {
+                Burning::Tree => size * 2.0,
+                _ => size,
+            }
+        }
+
+        /// Uses up fuel and cooks whatever is on the fire.
+        pub fn burn(&mut self, delta: f32) {
+            if !self.is_lit() {
+                return;
+            }
+            self.fuel = (self.fuel - delta).max(0.0);
+            if let Some((ref mut item, ref mut time)) = self.cooking {
+                *time += delta;
+                if *time >= COOK_TIME {
+                    if let Some(cooked) = item.cooked() {
+                        *item = cooked;
+                    }
+                }
+            }
+        }
+
+        /// What the fire becomes once it goes out, if it doesn't stay around as it is.
+        pub fn remains(&self) -> Option<EntityKind> {
+            match self.burning {
+                Burning::Tree if !self.is_lit() => Some(EntityKind::Stump(TREE_REGROW_TIME)),
+                _ => None,
+            }
+        }
+
+        /// Burnt out grass goes away altogether.
+        pub fn is_gone(&self) -> bool {
+            !self.is_lit() && self.burning == Burning::Grass
+        }
+    }
+
+    impl Encode for Fire {
+        fn encode(&self, w: &mut Writer) {
+            w.f32(self.fuel);
+            w.u8(match self.burning {
+                Burning::Logs => 0,
+                Burning::Grass => 1,
+                Burning::Tree => 2,
+            });
+            match self.cooking {
+                Some((ref item, time)) => {
+                    w.bool(true);
+                    item.encode(w);
+                    w.f32(time);
+                },
+                None => w.bool(false),
+            }
+        }
+        fn decode(r: &mut Reader) -> Result<Self, DecodeError> {
+            let fuel = r.f32()?;
+            let burning = match r.u8()? {
+                0 => Burning::Logs,
+                1 => Burning::Grass,
+                2 => Burning::Tree,
+                t => return Err(DecodeError::InvalidTag("burning", t)),
+            };
+            let cooking = if r.bool()? { Some((Item::decode(r)?, r.f32()?)) } else { None };
+            Ok(Fire { fuel, burning, cooking })
+        }
+    }
+
+    impl World {
+        pub fn campfire_in_front_of_player(&self, p: PlayerIndex) -> Option<NodeIndex> {
+            self.entity_in_front_of_player(p, |kind| matches!(kind, EntityKind::Campfire(_)))
+        }
+
+        /// What putting the item in the fire would do, if the fire takes it. Logs feed it and
+        /// raw food gets cooked, one thing at a time, as long as it burns. Grass and trees on
+        /// fire take nothing, they burn out on their own.
+        pub fn fire_tooltip(&self, id: NodeIndex, item: &Item) -> Option<Text> {
+            let fire = match self[id].kind {
+                EntityKind::Campfire(ref fire) if fire.burning == Burning::Logs => fire,
+                _ => return None,
+            };
+            match *item {
+                Item::Log if fire.fuel < MAX_FUEL => Some(Text::Stoke),
+                ref food if food.cooked().is_some() && fire.is_lit() && fire.cooking.is_none() => Some(Text::Cook),
+                _ => None,
+            }
+        }
+
+        /// Puts the item in the fire, returning whether the fire took it.
+        pub fn put_in_fire(&mut self, id: NodeIndex, item: &Item) -> bool {
+            if self.fire_tooltip(id, item).is_none() {
+                return false;
+            }
+            if let EntityKind::Campfire(ref mut fire) = self.content[id].kind {
+                match *item {
+                    Item::Log => fire.fuel = (fire.fuel + LOG_FUEL).min(MAX_FUEL),
+                    ref food => fire.cooking = Some((food.clone(), 0.0)),
+                }
+            }
+            true
+        }
+
+        /// Where the fires lighting anything within `radius` of `center` are, and how far each
+        /// one lights.
+        pub fn lights(&self, center: Point2<f32>, radius: f32) -> Vec<(NodeIndex, f32)> {
+            self.entities_within(center, radius + LIGHT_RADIUS).into_iter()
+                .filter_map(|id| match self[id].kind {
+                    EntityKind::Campfire(ref fire) if fire.is_lit() => Some((id, fire.light_radius())),
+                    _ => None,
+                })
+                .filter(|&(id, light)| (self[id].position - center).norm() < radius + light)
+                .collect()
+        }
+
+        /// From 0 far from any fire to 1 right next to one.
+        pub fn warmth_at(&self, position: Point2<f32>) -> f32 {
+            self.entities_within(position, WARMTH_RADIUS).into_iter()
+                .filter(|id| match self[*id].kind {
+                    EntityKind::Campfire(ref fire) => fire.is_lit(),
+                    _ => false,
+                })
+                .map(|id| 1.0 - ((self[id].position - position).norm() / WARMTH_RADIUS).min(1.0))
+                .fold(0.0, f32::max)
+        }
+
+        /// How cold it is at a spot, after the warmth of any fire nearby.
+        pub fn coldness_at(&self, position: Point2<f32>) -> f32 {
+            self.clock.coldness() * (1.0 - self.warmth_at(position))
+        }
+
+        /// A fire with enough fuel sometimes catches the grass and trees around it. It happens
+        /// less at night, when everything is damp.
+        pub fn spread_fire(&mut self, position: Point2<f32>, fire: &Fire) {
+            if fire.fuel < SPREAD_FUEL {
+                return;
+            }
+            let chance = SPREAD_CHANCE * self.clock.daylight() * self.delta();
+            for id in self.entities_within(position, SPREAD_RADIUS) {
+                let burning = match self[id].kind {
+                    EntityKind::Grass => Burning::Grass,
+                    EntityKind::Tree(_) => Burning::Tree,
+                    _ => continue,
+                };
+                if self.rng.gen::<f32>() < chance {
+                    self.ignite(id, burning);
+                }
+            }
+        }
+
+        fn ignite(&mut self, id: NodeIndex, burning: Burning) {
+            let fuel = match burning {
+                Burning::Logs => CAMPFIRE_FUEL,
+                Burning::Grass => GRASS_FUEL,
+                Burning::Tree => TREE_FUEL,
+            };
+            let was_solid = self[id].kind.is_solid();
+            self.content[id].kind = EntityKind::Campfire(Fire { fuel, burning, cooking: None });
+            let (position, size) = (self[id].position, self[id].kind.size());
+            self.index.update(id, position, size);
+            if was_solid != self[id].kind.is_solid() {
+                self.nav.invalidate();
+            }
+        }
+
+        /// Takes away the grass fires among these that burnt out.
+        pub fn clear_ashes(&mut self, burnt: &[NodeIndex]) {
+            for id in burnt {
+                let gone = match self.content.node_weight(*id).map(|e| &e.kind) {
+                    Some(EntityKind::Campfire(fire)) => fire.is_gone(),
+                    _ => false,
+                };
+                if gone {
+                    self.remove_entity(*id);
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn burning(world: &mut World, kind: EntityKind, burning: Burning, position: Point2<f32>) -> NodeIndex {
+            let id = world.add_entity(Entity::new(kind, position));
+            world.ignite(id, burning);
+            id
+        }
+
+        #[test]
+        fn only_campfires_take_logs_and_food() {
+            let mut world = World::empty(0);
+            let campfire = world.add_entity(Entity::new(EntityKind::Campfire(Fire::new()), Point2::new(0.0, 0.0)));
+            let grass = burning(&mut world, EntityKind::Grass, Burning::Grass, Point2::new(100.0, 0.0));
+            let tree = burning(&mut world, EntityKind::Tree(TREE_HITS), Burning::Tree, Point2::new(200.0, 0.0));
+
+            assert_eq!(world.fire_tooltip(campfire, &Item::Log), Some(Text::Stoke));
+            assert_eq!(world.fire_tooltip(campfire, &Item::Mushroom(false)), Some(Text::Cook));
+            for &id in &[grass, tree] {
+                assert_eq!(world.fire_tooltip(id, &Item::Log), None);
+                assert_eq!(world.fire_tooltip(id, &Item::Mushroom(false)), None);
+                assert!(!world.put_in_fire(id, &Item::Log), "A burning {} took a log", world[id].kind.name());
+            }
+        }
+
+        #[test]
+        fn fires_leave_what_they_burnt() {
+            let mut world = World::empty(0);
+            let campfire = world.add_entity(Entity::new(EntityKind::Campfire(Fire::new()), Point2::new(0.0, 0.0)));
+            let grass = burning(&mut world, EntityKind::Grass, Burning::Grass, Point2::new(100.0, 0.0));
+            let tree = burning(&mut world, EntityKind::Tree(TREE_HITS), Burning::Tree, Point2::new(200.0, 0.0));
+            for _ in 0..(TREE_FUEL * 60.0) as usize + 60 {
+                world.update(&mut FixedStep);
+            }
+            assert!(!world.content.contains_node(grass), "The grass fire never went away");
+            assert!(matches!(world[tree].kind, EntityKind::Stump(_)));
+            assert!(matches!(world[campfire].kind, EntityKind::Campfire(ref fire) if fire.is_lit()));
+        }
+
+        #[test]
+        fn only_fires_near_the_view_light_it() {
+            let mut world = World::empty(0);
+            let near = world.add_entity(Entity::new(EntityKind::Campfire(Fire::new()), Point2::new(150.0, 0.0)));
+            world.add_entity(Entity::new(EntityKind::Campfire(Fire::new()), Point2::new(1000.0, 0.0)));
+            let mut out = Fire::new();
+            out.fuel = 0.0;
+            world.add_entity(Entity::new(EntityKind::Campfire(out), Point2::new(10.0, 0.0)));
+            let lights = world.lights(Point2::new(0.0, 0.0), 100.0);
+            assert_eq!(lights, vec![(near, LIGHT_RADIUS)]);
+        }
+    }
+}
 
 mod entities {
     use super::*;
@@ -78,17 +6219,21 @@ mod entities {
         Tent(Inventory),
         Grass,
         Stone,
-        Tree,
-        Bush,
+        Tree(u32),
+        Stump(f32),
+        Bush(Bush),
+        Mushroom,
         Axe,
         Pond(f32),
-        //Monster(Monster),
+        Dropped(Item),
+        Monster(Monster, Brain),
+        Campfire(Fire),
     }
 
-    // #[derive(Clone, Debug)]
-    // pub enum Monster {
-    //     Mouse,
-    // }
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum Monster {
+        Mouse,
+    }
     
     use mursten::graphics::{Draw, DrawPrimitives, DrawMode, PushTransform};
 
@@ -108,9 +6253,20 @@ mod entities {
     {
         fn draw(&self, surface: &mut S) {
             match self {
+                EntityKind::Player(Player { rest: Some(Rest::Tent), .. }) => {
+                    // Inside the tent, nothing to see
+                },
+                EntityKind::Player(Player { rest: Some(Rest::Outdoors), .. }) => {
+                    surface.set_color(Palette::Player);
+                    surface.rectangle(DrawMode::Fill, Point2::new(-12.0, -6.0), 18.0, 6.0);
+                    surface.set_color(Palette::PlayerSkin);
+                    surface.rectangle(DrawMode::Fill, Point2::new(6.0, -8.0), 8.0, 8.0);
+                    surface.set_color(Palette::Player);
+                    surface.text(Point2::new(10.0, -24.0), "z");
+                },
                 EntityKind::Player(Player { t, log_speed, ..}) => {
 
-                    let amp = (log_speed.norm() - 1.0).max(0.0).min(1.0);
+                    let amp = (log_speed.norm() - 1.0).clamp(0.0, 1.0);
                     let lamp = amp * 0.5 + 0.5;
                     let speed = 6.0;
                     let t = t * speed;
@@ -172,13 +6328,81 @@ mod entities {
                         Point2::new(0.0, -5.0)
                     ]);
                 },
-                // EntityKind::Monster(monster) => {
-                //     monster.draw(surface);
-                // },
+                EntityKind::Monster(monster, _) => {
+                    let mut surface = PushTransform::new(surface, convert(Similarity2::new(Vector2::new(0.0, -8.0), 0.0, 0.35)));
+                    monster.draw(&mut surface);
+                },
                 EntityKind::Pond(size) => {
                     surface.set_color(Palette::Water);
                     surface.circle(DrawMode::Fill, Point2::origin(), *size)
                 },
+                EntityKind::Dropped(item) => {
+                    item.draw(surface);
+                },
+                EntityKind::Tree(_) => {
+                    surface.set_color(Palette::Wood);
+                    surface.rectangle(DrawMode::Fill, Point2::new(-3.0, -14.0), 6.0, 14.0);
+                    surface.set_color(Palette::Leaves);
+                    surface.circle(DrawMode::Fill, Point2::new(0.0, -24.0), 14.0);
+                },
+                EntityKind::Bush(bush) => {
+                    surface.set_color(Palette::Leaves);
+                    surface.ellipse(DrawMode::Fill, Point2::new(0.0, -6.0), 12.0, 9.0);
+                    surface.set_color(Palette::Berry);
+                    for i in 0..bush.berries {
+                        let p = Rotation2::new(i as f32 * 2.4) * Vector2::new(0.0, 3.0 + 2.0 * i as f32);
+                        surface.circle(DrawMode::Fill, Point2::new(0.0, -6.0) + p, 2.0);
+                    }
+                },
+                EntityKind::Mushroom => {
+                    Item::Mushroom(false).draw(surface);
+                },
+                EntityKind::Stump(_) => {
+                    surface.set_color(Palette::Wood);
+                    surface.rectangle(DrawMode::Fill, Point2::new(-4.0, -4.0), 8.0, 4.0);
+                },
+                EntityKind::Grass => {
+                    surface.set_color(Palette::TallGrass);
+                    surface.polygon(DrawMode::Fill, &vec![
+                        Point2::new(-6.0, 0.0),
+                        Point2::new(-5.0, -8.0),
+                        Point2::new(-2.0, -2.0),
+                        Point2::new(0.0, -10.0),
+                        Point2::new(2.0, -2.0),
+                        Point2::new(5.0, -8.0),
+                        Point2::new(6.0, 0.0),
+                    ]);
+                },
+                EntityKind::Stone => {
+                    surface.set_color(Palette::Stone);
+                    surface.ellipse(DrawMode::Fill, Point2::new(0.0, -4.0), 9.0, 6.0);
+                },
+                EntityKind::Campfire(fire) => {
+                    surface.set_color(Palette::Wood);
+                    match fire.burning {
+                        Burning::Logs => for angle in [-0.5, 0.5].iter() {
+                            let mut surface = PushTransform::new(surface, convert(Rotation2::new(*angle)));
+                            surface.rectangle(DrawMode::Fill, Point2::new(-8.0, -2.0), 16.0, 4.0);
+                        },
+                        Burning::Tree => surface.rectangle(DrawMode::Fill, Point2::new(-3.0, -14.0), 6.0, 14.0),
+                        Burning::Grass => {},
+                    }
+                    if fire.is_lit() {
+                        let mut surface = PushTransform::new(surface, convert(Similarity2::from_scaling(fire.flames())));
+                        surface.set_color(Palette::Fire);
+                        surface.polygon(DrawMode::Fill, &vec![
+                            Point2::new(-5.0, 0.0),
+                            Point2::new(-3.0, -8.0),
+                            Point2::new(0.0, -5.0),
+                            Point2::new(2.0, -12.0),
+                            Point2::new(5.0, 0.0),
+                        ]);
+                    }
+                    // Food goes over the flames
+                    if let Some((ref item, _)) = fire.cooking {
+                        item.draw(&mut PushTransform::new(surface, convert(Translation2::new(0.0, -14.0))));
+                    }
+                },
                 _ => {
                     surface.set_color(Palette::Unknown);
                     surface.circle(DrawMode::Fill, Point2::origin(), 5.0)
@@ -187,68 +6411,71 @@ mod entities {
         }
     }
 
-    // #[derive(Clone, Copy, Debug)]
-    // enum MonsterPalette {
-    //     MouseSkin,
-    //     MousePink,
-    //     MouseBrown,
-    //     Black,
-    //     Light,
-    // }
-    // 
-    // impl Color for MonsterPalette {
-    //     fn into_rgba(self) -> [f32; 4] {
-    //         match self {
-    //             MonsterPalette::MouseSkin => [0.80, 0.80, 0.80, 1.0],
-    //             MonsterPalette::MousePink => [0.80, 0.10, 0.40, 1.0],
-    //             MonsterPalette::MouseBrown => [0.70, 0.70, 0.20, 1.0],
-    //             MonsterPalette::Black => [0.10, 0.10, 0.10, 1.0],
-    //             MonsterPalette::Light => [0.90, 0.90, 0.90, 1.0],
-    //         }
-    //     }
-    // }
-
-
-    // impl<S> Draw<S> for Monster
-    //     where
-    //         S: DrawPrimitives
-    // {
-    //     fn draw(&self, surface: &mut S) {
-    //         match self {
-    //             Monster::Mouse => {
-    //                 // Left ear
-    //                 surface.set_color(MonsterPalette::MouseSkin);
-    //                 surface.circle(DrawMode::Fill, Point2::new(-24.0, -20.0), 15.0);
-    //                 surface.set_color(MonsterPalette::MousePink);
-    //                 surface.circle(DrawMode::Fill, Point2::new(-27.0, -18.0), 10.0);
-    //                 
-    //                 // Right ear
-    //                 surface.set_color(MonsterPalette::MouseSkin);
-    //                 surface.circle(DrawMode::Fill, Point2::new(24.0, -20.0), 15.0);
-    //                 surface.set_color(MonsterPalette::MousePink);
-    //                 surface.circle(DrawMode::Fill, Point2::new(27.0, -18.0), 10.0);
-    //                 
-    //                 // Body
-    //                 surface.set_color(MonsterPalette::MouseSkin);
-    //                 surface.ellipse(DrawMode::Fill, Point2::origin(), 26.0, 22.0);
-    //                 
-    //                 // Eyes
-    //                 surface.set_color(MonsterPalette::Black);
-    //                 surface.circle(DrawMode::Fill, Point2::new(-20.0, 2.0), 7.0);
-    //                 surface.circle(DrawMode::Fill, Point2::new(20.0, 2.0), 7.0);
-    //                 surface.set_color(MonsterPalette::Light);
-    //                 surface.square_centered(DrawMode::Fill, Point2::new(-21.0, 2.0), 5.0);
-    //                 surface.square_centered(DrawMode::Fill, Point2::new(19.0, 2.0), 5.0);
-    //             }
-    //         }
-    //     }
-    // }
+    #[derive(Clone, Copy, Debug)]
+    enum MonsterPalette {
+        MouseSkin,
+        MousePink,
+        Black,
+        Light,
+    }
+    
+    impl Color for MonsterPalette {
+        fn into_rgba(self) -> [f32; 4] {
+            match self {
+                MonsterPalette::MouseSkin => [0.80, 0.80, 0.80, 1.0],
+                MonsterPalette::MousePink => [0.80, 0.10, 0.40, 1.0],
+                MonsterPalette::Black => [0.10, 0.10, 0.10, 1.0],
+                MonsterPalette::Light => [0.90, 0.90, 0.90, 1.0],
+            }
+        }
+    }
+
+
+    impl<S> Draw<S> for Monster
+        where
+            S: DrawPrimitives
+    {
+        fn draw(&self, surface: &mut S) {
+            match self {
+                Monster::Mouse => {
+                    // Left ear
+                    surface.set_color(MonsterPalette::MouseSkin);
+                    surface.circle(DrawMode::Fill, Point2::new(-24.0, -20.0), 15.0);
+                    surface.set_color(MonsterPalette::MousePink);
+                    surface.circle(DrawMode::Fill, Point2::new(-27.0, -18.0), 10.0);
+                    
+                    // Right ear
+                    surface.set_color(MonsterPalette::MouseSkin);
+                    surface.circle(DrawMode::Fill, Point2::new(24.0, -20.0), 15.0);
+                    surface.set_color(MonsterPalette::MousePink);
+                    surface.circle(DrawMode::Fill, Point2::new(27.0, -18.0), 10.0);
+                    
+                    // Body
+                    surface.set_color(MonsterPalette::MouseSkin);
+                    surface.ellipse(DrawMode::Fill, Point2::origin(), 26.0, 22.0);
+                    
+                    // Eyes
+                    surface.set_color(MonsterPalette::Black);
+                    surface.circle(DrawMode::Fill, Point2::new(-20.0, 2.0), 7.0);
+                    surface.circle(DrawMode::Fill, Point2::new(20.0, 2.0), 7.0);
+                    surface.set_color(MonsterPalette::Light);
+                    surface.square_centered(DrawMode::Fill, Point2::new(-21.0, 2.0), 5.0);
+                    surface.square_centered(DrawMode::Fill, Point2::new(19.0, 2.0), 5.0);
+                }
+            }
+        }
+    }
     
     impl EntityKind {
         pub fn get_item(&self) -> Option<Item> {
             match self {
                 EntityKind::Axe => Some(Item::Axe),
-                EntityKind::Bush => Some(Item::Berry),
+                EntityKind::Bush(bush) if bush.berries > 0 => Some(Item::Berry),
+                EntityKind::Mushroom => Some(Item::Mushroom(false)),
+                EntityKind::Stone => Some(Item::Stone),
+                EntityKind::Grass => Some(Item::Grass),
+                EntityKind::Dropped(item) => Some(item.clone()),
+                EntityKind::Campfire(fire) => fire.cooking.as_ref().map(|(item, _)| item.clone()),
                 _ => None,
             }
         }
@@ -257,10 +6484,93 @@ mod entities {
             self.get_item().is_some()
         }
         
+        pub fn name(&self) -> &'static str {
+            match self {
+                EntityKind::Player(_) => "player",
+                EntityKind::Bag(_) => "bag",
+                EntityKind::Tent(_) => "tent",
+                EntityKind::Grass => "grass",
+                EntityKind::Stone => "stone",
+                EntityKind::Tree(_) => "tree",
+                EntityKind::Stump(_) => "tree",
+                EntityKind::Bush(_) => "bush",
+                EntityKind::Mushroom => "mushroom",
+                EntityKind::Axe => "axe",
+                EntityKind::Pond(_) => "pond",
+                EntityKind::Dropped(_) => "dropped",
+                EntityKind::Monster(Monster::Mouse, _) => "mouse",
+                EntityKind::Campfire(_) => "campfire",
+            }
+        }
+
+        /// What is left in the world after the item was picked up, if anything.
+        pub fn picked_up(&self) -> Option<EntityKind> {
+            match self {
+                EntityKind::Bush(bush) => Some(EntityKind::Bush(Bush { berries: bush.berries - 1, ..bush.clone() })),
+                EntityKind::Campfire(fire) => Some(EntityKind::Campfire(Fire { cooking: None, ..fire.clone() })),
+                _ => None,
+            }
+        }
+
+        pub fn inventory(&self) -> Option<&Inventory> {
+            match self {
+                EntityKind::Bag(inventory) | EntityKind::Tent(inventory) => Some(inventory),
+                _ => None,
+            }
+        }
+
+        pub fn inventory_mut(&mut self) -> Option<&mut Inventory> {
+            match self {
+                EntityKind::Bag(inventory) | EntityKind::Tent(inventory) => Some(inventory),
+                _ => None,
+            }
+        }
+        
+        /// What the entity takes up on the ground, relative to its position.
+        pub fn shape(&self) -> Shape {
+            match self {
+                EntityKind::Player(_) => Shape::Circle(8.0),
+                EntityKind::Bag(_) => Shape::Polygon(vec![
+                    Point2::new(-10.0, -10.0),
+                    Point2::new(-10.0,  10.0),
+                    Point2::new( 10.0,  10.0),
+                    Point2::new( 10.0, -10.0),
+                ]),
+                EntityKind::Tent(_) => Shape::Polygon(vec![
+                    Point2::new(-10.0, 0.0),
+                    Point2::new(-10.0, 10.0),
+                    Point2::new(0.0, 20.0),
+                    Point2::new(10.0, 10.0),
+                    Point2::new(10.0, 0.0),
+                    Point2::new(0.0, -5.0),
+                ]),
+                EntityKind::Pond(size) => Shape::Circle(*size),
+                EntityKind::Tree(_) => Shape::Circle(5.0),
+                EntityKind::Stump(_) => Shape::Circle(5.0),
+                EntityKind::Bush(_) => Shape::Circle(11.0),
+                EntityKind::Stone => Shape::Circle(8.0),
+                EntityKind::Grass => Shape::Circle(6.0),
+                EntityKind::Axe => Shape::Circle(6.0),
+                EntityKind::Dropped(_) => Shape::Circle(5.0),
+                EntityKind::Mushroom => Shape::Circle(5.0),
+                EntityKind::Monster(Monster::Mouse, _) => Shape::Circle(8.0),
+                EntityKind::Campfire(fire) => match fire.burning {
+                    Burning::Logs => Shape::Circle(8.0),
+                    Burning::Grass => Shape::Circle(6.0),
+                    Burning::Tree => Shape::Circle(5.0),
+                },
+            }
+        }
+
+        /// Radius of a circle around the whole footprint. Asked for all the time, so the
+        /// polygons have theirs worked out rather than built to measure.
         pub fn size(&self) -> f32 {
             match self {
-                EntityKind::Pond(size) => *size,
-                _ => 10.0,
+                // A corner
+                EntityKind::Bag(_) => 200.0f32.sqrt(),
+                // The ridge
+                EntityKind::Tent(_) => 20.0,
+                _ => self.shape().radius(),
             }
         }
         
@@ -269,35 +6579,98 @@ mod entities {
                 EntityKind::Bag(_) => true,
                 EntityKind::Tent(_) => true,
                 EntityKind::Stone => true,
-                EntityKind::Tree => true,
-                EntityKind::Bush => true,
+                EntityKind::Tree(_) => true,
+                EntityKind::Stump(_) => true,
+                EntityKind::Bush(_) => true,
+                EntityKind::Mushroom => false,
                 EntityKind::Pond(_) => true,
                 
                 EntityKind::Grass => false,
                 EntityKind::Player(_) => false,
                 EntityKind::Axe => false,
+                EntityKind::Dropped(_) => false,
+                // A burning tree still stands in the way
+                EntityKind::Campfire(fire) => fire.burning == Burning::Tree,
                 _ => false,
             }
         }
     }
 
-    #[derive(Clone, Debug)]
+    #[derive(Clone, Debug, PartialEq)]
     pub enum Item {
         Axe,
         Berry,
         Bottle(bool),
+        Log,
+        Mushroom(bool),
+        Stone,
+        Grass,
+        Rope,
+    }
+
+    /// How much eating something changes each of the player stats.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Nutrition {
+        pub hunger: f32,
+        pub thirst: f32,
+        pub sleep: f32,
     }
     
     impl Item {
-        pub fn do_action(self, world: &mut World) -> Option<Self> {
+        pub fn into_entity_kind(self) -> EntityKind {
+            match self {
+                Item::Axe => EntityKind::Axe,
+                item => EntityKind::Dropped(item),
+            }
+        }
+
+        pub fn nutrition(&self) -> Option<Nutrition> {
+            match self {
+                Item::Berry => Some(Nutrition { hunger: 0.15, thirst: 0.05, sleep: 0.0 }),
+                // Raw mushrooms fill you up but leave you sick and drowsy
+                Item::Mushroom(false) => Some(Nutrition { hunger: 0.25, thirst: -0.2, sleep: -0.1 }),
+                Item::Mushroom(true) => Some(Nutrition { hunger: 0.45, thirst: 0.0, sleep: 0.0 }),
+                _ => None,
+            }
+        }
+
+        /// What the item turns into after some time on a fire, if it cooks at all.
+        pub fn cooked(&self) -> Option<Item> {
+            match self {
+                Item::Mushroom(false) => Some(Item::Mushroom(true)),
+                _ => None,
+            }
+        }
+
+        pub fn max_stack(&self) -> u32 {
+            match self {
+                Item::Berry => 10,
+                Item::Log => 5,
+                Item::Stone => 5,
+                Item::Grass => 10,
+                Item::Rope => 5,
+                _ => 1,
+            }
+        }
+
+        /// What using the item does when player `p` holds it, returning what is left in hand.
+        pub fn do_action(self, world: &mut World, p: PlayerIndex) -> Option<Self> {
+            if let Some(fire) = world.campfire_in_front_of_player(p) {
+                if world.put_in_fire(fire, &self) {
+                    eprintln!("Putting {:?} in the fire", self);
+                    return None;
+                }
+            }
             match self {
                 Item::Bottle(full) => {
                     if full {
                         eprintln!("Drinking water");
-                        world.get_player_mut().thirst = 1.0;
+                        if let Some(player) = world.player_mut(p) {
+                            player.thirst = 1.0;
+                        }
                         Some(Item::Bottle(false))
                     }
-                    else if world.water_in_front_of_player() {
+                    else if world.water_in_front_of_player(p) {
                         eprintln!("Filling bottle");
                         Some(Item::Bottle(true))
                     }
@@ -306,24 +6679,43 @@ mod entities {
                         Some(Item::Bottle(false))
                     }
                 },
-                e => Some(e),
+                Item::Axe => {
+                    if let Some(tree) = world.tree_in_front_of_player(p) {
+                        world.chop(tree);
+                    }
+                    Some(Item::Axe)
+                },
+                e => match e.nutrition() {
+                    Some(nutrition) => {
+                        eprintln!("Eating {:?}", e);
+                        if let Some(player) = world.player_mut(p) {
+                            player.eat(&nutrition);
+                        }
+                        None
+                    },
+                    None => Some(e),
+                },
             }
         }
         
-        pub fn action_tooltip(self, world: &World) -> Option<Text> {
+        pub fn action_tooltip(self, world: &World, p: PlayerIndex) -> Option<Text> {
+            if let Some(text) = world.campfire_in_front_of_player(p).and_then(|fire| world.fire_tooltip(fire, &self)) {
+                return Some(text);
+            }
             match self {
                 Item::Bottle(full) => {
                     if full {
                         Some(Text::DrinkBottle)
                     }
-                    else if world.water_in_front_of_player() {
+                    else if world.water_in_front_of_player(p) {
                         Some(Text::FillBottle)
                     }
                     else {
                         None
                     }
                 },
-                _ => None
+                Item::Axe => world.tree_in_front_of_player(p).map(|_| Text::Chop),
+                e => e.nutrition().map(|_| Text::Eat),
             }
         }
         
@@ -340,9 +6732,38 @@ mod entities {
                     surface.circle(DrawMode::Fill, Point2::origin(), 5.0);
                 },
                 Item::Berry => {
-                    surface.set_color(Palette::Unknown);
+                    surface.set_color(Palette::Berry);
                     surface.circle(DrawMode::Fill, Point2::origin(), 5.0);
                 },
+                Item::Log => {
+                    surface.set_color(Palette::Wood);
+                    surface.rectangle(DrawMode::Fill, Point2::new(-8.0, -3.0), 16.0, 6.0);
+                },
+                Item::Mushroom(cooked) => {
+                    surface.set_color(Palette::MushroomStem);
+                    surface.rectangle(DrawMode::Fill, Point2::new(-2.0, -4.0), 4.0, 6.0);
+                    surface.set_color(if *cooked { Palette::Wood } else { Palette::MushroomCap });
+                    surface.ellipse(DrawMode::Fill, Point2::new(0.0, -5.0), 6.0, 3.0);
+                },
+                Item::Stone => {
+                    surface.set_color(Palette::Stone);
+                    surface.ellipse(DrawMode::Fill, Point2::origin(), 6.0, 4.0);
+                },
+                Item::Grass => {
+                    surface.set_color(Palette::TallGrass);
+                    surface.polygon(DrawMode::Fill, &vec![
+                        Point2::new(-4.0, 4.0),
+                        Point2::new(-4.0, -5.0),
+                        Point2::new(0.0, 2.0),
+                        Point2::new(4.0, -5.0),
+                        Point2::new(4.0, 4.0),
+                    ]);
+                },
+                Item::Rope => {
+                    surface.set_color(Palette::Rope);
+                    surface.circle(DrawMode::Line(2.0), Point2::origin(), 5.0);
+                    surface.circle(DrawMode::Line(2.0), Point2::origin(), 2.0);
+                },
                 Item::Bottle(full) => {
                     let mut surface = PushTransform::new(surface, convert(Similarity2::from_scaling(2.0)));
                     
@@ -378,9 +6799,38 @@ mod entities {
         pub thirst: f32,
         pub hunger: f32,
         pub sleep: f32,
+        pub health: f32,
+        pub rest: Option<Rest>,
+    }
+
+    /// Where the player is sleeping.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum Rest {
+        Tent,
+        Outdoors,
+    }
+
+    impl Rest {
+        /// Seconds of simulation time needed to go from exhausted to fully rested.
+        pub fn recovery_time(&self) -> f32 {
+            match self {
+                Rest::Tent => 20.0,
+                Rest::Outdoors => 40.0,
+            }
+        }
+
+        /// How fast hunger and thirst drain while sleeping, relative to being awake.
+        pub fn drain_factor(&self) -> f32 {
+            match self {
+                Rest::Tent => 0.3,
+                Rest::Outdoors => 1.0,
+            }
+        }
     }
     
     impl Player {
+        pub const HANDS: [Dpad; 4] = [Dpad::Up, Dpad::Right, Dpad::Down, Dpad::Left];
+
         pub fn new() -> Self {
             let mut n = Self {
                 hands: HashMap::new(),
@@ -390,19 +6840,272 @@ mod entities {
                 thirst: 1.0,
                 hunger: 1.0,
                 sleep: 1.0,
+                health: 1.0,
+                rest: None,
             };
             n.hands.insert(Dpad::Left, Item::Bottle(true));
             n
         }
+
+        pub fn is_sleeping(&self) -> bool {
+            self.rest.is_some()
+        }
+
+        /// Turns towards the d-pad, or keeps going the same way slowing down to a stop.
+        pub fn steer(&mut self, d_pad: Option<Dpad>) {
+            let direction = self.log_speed.normalize();
+            let new_speed = match d_pad {
+                Some(direction) => {
+                    let d : Vector2<_> = direction.into();
+                    d * 2.0
+                },
+                None => direction,
+            };
+
+            let d = new_speed - self.log_speed;
+            self.log_speed += d / 5.0;
+
+            if self.log_speed.norm() < 1.0 {
+                self.log_speed = self.log_speed.normalize();
+            }
+        }
+
+        pub fn is_dead(&self) -> bool {
+            self.health <= 0.0
+        }
+
+        /// Every depleted stat hurts, and health only comes back when the player is well fed,
+        /// hydrated and rested.
+        pub fn update_health(&mut self, delta: f32) {
+            let depleted = [self.hunger, self.thirst, self.sleep].iter().filter(|s| **s <= 0.0).count();
+            if depleted > 0 {
+                self.health -= depleted as f32 * delta / 20.0;
+            }
+            else if self.hunger > 0.5 && self.thirst > 0.5 && self.sleep > 0.5 {
+                self.health += delta / 60.0;
+            }
+            self.health = self.health.clamp(0.0, 1.0);
+        }
+
+        pub fn eat(&mut self, nutrition: &Nutrition) {
+            self.hunger = (self.hunger + nutrition.hunger).clamp(0.0, 1.0);
+            self.thirst = (self.thirst + nutrition.thirst).clamp(0.0, 1.0);
+            self.sleep = (self.sleep + nutrition.sleep).clamp(0.0, 1.0);
+        }
+
+        /// The current hand if it is empty, otherwise the first empty one.
+        pub fn free_hand(&self) -> Option<Dpad> {
+            if !self.hands.contains_key(&self.current_hand) {
+                return Some(self.current_hand);
+            }
+            Self::HANDS.iter().cloned().find(|hand| !self.hands.contains_key(hand))
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct Bush {
+        pub berries: u32,
+        pub regrow: f32,
+    }
+
+    impl Bush {
+        pub fn new() -> Self {
+            Self { berries: BUSH_BERRIES, regrow: BUSH_REGROW_TIME }
+        }
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Stack {
+        pub item: Item,
+        pub count: u32,
     }
 
     #[derive(Clone, Debug)]
     pub struct Inventory {
+        slots: Vec<Option<Stack>>,
+    }
+
+    impl Inventory {
+        pub fn new(capacity: usize) -> Self {
+            Self { slots: vec![None; capacity] }
+        }
+
+        pub fn from_slots(slots: Vec<Option<Stack>>) -> Self {
+            Self { slots }
+        }
+
+        pub fn bag() -> Self {
+            Self::new(4)
+        }
+
+        pub fn tent() -> Self {
+            Self::new(8)
+        }
+
+        pub fn slots(&self) -> &[Option<Stack>] {
+            &self.slots
+        }
+
+        pub fn count(&self, item: &Item) -> u32 {
+            self.slots.iter()
+                .filter_map(|s| s.as_ref())
+                .filter(|s| s.item == *item)
+                .map(|s| s.count)
+                .sum()
+        }
+
+        /// Stores the item, stacking it on top of a matching slot if there is room, or in the
+        /// first free slot otherwise. Gives the item back if it doesn't fit.
+        pub fn insert(&mut self, item: Item) -> Result<usize, Item> {
+            let max = item.max_stack();
+            let stacked = self.slots.iter().position(|s| match s {
+                Some(stack) => stack.item == item && stack.count < max,
+                None => false,
+            });
+            if let Some(i) = stacked {
+                self.slots[i].as_mut().unwrap().count += 1;
+                return Ok(i);
+            }
+            if let Some(i) = self.slots.iter().position(|s| s.is_none()) {
+                self.slots[i] = Some(Stack { item, count: 1 });
+                return Ok(i);
+            }
+            Err(item)
+        }
+
+        /// Takes out up to `count` of the item, from the last slots first. Returns how many it took.
+        pub fn remove(&mut self, item: &Item, count: u32) -> u32 {
+            let mut taken = 0;
+            for slot in (0..self.slots.len()).rev() {
+                while taken < count && self.slots[slot].as_ref().map(|stack| stack.item == *item).unwrap_or(false) {
+                    self.take(slot);
+                    taken += 1;
+                }
+            }
+            taken
+        }
+
+        /// Takes a single item out of the given slot.
+        pub fn take(&mut self, slot: usize) -> Option<Item> {
+            let (item, empty) = match self.slots.get_mut(slot) {
+                Some(Some(stack)) => {
+                    stack.count -= 1;
+                    (stack.item.clone(), stack.count == 0)
+                },
+                _ => return None,
+            };
+            if empty {
+                self.slots[slot] = None;
+            }
+            Some(item)
+        }
+
+        /// Index of the last occupied slot, the one the player gets when reaching in.
+        pub fn last_slot(&self) -> Option<usize> {
+            self.slots.iter().rposition(|s| s.is_some())
+        }
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum TransferError {
+        NotAContainer,
+        EmptyHand,
+        HandOccupied,
+        EmptySlot,
+        ContainerFull,
+        NoSuchPlayer,
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn size_is_the_radius_of_the_shape() {
+            let kinds = vec![
+                EntityKind::Bag(Inventory::bag()),
+                EntityKind::Tent(Inventory::tent()),
+                EntityKind::Player(Player::new()),
+                EntityKind::Pond(40.0),
+                EntityKind::Stone,
+                EntityKind::Dropped(Item::Berry),
+            ];
+            for kind in kinds {
+                assert_eq!(kind.size(), kind.shape().radius(), "{} has the wrong size", kind.name());
+            }
+        }
+
+        #[test]
+        fn items_stack_before_taking_another_slot() {
+            let mut bag = Inventory::bag();
+            for _ in 0..12 {
+                assert!(bag.insert(Item::Berry).is_ok());
+            }
+            assert_eq!(bag.slots()[0], Some(Stack { item: Item::Berry, count: 10 }));
+            assert_eq!(bag.slots()[1], Some(Stack { item: Item::Berry, count: 2 }));
+            assert_eq!(bag.insert(Item::Log), Ok(2));
+            assert_eq!(bag.insert(Item::Axe), Ok(3));
+            assert_eq!(bag.insert(Item::Axe), Err(Item::Axe));
+            assert_eq!(bag.insert(Item::Berry), Ok(1));
+
+            assert_eq!(bag.take(0), Some(Item::Berry));
+            assert_eq!(bag.remove(&Item::Berry, 20), 12);
+            assert_eq!(bag.count(&Item::Berry), 0);
+            assert_eq!(bag.last_slot(), Some(3));
+        }
+
+        /// A player with a bag right in front of them.
+        fn facing_a_bag() -> (World, NodeIndex) {
+            let mut world = World::empty(0);
+            let p = world.add_player();
+            let front = world.point_in_front_of_player(p).unwrap();
+            let bag = world.add_entity(Entity::new(EntityKind::Bag(Inventory::bag()), front));
+            (world, bag)
+        }
+
+        fn hand(world: &World, hand: Dpad) -> Option<Item> {
+            world.player(0).unwrap().hands.get(&hand).cloned()
+        }
+
+        #[test]
+        fn items_go_between_hands_and_containers() {
+            let (mut world, bag) = facing_a_bag();
+            assert_eq!(world.container_in_front_of_player(0), Some(bag));
+            world.player_mut(0).unwrap().hands.insert(Dpad::Up, Item::Berry);
+
+            assert_eq!(world.store_in_container(0, bag, Dpad::Up), Ok(()));
+            assert_eq!(hand(&world, Dpad::Up), None);
+            assert_eq!(world[bag].kind.inventory().unwrap().count(&Item::Berry), 1);
+            assert_eq!(world.store_in_container(0, bag, Dpad::Up), Err(TransferError::EmptyHand));
+
+            assert_eq!(world.take_from_container(0, bag, 0, Dpad::Down), Ok(()));
+            assert_eq!(hand(&world, Dpad::Down), Some(Item::Berry));
+            assert_eq!(world.take_from_container(0, bag, 0, Dpad::Right), Err(TransferError::EmptySlot));
+            world[bag].kind.inventory_mut().unwrap().insert(Item::Log).unwrap();
+            assert_eq!(world.take_from_container(0, bag, 0, Dpad::Left), Err(TransferError::HandOccupied));
+            assert_eq!(world.store_in_container(1, bag, Dpad::Right), Err(TransferError::NoSuchPlayer));
+        }
+
+        #[test]
+        fn full_containers_leave_the_item_in_hand() {
+            let (mut world, bag) = facing_a_bag();
+            for _ in 0..4 {
+                world[bag].kind.inventory_mut().unwrap().insert(Item::Axe).unwrap();
+            }
+            world.player_mut(0).unwrap().hands.insert(Dpad::Down, Item::Log);
+            assert_eq!(world.store_in_container(0, bag, Dpad::Down), Err(TransferError::ContainerFull));
+            assert_eq!(hand(&world, Dpad::Down), Some(Item::Log));
+
+            let stone = world.add_entity(Entity::new(EntityKind::Stone, Point2::new(0.0, 0.0)));
+            assert_eq!(world.store_in_container(0, stone, Dpad::Down), Err(TransferError::NotAContainer));
+            assert_eq!(hand(&world, Dpad::Down), Some(Item::Log));
+        }
     }
 }
 
 mod ui {
     use super::*;
+    use codec::{Encode, Writer, Reader, DecodeError};
     use mursten::graphics::{Draw, DrawPrimitives, DrawMode, PushTransform};
 
     #[derive(Clone, Debug)]
@@ -434,11 +7137,135 @@ mod ui {
         }
     }
 
+    pub struct GameOverScreen {
+        pub days: u32,
+    }
+
+    impl<S> Draw<S> for GameOverScreen
+    where
+        S: DrawPrimitives
+    {
+        fn draw(&self, surface: &mut S) {
+            let days = format!("You survived {} day{}", self.days, if self.days == 1 { "" } else { "s" });
+            surface.set_color(UIPalette::Text);
+            surface.text(Point2::new(-36.0, -30.0), "You died");
+            surface.text(Point2::new(-(days.len() as f32) * 4.0, -10.0), &days);
+            surface.text(Point2::new(-64.0, 20.0), "Press A to restart");
+        }
+    }
+
+    /// Shown while a networked game waits for the server to answer.
+    pub struct ConnectingScreen {
+        pub address: String,
+    }
+
+    impl<S> Draw<S> for ConnectingScreen
+    where
+        S: DrawPrimitives
+    {
+        fn draw(&self, surface: &mut S) {
+            let text = format!("Connecting to {}", self.address);
+            surface.set_color(UIPalette::Text);
+            surface.text(Point2::new(-(text.len() as f32) * 4.0, -4.0), &text);
+        }
+    }
+
+    /// Side of the squares the night is drawn in around lights.
+    const DARKNESS_TILE: f32 = 8.0;
+    /// Shades of darkness between full night and none.
+    const DARKNESS_LEVELS: f32 = 8.0;
+
+    /// The night over the whole screen, lifted around each light.
+    pub struct Darkness {
+        /// From 0.0 in daylight to 1.0 at midnight.
+        pub amount: f32,
+        /// Where each light is on screen and how far it reaches.
+        pub lights: Vec<(Point2<f32>, f32)>,
+    }
+
+    impl Darkness {
+        fn level_at(&self, point: Point2<f32>) -> u32 {
+            let light = self.lights.iter()
+                .map(|(center, radius)| 1.0 - ((point - center).norm() / radius).min(1.0))
+                .fold(0.0, f32::max);
+            (self.amount * (1.0 - light) * DARKNESS_LEVELS).round() as u32
+        }
+    }
+
+    impl<S> Draw<S> for Darkness
+    where
+        S: DrawPrimitives
+    {
+        fn draw(&self, surface: &mut S) {
+            if self.lights.is_empty() {
+                surface.set_color(Palette::Night(self.amount));
+                surface.rectangle(DrawMode::Fill, Point2::origin(), 320.0, 240.0);
+                return;
+            }
+            // Each row goes in runs of tiles as dark as each other
+            let (columns, rows) = ((320.0 / DARKNESS_TILE) as u32, (240.0 / DARKNESS_TILE) as u32);
+            for row in 0..rows {
+                let y = row as f32 * DARKNESS_TILE;
+                let mut start = 0;
+                let mut level = self.level_at(Point2::new(DARKNESS_TILE / 2.0, y + DARKNESS_TILE / 2.0));
+                for column in 1..columns + 1 {
+                    let next = if column < columns {
+                        Some(self.level_at(Point2::new((column as f32 + 0.5) * DARKNESS_TILE, y + DARKNESS_TILE / 2.0)))
+                    }
+                    else {
+                        None
+                    };
+                    if next == Some(level) {
+                        continue;
+                    }
+                    if level > 0 {
+                        surface.set_color(Palette::Night(level as f32 / DARKNESS_LEVELS));
+                        let x = start as f32 * DARKNESS_TILE;
+                        surface.rectangle(DrawMode::Fill, Point2::new(x, y), (column - start) as f32 * DARKNESS_TILE, DARKNESS_TILE);
+                    }
+                    start = column;
+                    level = next.unwrap_or(0);
+                }
+            }
+        }
+    }
+
+    /// Day and hour, drawn right-aligned to the origin.
+    pub struct ClockDisplay(pub Clock);
+
+    impl<S> Draw<S> for ClockDisplay
+    where
+        S: DrawPrimitives
+    {
+        fn draw(&self, surface: &mut S) {
+            let ClockDisplay(clock) = self;
+            let hour = clock.hour();
+            let text = format!("Day {} {:02}:{:02}", clock.day() + 1, hour.floor() as u32, (hour.fract() * 60.0).floor() as u32);
+            surface.set_color(UIPalette::Text);
+            surface.text(Point2::new(-(text.len() as f32) * 8.0, -8.0), &text);
+        }
+    }
+
+    /// Hint for what the A button will do, drawn right-aligned to the origin.
+    pub struct Tooltip(pub Text);
+
+    impl<S> Draw<S> for Tooltip
+    where
+        S: DrawPrimitives
+    {
+        fn draw(&self, surface: &mut S) {
+            let Tooltip(text) = self;
+            surface.set_color(UIPalette::Text);
+            surface.text(Point2::new(-text.width(), -text.center().y), text.str());
+        }
+    }
+
     #[derive(Clone, Debug)]
     pub enum StatIndicator {
         Sleep(Player),
         Thirst(Player),
         Hunger(Player),
+        Health(Player),
     }
     
     impl StatIndicator {
@@ -447,14 +7274,16 @@ mod ui {
                 StatIndicator::Sleep(p) => p.sleep,
                 StatIndicator::Thirst(p) => p.thirst,
                 StatIndicator::Hunger(p) => p.hunger,
+                StatIndicator::Health(p) => p.health,
             }
         }
         
-        pub fn color(&self) -> UIPalette {
+        fn color(&self) -> UIPalette {
             match self {
                 StatIndicator::Sleep(_) => UIPalette::StatIndicatorSleep,
                 StatIndicator::Thirst(_) => UIPalette::StatIndicatorThirst,
                 StatIndicator::Hunger(_) => UIPalette::StatIndicatorHunger,
+                StatIndicator::Health(_) => UIPalette::StatIndicatorHealth,
             }
         }
         
@@ -463,6 +7292,7 @@ mod ui {
                 StatIndicator::Sleep(ref mut p) => p.clone_from(player),
                 StatIndicator::Thirst(ref mut p) => p.clone_from(player),
                 StatIndicator::Hunger(ref mut p) => p.clone_from(player),
+                StatIndicator::Health(ref mut p) => p.clone_from(player),
             }
         }
     }
@@ -473,7 +7303,6 @@ mod ui {
     {
         fn draw(&self, surface: &mut S) {
             let size = 15.0;
-            let points = 30.0;
 
             surface.set_color(UIPalette::StatIndicatorBack);
             surface.circle(DrawMode::Fill, Point2::origin(), size + 2.0);
@@ -513,13 +7342,42 @@ mod ui {
             self.state == SelectorState::AboutToCancel || self.state == SelectorState::ItemChosed
         }
     }
+
+    impl Encode for Selector {
+        fn encode(&self, w: &mut Writer) {
+            w.vector(self.axis);
+            self.choice.encode(w);
+            self.player.encode(w);
+            match self.state {
+                SelectorState::Idle => w.u8(0),
+                SelectorState::Deciding(timeout) => {
+                    w.u8(1);
+                    w.f32(timeout);
+                },
+                SelectorState::ItemChosed => w.u8(2),
+                SelectorState::AboutToCancel => w.u8(3),
+            }
+        }
+        fn decode(r: &mut Reader) -> Result<Self, DecodeError> {
+            let axis = r.vector()?;
+            let choice = Option::decode(r)?;
+            let player = Player::decode(r)?;
+            let state = match r.u8()? {
+                0 => SelectorState::Idle,
+                1 => SelectorState::Deciding(r.f32()?),
+                2 => SelectorState::ItemChosed,
+                3 => SelectorState::AboutToCancel,
+                t => return Err(DecodeError::InvalidTag("selector state", t)),
+            };
+            Ok(Selector { axis, choice, player, state })
+        }
+    }
     
     impl<C> Update<C> for Selector
     where
         C: ElapsedDelta,
     {
-        fn update(&mut self, context: &mut C) {
-            
+        fn update(&mut self, _context: &mut C) {
             let direction = if let Some(dpad) = self.choice {
                 dpad.into()
             }
@@ -527,7 +7385,7 @@ mod ui {
                 Vector2::new(0.0, 0.0)
             };
             let d = direction - self.axis;
-            self.axis = self.axis + d / 2.0;
+            self.axis += d / 2.0;
         }
     }
 
@@ -584,6 +7442,7 @@ mod ui {
         StatIndicatorSleep,
         StatIndicatorThirst,
         StatIndicatorHunger,
+        StatIndicatorHealth,
     }
 
     impl Color for UIPalette {
@@ -598,16 +7457,25 @@ mod ui {
                 UIPalette::StatIndicatorSleep => [0.9, 0.9, 0.2, 1.0],
                 UIPalette::StatIndicatorThirst => [0.3, 0.3, 0.9, 1.0],
                 UIPalette::StatIndicatorHunger => [0.9, 0.3, 0.3, 1.0],
+                UIPalette::StatIndicatorHealth => [0.9, 0.9, 0.9, 1.0],
             }
         }
     }
     
+    #[derive(Clone, Copy, Debug, PartialEq)]
     pub enum Text {
         ButtonA,
         ButtonB,
         DrinkBottle,
         FillBottle,
         PickUp,
+        Chop,
+        Eat,
+        Sleep,
+        Stoke,
+        Cook,
+        /// Name of the recipe.
+        Craft(&'static str),
     }
     
     impl Text {
@@ -615,14 +7483,22 @@ mod ui {
             match self {
                 Text::ButtonA => "A",
                 Text::ButtonB => "B",
-                _ => "...",
+                Text::DrinkBottle => "Drink",
+                Text::FillBottle => "Fill",
+                Text::PickUp => "Pick up",
+                Text::Chop => "Chop",
+                Text::Eat => "Eat",
+                Text::Sleep => "Sleep",
+                Text::Stoke => "Stoke",
+                Text::Cook => "Cook",
+                Text::Craft(name) => name,
             }
         }
         pub fn width(&self) -> f32 {
             match self {
                 Text::ButtonA => 10.0,
                 Text::ButtonB => 11.0,
-                _ => 20.0,
+                t => t.str().len() as f32 * 8.0,
             }
         }
         pub fn center(&self) -> Vector2<f32> {
@@ -645,6 +7521,15 @@ enum Palette {
     Water,
     Grass,
     TallGrass,
+    Night(f32),
+    Wood,
+    Leaves,
+    Stone,
+    Berry,
+    MushroomCap,
+    MushroomStem,
+    Fire,
+    Rope,
 }
 
 impl Color for Palette {
@@ -662,130 +7547,507 @@ impl Color for Palette {
             Palette::Glass => [0.90, 0.90, 0.90, 0.8],
             Palette::Grass => [0.20, 0.70, 0.20, 0.3],
             Palette::TallGrass => [0.20, 0.50, 0.20, 1.0],
+            Palette::Night(darkness) => [0.02, 0.02, 0.12, 0.75 * darkness],
+            Palette::Wood => [0.45, 0.30, 0.15, 1.0],
+            Palette::Leaves => [0.10, 0.45, 0.15, 1.0],
+            Palette::Stone => [0.55, 0.55, 0.55, 1.0],
+            Palette::Berry => [0.75, 0.10, 0.30, 1.0],
+            Palette::MushroomCap => [0.85, 0.15, 0.10, 1.0],
+            Palette::MushroomStem => [0.95, 0.90, 0.80, 1.0],
+            Palette::Fire => [1.00, 0.55, 0.10, 1.0],
+            Palette::Rope => [0.80, 0.70, 0.45, 1.0],
+        }
+    }
+}
+
+impl<S> Draw<S> for World
+where
+    S: DrawPrimitives,
+{
+    fn draw(&self, surface: &mut S) {
+        self.draw_at(surface, |id| self.content[id].position);
+    }
+}
+
+impl World {
+    /// Draws every entity where `position` says, back to front.
+    fn draw_at<S, F>(&self, surface: &mut S, position: F)
+    where
+        S: DrawPrimitives,
+        F: Fn(NodeIndex) -> Point2<f32>,
+    {
+        use mursten::graphics::PushTransform;
+
+        // What is packed away in something isn't seen
+        let mut ids: Vec<(NodeIndex, Point2<f32>)> = self.content.node_indices()
+            .filter(|id| self.container_of(*id).is_none())
+            .map(|id| (id, position(id)))
+            .collect();
+
+        ids.sort_by(|a, b| a.1.y.partial_cmp(&b.1.y).unwrap());
+
+        for (id, at) in ids {
+            self.content[id].kind.draw(&mut PushTransform::new(surface, convert(Translation2::from(at.coords))));
+        }
+    }
+}
+
+impl World {
+    /// A world nobody has seen before, for that many players.
+    fn new(players: usize) -> Self {
+        Self::with_players(rand::random(), players)
+    }
+
+    fn from_seed(seed: u64) -> Self {
+        Self::with_players(seed, 1)
+    }
+
+    /// Builds the camp around the players and generates the land around it. The same seed
+    /// always gives the same world.
+    fn with_players(seed: u64, count: usize) -> Self {
+        let mut world = Self::empty(seed);
+
+        for i in 0..count.max(1) {
+            let position = Point2::new(START.0 + 20.0 * i as f32, START.1);
+            let id = world.add_entity(Entity::new(EntityKind::Player(Player::new()), position));
+            world.players.push(id);
+        }
+        world.add_entity(Entity::new(EntityKind::Pond(40.0), Point2::new(230.0, 140.0)));
+        let tent = world.add_entity(Entity::new(EntityKind::Tent(Inventory::tent()), Point2::new(90.0, 60.0)));
+        let bag = world.add_entity(Entity::new(EntityKind::Bag(Inventory::bag()), Point2::new(110.0, 150.0)));
+        // The camp is shared
+        for id in world.players.clone() {
+            world.link(id, tent, Edge::Owns);
+            world.link(id, bag, Edge::Owns);
+        }
+        world.add_entity(Entity::new(EntityKind::Axe, Point2::new(140.0, 190.0)));
+
+        world.add_entity(Entity::new(EntityKind::Monster(Monster::Mouse, Brain::new()), Point2::new(50.0, 80.0)));
+
+        world.stream_chunks();
+        world
+    }
+
+    /// A world with nothing in it yet.
+    fn empty(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: WorldRng::new(seed),
+            players: vec![],
+            content: StableGraph::new(),
+            index: SpatialIndex::new(),
+            nav: Navigator::new(),
+            chunks: Chunks::new(),
+            speed: 60.0,
+            delta: 0.0,
+            clock: Clock::new(DAY_LENGTH),
+            spawn_cooldown: SPAWN_INTERVAL,
+            moved: std::collections::HashMap::new(),
+        }
+    }
+
+    /// Someone joins a game that is already going. They start next to the others and share
+    /// the camp of the first player.
+    fn add_player(&mut self) -> PlayerIndex {
+        let p = self.players.len();
+        // Chunks tell players apart from the entities in them by counting down from the top
+        assert!((p as u32) < MAX_PLAYERS, "A world can't have more than {} players", MAX_PLAYERS);
+        let position = Point2::new(START.0 + 20.0 * p as f32, START.1);
+        let id = self.add_entity(Entity::new(EntityKind::Player(Player::new()), position));
+        if let Some(first) = self.player_node(0) {
+            for owned in self.related(first, Edge::Owns) {
+                self.link(id, owned, Edge::Owns);
+            }
+        }
+        self.players.push(id);
+        p
+    }
+
+    /// Adds an entity to the graph, keeping the spatial index in sync.
+    pub fn add_entity(&mut self, entity: Entity) -> NodeIndex {
+        let (position, size) = (entity.position, entity.kind.size());
+        if entity.kind.is_solid() {
+            self.nav.invalidate();
+        }
+        let id = self.content.add_node(entity);
+        self.index.insert(id, position, size);
+        id
+    }
+
+    /// Removes an entity along with everything that depends on it, like the contents of a bag
+    /// or whatever is attached to it.
+    pub fn remove_entity(&mut self, id: NodeIndex) -> Option<Entity> {
+        // Last first, so nothing is ever left behind without what it depends on
+        let mut removed = None;
+        for dependent in self.with_dependents(id).into_iter().rev() {
+            removed = self.detach_entity(dependent);
+        }
+        removed
+    }
+
+    /// Puts another entity where this one is, keeping the spatial index in sync.
+    pub fn replace_entity(&mut self, id: NodeIndex, entity: Entity) {
+        if entity.kind.is_solid() || self.content[id].kind.is_solid() {
+            self.nav.invalidate();
+        }
+        self.index.update(id, entity.position, entity.kind.size());
+        self.content[id] = entity;
+    }
+
+    /// Removes just this entity, leaving its dependents in the world.
+    pub fn detach_entity(&mut self, id: NodeIndex) -> Option<Entity> {
+        if self.content.node_weight(id).map(|e| e.kind.is_solid()).unwrap_or(false) {
+            self.nav.invalidate();
+        }
+        self.index.remove(id);
+        self.content.remove_node(id)
+    }
+
+    /// Entities whose footprint touches the circle.
+    pub fn entities_within(&self, center: Point2<f32>, radius: f32) -> Vec<NodeIndex> {
+        self.index.within_radius(center, radius)
+    }
+
+    /// Entities the ray goes through, with how far along it they start, closest first.
+    pub fn entities_along(&self, origin: Point2<f32>, direction: Vector2<f32>, length: f32) -> Vec<(NodeIndex, f32)> {
+        self.index.along_ray(origin, direction, length)
+    }
+
+    
+    pub fn point_in_front_of_player(&self, p: PlayerIndex) -> Option<Point2<f32>> {
+        let pos = self.player_position(p)?;
+        Some(pos + self.player(p)?.log_speed.normalize() * REACH)
+    }
+
+    pub fn entity_in_front_of_player<F>(&self, p: PlayerIndex, filter: F) -> Option<NodeIndex>
+    where
+        F: Fn(&EntityKind) -> bool,
+    {
+        let front = self.point_in_front_of_player(p)?;
+        let reach = 5.0;
+        self.entities_within(front, reach).into_iter()
+            .filter(|id| !self.is_player_node(*id) && !self.is_carried(*id) && filter(&self.content[*id].kind))
+            .map(|id| (id, (front - self.content[id].position).norm() - self.content[id].kind.size()))
+            .filter(|(_, d)| *d < reach)
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+            .map(|(id, _)| id)
+    }
+
+    pub fn pickupable_in_front_of_player(&self, p: PlayerIndex) -> Option<NodeIndex> {
+        self.entity_in_front_of_player(p, |kind| kind.is_pickupable())
+    }
+
+    /// Picks up whatever is in front of the player into a free hand, returning the hand used.
+    pub fn pick_up(&mut self, p: PlayerIndex) -> Option<Dpad> {
+        let hand = self.player(p)?.free_hand()?;
+        let id = self.pickupable_in_front_of_player(p)?;
+        let item = self.content[id].kind.get_item()?;
+        match self.content[id].kind.picked_up() {
+            Some(kind) => self.content[id].kind = kind,
+            None => { self.remove_entity(id); },
+        }
+        self.player_mut(p)?.hands.insert(hand, item);
+        Some(hand)
+    }
+
+    /// Drops the item held in `hand` at the player's feet, returning the new entity.
+    pub fn drop_item(&mut self, p: PlayerIndex, hand: Dpad) -> Option<NodeIndex> {
+        let item = self.player_mut(p)?.hands.remove(&hand)?;
+        let position = self.player_position(p)?;
+        Some(self.add_entity(Entity::new(item.into_entity_kind(), position)))
+    }
+
+    pub fn tree_in_front_of_player(&self, p: PlayerIndex) -> Option<NodeIndex> {
+        self.entity_in_front_of_player(p, |kind| matches!(kind, EntityKind::Tree(_)))
+    }
+
+    /// Hits a tree once. When it runs out of hits it becomes a stump and drops its logs around it.
+    pub fn chop(&mut self, tree: NodeIndex) {
+        let hits = match self.content[tree].kind {
+            EntityKind::Tree(hits) => hits.saturating_sub(1),
+            _ => return,
+        };
+        if hits > 0 {
+            self.content[tree].kind = EntityKind::Tree(hits);
+            return;
+        }
+        let position = self.content[tree].position;
+        self.content[tree].kind = EntityKind::Stump(TREE_REGROW_TIME);
+        self.index.update(tree, position, self.content[tree].kind.size());
+        self.nav.invalidate();
+        for i in 0..TREE_LOGS {
+            let angle = i as f32 * f32::two_pi() / TREE_LOGS as f32;
+            let offset = Rotation2::new(angle) * Vector2::new(18.0, 0.0);
+            self.add_entity(Entity::new(Item::Log.into_entity_kind(), position + offset));
+        }
+    }
+
+    /// A tent the player can sleep in, one that is theirs or nobody's.
+    pub fn tent_in_front_of_player(&self, p: PlayerIndex) -> Option<NodeIndex> {
+        let me = self.player_node(p)?;
+        self.entity_in_front_of_player(p, |kind| matches!(kind, EntityKind::Tent(_)))
+            .filter(|tent| self.owner_of(*tent).is_none() || self.is_linked(me, *tent, Edge::Owns))
+    }
+
+    /// Whether the player is tired enough to lie down on the ground.
+    pub fn can_sleep_outdoors(&self, p: PlayerIndex) -> bool {
+        self.player(p).map(|player| player.sleep < 0.5).unwrap_or(false)
+    }
+
+    pub fn start_sleeping(&mut self, p: PlayerIndex, rest: Rest) {
+        if let Some(player) = self.player_mut(p) {
+            player.rest = Some(rest);
+        }
+    }
+
+    pub fn wake_up(&mut self, p: PlayerIndex) {
+        if let Some(player) = self.player_mut(p) {
+            player.rest = None;
+        }
+    }
+
+    pub fn days_survived(&self) -> u32 {
+        self.clock.days_elapsed()
+    }
+
+    pub fn is_player_sleeping(&self, p: PlayerIndex) -> bool {
+        self.player(p).map(|player| player.is_sleeping()).unwrap_or(false)
+    }
+
+    /// Time only speeds up once every player still alive is asleep.
+    pub fn is_everyone_sleeping(&self) -> bool {
+        let alive: Vec<&Player> = (0..self.players.len())
+            .filter_map(|p| self.player(p))
+            .filter(|player| !player.is_dead())
+            .collect();
+        !alive.is_empty() && alive.iter().all(|player| player.is_sleeping())
+    }
+
+    pub fn is_everyone_dead(&self) -> bool {
+        (0..self.players.len()).all(|p| self.player(p).map(|player| player.is_dead()).unwrap_or(true))
+    }
+
+    pub fn container_in_front_of_player(&self, p: PlayerIndex) -> Option<NodeIndex> {
+        self.entity_in_front_of_player(p, |kind| kind.inventory().is_some())
+    }
+
+    /// Moves the item held in `hand` into the container.
+    pub fn store_in_container(&mut self, p: PlayerIndex, container: NodeIndex, hand: Dpad) -> Result<(), TransferError> {
+        let item = self.player(p).ok_or(TransferError::NoSuchPlayer)?
+            .hands.get(&hand).cloned().ok_or(TransferError::EmptyHand)?;
+        {
+            let inventory = self.content[container].kind.inventory_mut().ok_or(TransferError::NotAContainer)?;
+            inventory.insert(item).map_err(|_| TransferError::ContainerFull)?;
         }
+        self.player_mut(p).ok_or(TransferError::NoSuchPlayer)?.hands.remove(&hand);
+        Ok(())
     }
-}
 
-impl<S> Draw<S> for World
-where
-    S: DrawPrimitives,
-{
-    fn draw(&self, surface: &mut S) {
-        let mut ids: Vec<NodeIndex> = self.content.node_indices().collect();
+    /// Moves one item from a slot of the container into `hand`, which has to be free.
+    pub fn take_from_container(&mut self, p: PlayerIndex, container: NodeIndex, slot: usize, hand: Dpad) -> Result<(), TransferError> {
+        if self.player(p).ok_or(TransferError::NoSuchPlayer)?.hands.contains_key(&hand) {
+            return Err(TransferError::HandOccupied);
+        }
+        let item = {
+            let inventory = self.content[container].kind.inventory_mut().ok_or(TransferError::NotAContainer)?;
+            inventory.take(slot).ok_or(TransferError::EmptySlot)?
+        };
+        self.player_mut(p).ok_or(TransferError::NoSuchPlayer)?.hands.insert(hand, item);
+        Ok(())
+    }
 
-        ids.sort_by(|a, b| {
-            let a = self.content[a.clone()].position.y;
-            let b = self.content[b.clone()].position.y;
-            a.partial_cmp(&b).unwrap()
-        });
+    /// Whether the player can reach a pond the way they are facing.
+    pub fn water_in_front_of_player(&self, p: PlayerIndex) -> bool {
+        let (position, player) = match (self.player_position(p), self.player(p)) {
+            (Some(position), Some(player)) => (position, player),
+            _ => return false,
+        };
+        self.entities_along(position, player.log_speed, REACH).into_iter()
+            .any(|(id, _)| matches!(self.content[id].kind, EntityKind::Pond(_)))
+    }
 
-        for id in ids {
-            self.content[id].draw(surface);
+    /// Where a player standing at `position` ends up after walking at `log_speed` for `delta`
+    /// seconds. A full stride covers `speed` pixels a second.
+    pub fn walk(&self, position: Point2<f32>, size: f32, log_speed: Vector2<f32>, delta: f32) -> Point2<f32> {
+        let s = log_speed.norm().log2();
+        if s.abs() > 0.02 {
+            self.slide(position, size, log_speed.normalize() * s * self.speed * delta)
+        }
+        else {
+            position
         }
     }
-}
 
-impl World {
-    fn new() -> Self {
-        let mut content = StableGraph::new();
+    /// Moves player `p` along for `delta` seconds the way they are heading.
+    pub fn walk_player(&mut self, p: PlayerIndex, delta: f32) {
+        let id = match self.player_node(p) {
+            Some(id) => id,
+            None => return,
+        };
+        let (position, size) = (self.content[id].position, self.content[id].kind.size());
+        let log_speed = match self.content[id].kind {
+            EntityKind::Player(ref player) => player.log_speed,
+            _ => return,
+        };
+        let position = self.walk(position, size, log_speed, delta);
+        self.index.update(id, position, size);
+        self.content[id].position = position;
+    }
 
-        let player_id = content.add_node(Entity::new(EntityKind::Player(Player::new()), Point2::new(150.0, 100.0)));
-        content.add_node(Entity::new(EntityKind::Pond(40.0), Point2::new(200.0, 130.0)));
-        content.add_node(Entity::new(EntityKind::Pond(60.0), Point2::new(280.0, 120.0)));
-        content.add_node(Entity::new(EntityKind::Pond(50.0), Point2::new(240.0, 180.0)));
-        // content.add_node(Entity::new(EntityKind::Monster(Monster::Mouse), Point2::new(50.0, 80.0)));
-        // content.add_node(Entity::Tree(Tree::new(Point2::new(100.0, 200.0))));
-        // content.add_node(Entity::Tree(Tree::new(Point2::new(200.0, 180.0))));
-        // content.add_node(Entity::Monster(Monster::new(Point2::new(200.0, 200.0))));
-        // let player_id = content.add_node(Entity::Player(Player::new()));
+    pub fn player_count(&self) -> usize {
+        self.players.len()
+    }
 
-        Self { player_id, content, speed: 100.0, delta: 0.0, spawn_cooldown: 3.0 }
+    pub fn player_node(&self, p: PlayerIndex) -> Option<NodeIndex> {
+        self.players.get(p).cloned()
     }
-    
-    pub fn water_in_front_of_player(&self) -> bool {
-        let pos = self.get_player_position();
-        let player = self.get_player().clone();
-        let distance = 20.0;
-        let water_pos = pos + player.log_speed.normalize() * distance;
 
-        let mut ids: Vec<NodeIndex> = self.content.node_indices().collect();
-        for id in ids {
-            let e = &self.content[id];
-            if let EntityKind::Pond(size) = e.kind.clone() {
-                let d = (water_pos - e.position).norm();
-                if d < size {
-                    return true
-                }
-            }
-        }
-        return false
+    pub fn is_player_node(&self, id: NodeIndex) -> bool {
+        self.players.contains(&id)
     }
 
-    pub fn get_player(&self) -> &Player {
-        let pe = &self.content[self.player_id];
-        if let EntityKind::Player(ref player) = pe.kind {
-            player
-        } else {
-            panic!("Not a player!")
+    pub fn player(&self, p: PlayerIndex) -> Option<&Player> {
+        let pe = self.content.node_weight(self.player_node(p)?)?;
+        match pe.kind {
+            EntityKind::Player(ref player) => Some(player),
+            _ => None,
         }
     }
-    pub fn get_player_mut(&mut self) -> &mut Player {
-        let pe = &mut self.content[self.player_id];
-        if let EntityKind::Player(ref mut player) = pe.kind {
-            player
-        } else {
-            panic!("Not a player!")
+    pub fn player_mut(&mut self, p: PlayerIndex) -> Option<&mut Player> {
+        let id = self.player_node(p)?;
+        match self.content.node_weight_mut(id)?.kind {
+            EntityKind::Player(ref mut player) => Some(player),
+            _ => None,
         }
     }
-    pub fn get_player_position(&self) -> Point2<f32> {
-        let pe = &self.content[self.player_id];
-        pe.position.clone()
+    pub fn player_position(&self, p: PlayerIndex) -> Option<Point2<f32>> {
+        self.content.node_weight(self.player_node(p)?).map(|pe| pe.position)
+    }
+
+    /// Where the closest player that is up and about stands, the one a monster should fear.
+    pub fn nearest_awake_player(&self, position: Point2<f32>) -> Option<Point2<f32>> {
+        (0..self.players.len())
+            .filter(|p| self.player(*p).map(|player| !player.is_sleeping() && !player.is_dead()).unwrap_or(false))
+            .filter_map(|p| self.player_position(p))
+            .min_by(|a, b| (*a - position).norm().partial_cmp(&(*b - position).norm()).unwrap())
     }
 }
 
 impl<B> Update<B> for World
 where
-    B: ElapsedDelta + JoystickProvider,
+    B: ElapsedDelta,
 {
     fn update(&mut self, backend: &mut B) {
 
-        self.delta = backend.delta();
-        let mut is_alive = true;
+        // Time flies while sleeping
+        let time_scale = if self.is_everyone_sleeping() { SLEEP_TIME_SCALE } else { 1.0 };
+        self.delta = backend.delta() * time_scale;
+        self.clock.advance(self.delta);
+        self.stream_chunks();
+
+        self.spawn_cooldown -= self.delta;
+        if self.spawn_cooldown <= 0.0 {
+            self.spawn_cooldown = SPAWN_INTERVAL;
+            for p in 0..self.players.len() {
+                if let Some(center) = self.player_position(p) {
+                    self.spawn(center);
+                }
+            }
+        }
 
+        self.moved.clear();
         let ids: Vec<NodeIndex> = self.content.node_indices().collect();
+        let mut strays = vec![];
+        let mut burnt = vec![];
         for id in ids {
-            let mut o = self.content[id].clone();
-            o.update(self);
+            // Whatever got eaten along the way isn't there anymore
+            let mut o = match self.content.node_weight(id) {
+                Some(o) => o.clone(),
+                None => continue,
+            };
+            o.update(id, self);
+            if o.position != self.content[id].position {
+                self.moved.insert(id, self.content[id].position);
+                if chunk_of(o.position) != chunk_of(self.content[id].position) {
+                    strays.push(id);
+                }
+            }
+            if self.index.update(id, o.position, o.kind.size()) && o.kind.is_solid() {
+                self.nav.invalidate();
+            }
+            if let EntityKind::Campfire(ref fire) = o.kind {
+                if fire.is_gone() {
+                    burnt.push(id);
+                }
+            }
             self.content[id] = o;
         }
+        self.carry_attached();
+        self.store_strays(&strays);
+        self.clear_ashes(&burnt);
     }
 }
 
-impl Update<World> for Entity {
-    fn update(&mut self, w: &mut World) {
+impl Entity {
+    /// Plays a step of the entity, which is node `me` of the world. It's out of the graph
+    /// until it's done, so this is how it still finds its own edges.
+    fn update(&mut self, me: NodeIndex, w: &mut World) {
         let self_size = self.kind.size();
         match self.kind {
             EntityKind::Player(ref mut player) => {
-                let mut s = player.log_speed.norm().log2();
-                if s.abs() > 0.02 {
-                    let movement = player.log_speed.normalize() * s;
-                    let eids: Vec<NodeIndex> = w.content.node_indices().collect();
-                    for eid in eids {
-                        let other = w.content[eid].clone();
-                        if let EntityKind::Player(_) = other.kind {
-                            continue;
-                        }
-                        let d = ((self.position + movement) - other.position).norm();
-                        s = (d - (self_size + other.kind.size())).max(0.0).min(s);
+                if player.is_dead() {
+                    return;
+                }
+                player.update_health(w.delta());
+
+                // Cold nights make the player hungrier and more tired, unless there's a fire nearby
+                let coldness = w.coldness_at(self.position);
+                let cold = 1.0 + coldness * 0.5;
+
+                if let Some(rest) = player.rest {
+                    player.sleep = (player.sleep + w.delta() / rest.recovery_time()).min(1.0);
+                    player.hunger = (player.hunger - cold * rest.drain_factor() * w.delta() / 30.0).max(0.0);
+                    player.thirst = (player.thirst - rest.drain_factor() * w.delta() / 15.0).max(0.0);
+                    if rest == Rest::Outdoors {
+                        // Sleeping in the open at night can freeze you
+                        player.health = (player.health - coldness * w.delta() / 90.0).max(0.0);
                     }
-                    let movement = player.log_speed.normalize() * s;
-                    self.position = self.position + movement;
+                    if player.sleep >= 1.0 {
+                        player.rest = None;
+                    }
+                    return;
                 }
+
+                self.position = w.walk(self.position, self_size, player.log_speed, w.delta());
                 
-                player.sleep = (player.sleep - w.delta() / 60.0).max(0.0);
-                player.hunger = (player.hunger - w.delta() / 30.0).max(0.0);
+                player.sleep = (player.sleep - cold * w.delta() / 60.0).max(0.0);
+                player.hunger = (player.hunger - cold * w.delta() / 30.0).max(0.0);
                 player.thirst = (player.thirst - w.delta() / 15.0).max(0.0);
             },
+            EntityKind::Monster(_, ref mut brain) => {
+                let velocity = brain.think(me, self.position, w);
+                self.position = w.slide(self.position, self_size, velocity * w.delta());
+            },
+            EntityKind::Bush(ref mut bush) if bush.berries < BUSH_BERRIES => {
+                bush.regrow -= w.delta();
+                if bush.regrow <= 0.0 {
+                    bush.berries += 1;
+                    bush.regrow = BUSH_REGROW_TIME;
+                }
+            },
+            EntityKind::Stump(ref mut regrow) => {
+                *regrow -= w.delta();
+                if *regrow <= 0.0 {
+                    self.kind = EntityKind::Tree(TREE_HITS);
+                }
+            },
+            EntityKind::Campfire(ref mut fire) => {
+                fire.burn(w.delta());
+                w.spread_fire(self.position, fire);
+                if let Some(remains) = fire.remains() {
+                    self.kind = remains;
+                }
+            },
             _ => {
                 
             }
@@ -796,56 +8058,447 @@ impl Update<World> for Entity {
 
 use mursten::Scene;
 
-struct Walden {
-    world: World,
+#[derive(Clone, Debug, PartialEq)]
+enum GameState {
+    Playing,
+    GameOver { days: u32 },
+}
+
+/// What one player has on screen besides the world: buttons, selector and stats.
+struct Seat {
     button_a: Button,
     button_b: Button,
     selector: Selector,
     indicators: Vec<StatIndicator>,
-    camera_pos: Vector2<f32>,
 }
 
-impl Walden {
-    fn new() -> Self {
-        let world = World::new();
-        let player = world.get_player().clone();
-        Walden {
-            world: world,
+impl Seat {
+    fn new(player: Player) -> Self {
+        Seat {
             button_a: Button::a(),
             button_b: Button::b(),
             selector: Selector::new(player.clone()),
-            camera_pos: Vector2::new(0.0, 0.0),
             indicators: vec![
                 StatIndicator::Hunger(player.clone()),
                 StatIndicator::Thirst(player.clone()),
                 StatIndicator::Sleep(player.clone()),
+                StatIndicator::Health(player.clone()),
             ],
         }
     }
-    fn trigger_action(&mut self) {
-        let player = self.world.get_player().clone();
-        
+}
+
+const SCREEN_CENTER: (f32, f32) = (160.0, 120.0);
+/// Room kept around the players when the camera zooms out to fit them all...
+const CAMERA_MARGIN: f32 = 60.0;
+/// ...down to this far, past which they go off screen.
+const MIN_ZOOM: f32 = 0.5;
+
+struct Walden {
+    state: GameState,
+    world: World,
+    /// One per player in the world, in the same order.
+    seats: Vec<Seat>,
+    camera_pos: Vector2<f32>,
+    camera_zoom: f32,
+    /// The only player shown when each one has a screen of their own, as over the network.
+    focus: Option<PlayerIndex>,
+    /// Time that went by and isn't a whole step yet. It's not kept in saves, a loaded game
+    /// starts on a step.
+    accumulator: f32,
+    /// Where the camera was before the last step, to draw in between.
+    previous_camera: Option<(Vector2<f32>, f32)>,
+    /// Where to keep the game when the players wake up, None for games that aren't kept.
+    save_path: Option<std::path::PathBuf>,
+}
+
+impl Walden {
+    /// Carries on with the game saved at `path`, or starts a new one if it can't be read.
+    /// Either way the game gets kept there from then on.
+    fn resume(path: std::path::PathBuf) -> Self {
+        let mut game = match Walden::load(&path) {
+            Ok(game) => {
+                eprintln!("Loaded {}", path.display());
+                game
+            },
+            Err(error) => {
+                eprintln!("Can't load {}, starting over: {}", path.display(), error);
+                Self::fresh()
+            },
+        };
+        game.save_path = Some(path);
+        game
+    }
+    /// Starts a new game kept at `path`, replacing whatever was saved there when it gets saved.
+    fn start(path: std::path::PathBuf) -> Self {
+        Walden { save_path: Some(path), ..Self::fresh() }
+    }
+    fn fresh() -> Self {
+        // Set WALDEN_SEED to replay a specific world
+        let seed = std::env::var("WALDEN_SEED").ok()
+            .and_then(|seed| seed.parse().ok())
+            .unwrap_or_else(rand::random);
+        eprintln!("World seed: {}", seed);
+        Self::with_world(World::from_seed(seed))
+    }
+    fn with_world(world: World) -> Self {
+        let seats = (0..world.player_count())
+            .map(|p| Seat::new(world.player(p).cloned().unwrap_or_else(Player::new)))
+            .collect();
+        Walden {
+            state: GameState::Playing,
+            world,
+            seats,
+            camera_pos: Vector2::new(0.0, 0.0),
+            camera_zoom: 1.0,
+            focus: None,
+            accumulator: 0.0,
+            previous_camera: None,
+            save_path: None,
+        }
+    }
+    /// Someone joins the game halfway through.
+    fn add_player(&mut self) -> PlayerIndex {
+        let p = self.world.add_player();
+        self.seats.push(Seat::new(self.world.player(p).cloned().unwrap_or_else(Player::new)));
+        p
+    }
+    /// Whoever presses A on a joystick nobody is playing with yet joins in.
+    fn join<C>(&mut self, context: &C)
+    where
+        C: JoystickProvider,
+    {
+        if self.is_game_over() {
+            return;
+        }
+        let joysticks = context.available_joysticks();
+        while let Some(jid) = joysticks.get(self.seats.len()) {
+            if self.seats.len() as u32 >= MAX_PLAYERS || !Input::from_state(&context.joystick(*jid)).a {
+                break;
+            }
+            let p = self.add_player();
+            eprintln!("Player {} joined", p + 1);
+            // The press that joined doesn't do anything else
+            self.seats[p].selector.state = SelectorState::AboutToCancel;
+        }
+    }
+    /// Starts over in a new world, with everyone that was playing.
+    fn restart(&mut self) {
+        eprintln!("Starting over");
+        let save_path = self.save_path.take();
+        *self = Walden::with_world(World::new(self.world.player_count()));
+        self.save_path = save_path;
+    }
+    fn autosave(&self) {
+        if let Some(path) = self.save_path.clone() {
+            match self.save(&path) {
+                Ok(()) => eprintln!("Saved to {}", path.display()),
+                Err(error) => eprintln!("Can't save to {}: {}", path.display(), error),
+            }
+        }
+    }
+    fn is_game_over(&self) -> bool {
+        self.state != GameState::Playing
+    }
+    fn trigger_action(&mut self, p: PlayerIndex) {
+        let player = match self.world.player(p) {
+            Some(player) => player.clone(),
+            None => return,
+        };
+
         if let Some(item) = player.hands.get(&player.current_hand) {
             eprintln!("Doing action with {:?}", item);
-            if let Some(resolved_item) = item.clone().do_action(&mut self.world) {
-                self.world.get_player_mut().hands.insert(player.current_hand, resolved_item);
-            }
-            else {
-                self.world.get_player_mut().hands.remove(&player.current_hand);
+            let resolved_item = item.clone().do_action(&mut self.world, p);
+            if let Some(hands) = self.world.player_mut(p).map(|player| &mut player.hands) {
+                match resolved_item {
+                    Some(resolved_item) => { hands.insert(player.current_hand, resolved_item); },
+                    None => { hands.remove(&player.current_hand); },
+                }
             }
         }
+        else if self.world.tent_in_front_of_player(p).is_some() {
+            self.world.start_sleeping(p, Rest::Tent);
+        }
+        else if let Some(hand) = self.world.pick_up(p) {
+            eprintln!("Picked up {:?}", self.world.player(p).and_then(|player| player.hands.get(&hand)));
+        }
+        else if self.world.can_sleep_outdoors(p) {
+            self.world.start_sleeping(p, Rest::Outdoors);
+        }
         else {
             eprintln!("Doing action with empty hand");
         }
     }
-    fn swap_item(&mut self, dpad: Dpad) {
-        let player = self.world.get_player().clone();
-        let item = player.hands.get(&player.current_hand);
-        let new_item = player.hands.get(&dpad);
-        eprintln!("Swapping {:?} for {:?}", item, new_item);
-        
-        self.world.get_player_mut().current_hand = dpad;
+    fn secondary_action(&mut self, p: PlayerIndex) {
+        if self.world.container_in_front_of_player(p).is_some() {
+            self.use_container(p);
+        }
+        else if let Some(hand) = self.world.player(p).map(|player| player.current_hand) {
+            if let Some(id) = self.world.drop_item(p, hand) {
+                eprintln!("Dropped {:?}", self.world[id].kind);
+            }
+        }
+    }
+    fn craft(&mut self, p: PlayerIndex, hand: Option<Dpad>) {
+        match self.world.craftable(p, hand) {
+            Some(recipe) => match self.world.craft(p, recipe) {
+                Ok(()) => eprintln!("Made {}", recipe.name),
+                Err(error) => eprintln!("Can't make {}: {:?}", recipe.name, error),
+            },
+            None => eprintln!("Nothing to make with that"),
+        }
+    }
+    /// What B makes while the selector is open.
+    fn craft_tooltip(&self, p: PlayerIndex) -> Option<Text> {
+        let seat = &self.seats[p];
+        if seat.selector.state == SelectorState::Idle {
+            return None;
+        }
+        self.world.craftable(p, seat.selector.choice).map(|recipe| Text::Craft(recipe.name))
+    }
+    fn tooltip(&self, p: PlayerIndex) -> Option<Text> {
+        let player = self.world.player(p)?;
+        match player.hands.get(&player.current_hand) {
+            Some(item) => item.clone().action_tooltip(&self.world, p),
+            None => {
+                if self.world.tent_in_front_of_player(p).is_some() {
+                    Some(Text::Sleep)
+                }
+                else if self.world.pickupable_in_front_of_player(p).is_some() {
+                    Some(Text::PickUp)
+                }
+                else if self.world.can_sleep_outdoors(p) {
+                    Some(Text::Sleep)
+                }
+                else {
+                    None
+                }
+            },
+        }
+    }
+    fn use_container(&mut self, p: PlayerIndex) {
+        let container = match self.world.container_in_front_of_player(p) {
+            Some(container) => container,
+            None => return,
+        };
+        let (hand, holding) = match self.world.player(p) {
+            Some(player) => (player.current_hand, player.hands.contains_key(&player.current_hand)),
+            None => return,
+        };
+        let result = if holding {
+            self.world.store_in_container(p, container, hand)
+        }
+        else {
+            match self.world[container].kind.inventory().and_then(|i| i.last_slot()) {
+                Some(slot) => self.world.take_from_container(p, container, slot, hand),
+                None => Err(TransferError::EmptySlot),
+            }
+        };
+        if let Err(error) = result {
+            eprintln!("Can't use container: {:?}", error);
+        }
+    }
+    fn swap_item(&mut self, p: PlayerIndex, dpad: Dpad) {
+        if let Some(player) = self.world.player_mut(p) {
+            eprintln!("Swapping {:?} for {:?}", player.hands.get(&player.current_hand), player.hands.get(&dpad));
+            player.current_hand = dpad;
+        }
+    }
+
+    /// Moves the camera towards the players, zooming out when they are too far apart to fit.
+    fn update_camera(&mut self) {
+        let alive: Vec<PlayerIndex> = (0..self.world.player_count())
+            .filter(|p| self.world.player(*p).map(|player| !player.is_dead()).unwrap_or(false))
+            .collect();
+        let followed = match self.focus {
+            Some(p) => vec![p],
+            None if alive.is_empty() => (0..self.world.player_count()).collect(),
+            None => alive,
+        };
+        let positions: Vec<Point2<f32>> = followed.iter().filter_map(|p| self.world.player_position(*p)).collect();
+        if positions.is_empty() {
+            return;
+        }
+        let count = positions.len() as f32;
+        let center = Point2::from(positions.iter().fold(Vector2::new(0.0, 0.0), |sum, p| sum + p.coords) / count);
+        let look_ahead = followed.iter()
+            .filter_map(|p| self.world.player(*p))
+            .fold(Vector2::new(0.0, 0.0), |sum, player| {
+                if player.is_sleeping() { sum } else { sum + player.log_speed.normalize() * 40.0 }
+            }) / count;
+
+        let pos = center - Vector2::new(SCREEN_CENTER.0, SCREEN_CENTER.1);
+        let offset = (pos + look_ahead).coords * -1.0;
+        self.camera_pos = self.camera_pos * 0.9 + offset * 0.1;
+
+        let (low, high) = positions.iter().fold((center, center), |(low, high), p| {
+            (Point2::new(low.x.min(p.x), low.y.min(p.y)), Point2::new(high.x.max(p.x), high.y.max(p.y)))
+        });
+        let zoom = (SCREEN_CENTER.0 * 2.0 / (high.x - low.x + CAMERA_MARGIN * 2.0))
+            .min(SCREEN_CENTER.1 * 2.0 / (high.y - low.y + CAMERA_MARGIN * 2.0))
+            .clamp(MIN_ZOOM, 1.0);
+        self.camera_zoom = if (zoom - self.camera_zoom).abs() < 0.001 { zoom } else { self.camera_zoom * 0.9 + zoom * 0.1 };
     }
+
+    /// How far drawing is from the last step towards the next one, from 0 to 1.
+    fn alpha(&self) -> f32 {
+        (self.accumulator / STEP).clamp(0.0, 1.0)
+    }
+
+    /// Where an entity gets drawn, between where it was and where it is.
+    fn drawn_position(&self, id: NodeIndex) -> Point2<f32> {
+        let position = self.world[id].position;
+        match self.world.moved.get(&id) {
+            Some(previous) => previous + (position - previous) * self.alpha(),
+            None => position,
+        }
+    }
+
+    /// From world to screen coordinates.
+    fn camera(&self) -> Matrix3<f32> {
+        let (pos, zoom) = match self.previous_camera {
+            Some((pos, zoom)) => (pos + (self.camera_pos - pos) * self.alpha(), zoom + (self.camera_zoom - zoom) * self.alpha()),
+            None => (self.camera_pos, self.camera_zoom),
+        };
+        if zoom == 1.0 {
+            return convert(Translation2::from(pos));
+        }
+        let center = Vector2::new(SCREEN_CENTER.0, SCREEN_CENTER.1);
+        convert(Similarity2::new(center, 0.0, zoom) * Translation2::from(pos - center))
+    }
+
+    /// Reads the buttons and d-pad of one player, runs their selector and does whatever they
+    /// asked for.
+    fn update_seat<C>(&mut self, p: PlayerIndex, input: Input, context: &mut C)
+    where
+        C: ElapsedDelta,
+    {
+        if self.world.player(p).map(|player| player.is_dead()).unwrap_or(true) {
+            return;
+        }
+
+        // Letting go of any button wakes the player up
+        let sleeping = self.world.is_player_sleeping(p);
+        let released = (self.seats[p].button_a.pressed && !input.a)
+            || (self.seats[p].button_b.pressed && !input.b);
+        if sleeping && released {
+            self.world.wake_up(p);
+        }
+
+        match self.steer_seat(p, input, sleeping, context) {
+            Some(SeatAction::Primary) => self.trigger_action(p),
+            Some(SeatAction::Secondary) => self.secondary_action(p),
+            Some(SeatAction::Craft(hand)) => self.craft(p, hand),
+            Some(SeatAction::Swap(hand)) => self.swap_item(p, hand),
+            None => {},
+        }
+    }
+
+    /// Turns the player towards the d-pad and moves their buttons and selector along, without
+    /// touching anything else in the world. Returns what the player asked for, if anything.
+    /// This much of a step can be guessed before the server plays it.
+    fn steer_seat<C>(&mut self, p: PlayerIndex, input: Input, sleeping: bool, context: &mut C) -> Option<SeatAction>
+    where
+        C: ElapsedDelta,
+    {
+        {
+            let seat = &mut self.seats[p];
+            let player = self.world.player_mut(p)?;
+            player.t += context.delta();
+            let free = seat.selector.state == SelectorState::Idle && !sleeping;
+            player.steer(if free { input.d_pad } else { None });
+
+            seat.selector.player = player.clone();
+            for indicator in seat.indicators.iter_mut() {
+                indicator.set_player(player);
+            }
+        }
+
+        let seat = &mut self.seats[p];
+        let b_just_pressed = input.b && !seat.button_b.pressed;
+        seat.button_a.pressed = input.a;
+        seat.button_b.pressed = input.b;
+
+        let mut action = None;
+        if b_just_pressed && seat.selector.state == SelectorState::Idle && !sleeping {
+            action = Some(SeatAction::Secondary);
+        }
+        else if b_just_pressed && !sleeping {
+            // B with the selector open makes something, and letting go of A afterwards does
+            // nothing else
+            action = Some(SeatAction::Craft(seat.selector.choice));
+            seat.selector.state = SelectorState::AboutToCancel;
+        }
+
+        let next_state = match seat.selector.state.clone() {
+            SelectorState::Idle => {
+                if input.a && !sleeping {
+                    SelectorState::Deciding(0.5)
+                }
+                else {
+                    SelectorState::Idle
+                }
+            },
+            SelectorState::Deciding(timeout) => {
+                seat.selector.choice = input.d_pad;
+
+                if !input.a {
+                    action = Some(SeatAction::Primary);
+                    SelectorState::Idle
+                }
+                else if input.d_pad.is_some() {
+                    SelectorState::ItemChosed
+                }
+                else if timeout <= 0.0 {
+                    SelectorState::AboutToCancel
+                }
+                else {
+                    SelectorState::Deciding(timeout - context.delta())
+                }
+            },
+            SelectorState::ItemChosed => {
+                seat.selector.choice = input.d_pad;
+
+                match input.d_pad {
+                    Some(hand) if !input.a => {
+                        action = Some(SeatAction::Swap(hand));
+                        SelectorState::Idle
+                    },
+                    Some(_) => SelectorState::ItemChosed,
+                    None => SelectorState::AboutToCancel,
+                }
+            },
+            SelectorState::AboutToCancel => {
+                seat.selector.choice = input.d_pad;
+
+                if input.d_pad.is_some() && input.a {
+                    SelectorState::ItemChosed
+                }
+                else if input.d_pad.is_none() && !input.a {
+                    SelectorState::Idle
+                }
+                else {
+                    SelectorState::AboutToCancel
+                }
+            }
+        };
+        seat.selector.state = next_state;
+        seat.selector.update(context);
+        action
+    }
+}
+
+/// What a player asked for with a step of input.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SeatAction {
+    /// A let go of without picking a hand: use what is held, or the hands.
+    Primary,
+    /// B with the selector closed: drop, or use a container.
+    Secondary,
+    /// B with the selector open: make something out of the hand picked.
+    Craft(Option<Dpad>),
+    /// A let go of while picking a hand: hold that one.
+    Swap(Dpad),
 }
 
 impl<S> Draw<S> for Walden
@@ -853,176 +8506,201 @@ where
     S: DrawPrimitives,
 {
     fn draw(&self, surface: &mut S) {
+        use mursten::graphics::PushTransform;
+
+        if let GameState::GameOver { days } = self.state {
+            surface.clear(Palette::Void);
+            GameOverScreen { days }.draw(&mut PushTransform::new(surface, convert(Translation2::from(Vector2::new(160.0, 120.0)))));
+            surface.present();
+            return;
+        }
+
         surface.clear(Palette::Grass);
 
-        use mursten::graphics::PushTransform;
+        let together = self.seats.len() > 1;
         {
-            self.world.draw(
-                &mut PushTransform::new(
-                    surface,
-                    convert(Translation2::from(self.camera_pos))
-                )
-            );
+            let mut surface = PushTransform::new(surface, self.camera());
+            self.world.draw_at(&mut surface, |id| self.drawn_position(id));
+            // Tell the players apart
+            if together {
+                surface.set_color(Palette::Player);
+                for (p, id) in self.world.players.iter().enumerate() {
+                    surface.text(self.drawn_position(*id) + Vector2::new(-4.0, -64.0), &format!("{}", p + 1));
+                }
+            }
         }
 
-        
-        let player = self.world.get_player().clone();
-         
-        
-        if let Some(item) = player.hands.get(&player.current_hand) {
-            item.draw(
-                &mut PushTransform::new(
-                    surface,
-                    convert(Translation2::from(Vector2::new(260.0, 220.0)))
-                )
-            );
-        }
-        self.button_a.draw(&mut PushTransform::new(surface, convert(Translation2::from(Vector2::new(280.0, 220.0)))));
-        self.button_b.draw(&mut PushTransform::new(surface, convert(Translation2::from(Vector2::new(300.0, 200.0)))));
-        if self.selector.is_visible() {
-            self.selector.draw(&mut PushTransform::new(surface, convert(Translation2::from(Vector2::new(160.0, 120.0)))));
-        }
-        
-        for (i, indicator) in self.indicators.iter().enumerate() {
-            indicator.draw(
-                &mut PushTransform::new(
-                    surface,
-                    convert(Translation2::from(Vector2::new(25.0 + 40.0 * i as f32, 25.0)))
-                )
-            );
+        // Darken everything as the night comes, except around fires
+        let camera = self.camera();
+        // The camera only ever scales evenly
+        let scale = camera[(0, 0)];
+        let view = Point2::new((SCREEN_CENTER.0 - camera[(0, 2)]) / scale, (SCREEN_CENTER.1 - camera[(1, 2)]) / scale);
+        let view_radius = Vector2::new(SCREEN_CENTER.0, SCREEN_CENTER.1).norm() / scale;
+        let lights = self.world.lights(view, view_radius).into_iter()
+            .map(|(id, radius)| {
+                let position = self.drawn_position(id);
+                let v = camera * Vector3::new(position.x, position.y, 1.0);
+                (Point2::new(v.x, v.y), radius * scale)
+            })
+            .collect();
+        Darkness { amount: 1.0 - self.world.clock.daylight(), lights }.draw(surface);
+
+        let shown: Vec<PlayerIndex> = match self.focus {
+            Some(p) => vec![p],
+            None => (0..self.seats.len()).collect(),
+        };
+        for (slot, p) in shown.into_iter().enumerate() {
+            let (seat, player) = match (self.seats.get(p), self.world.player(p)) {
+                (Some(seat), Some(player)) => (seat, player),
+                _ => continue,
+            };
+            // The first player's buttons go bottom right, the second's bottom left, and so on up
+            let corner = Vector2::new(-160.0 * (slot % 2) as f32, -50.0 * (slot / 2) as f32);
+            let at = |x: f32, y: f32| convert(Translation2::from(Vector2::new(x, y) + corner));
+
+            if let Some(item) = player.hands.get(&player.current_hand) {
+                item.draw(&mut PushTransform::new(surface, at(260.0, 220.0)));
+            }
+            seat.button_a.draw(&mut PushTransform::new(surface, at(280.0, 220.0)));
+            if let Some(text) = self.tooltip(p) {
+                Tooltip(text).draw(&mut PushTransform::new(surface, at(245.0, 220.0)));
+            }
+            seat.button_b.draw(&mut PushTransform::new(surface, at(300.0, 200.0)));
+            if let Some(text) = self.craft_tooltip(p) {
+                Tooltip(text).draw(&mut PushTransform::new(surface, at(285.0, 200.0)));
+            }
+            if seat.selector.is_visible() {
+                // Alone the selector sits in the middle, together it goes over each player
+                let center = match self.world.player_node(p).map(|id| self.drawn_position(id)) {
+                    Some(position) if together && self.focus.is_none() => {
+                        let v = self.camera() * Vector3::new(position.x, position.y - 24.0, 1.0);
+                        Vector2::new(v.x, v.y)
+                    },
+                    _ => Vector2::new(SCREEN_CENTER.0, SCREEN_CENTER.1),
+                };
+                seat.selector.draw(&mut PushTransform::new(surface, convert(Translation2::from(center))));
+            }
+
+            for (i, indicator) in seat.indicators.iter().enumerate() {
+                indicator.draw(
+                    &mut PushTransform::new(
+                        surface,
+                        convert(Translation2::from(Vector2::new(25.0 + 40.0 * i as f32, 25.0 + 40.0 * slot as f32)))
+                    )
+                );
+            }
         }
-        
+
+        ClockDisplay(self.world.clock.clone()).draw(&mut PushTransform::new(surface, convert(Translation2::from(Vector2::new(310.0, 20.0)))));
+
         surface.present();
     }
 }
 
 impl<C> Update<C> for Walden
 where
-    C: ElapsedDelta + JoystickProvider, 
+    C: ElapsedDelta + JoystickProvider + KeyboardProvider + MouseProvider,
 {
     fn update(&mut self, context: &mut C) {
-        if let Some(jid) = context.available_joysticks().first() {
-            let joystick = context.joystick(*jid);
-            {
-                {
-                    let player = self.world.get_player_mut();
-                    player.t += context.delta();
-                    let direction = player.log_speed.normalize();
-
-                    let new_speed = if self.selector.state == SelectorState::Idle {
-                        match joystick.d_pad.clone() {
-                            Some(direction) => {
-                                let d : Vector2<_> = direction.into();
-                                d * 2.0
-                            },
-                            None => direction,
-                        }
-                    }
-                    else { direction };
-                    
-                    let d = new_speed - player.log_speed;
-                    player.log_speed += d / 5.0;
-                    
-                    if player.log_speed.norm() < 1.0 {
-                        player.log_speed = player.log_speed.normalize();
-                    }
-                    
-                    self.selector.player = player.clone();
-                    for ref mut indicator in self.indicators.iter_mut() {
-                        indicator.set_player(player);
-                    }
-                }
+        self.join(context);
+        // Joystick, keyboard and mouse all end up as the same d-pad and buttons
+        let inputs: Vec<Input> = (0..self.seats.len()).map(|p| controls::read(context, p)).collect();
 
-                self.button_a.pressed = joystick.a.is_pressed();
-                self.button_b.pressed = joystick.b.is_pressed();
+        // The game moves in steps of the same length however long the frame took, as many as
+        // fit in the time that went by. Whatever is left shows up as how far drawing gets
+        // towards the next one.
+        self.accumulator += context.delta().min(MAX_FRAME);
+        while self.accumulator > STEP - STEP_SLACK {
+            self.accumulator -= STEP;
+            self.step(&inputs);
+        }
+    }
+}
 
+impl Walden {
+    fn step(&mut self, inputs: &[Input]) {
+        if self.is_game_over() {
+            // Anyone can start over
+            let released = self.seats.iter().zip(inputs.iter()).any(|(seat, input)| seat.button_a.pressed && !input.a);
+            if released {
+                self.restart();
+                return;
+            }
+            for (seat, input) in self.seats.iter_mut().zip(inputs.iter()) {
+                seat.button_a.pressed = input.a;
+            }
+            return;
+        }
+        let was_sleeping = self.world.is_everyone_sleeping();
+        self.previous_camera = Some((self.camera_pos, self.camera_zoom));
 
-                let state = self.selector.state.clone();
-                let next_state = match state {
-                    SelectorState::Idle => {
-                        if joystick.a.is_pressed() {
-                            SelectorState::Deciding(0.5)
-                        }
-                        else {
-                            SelectorState::Idle
-                        }
-                    },
-                    SelectorState::Deciding(timeout) => {
-                        self.selector.choice = joystick.d_pad;
-                        
-                        if joystick.a.is_not_pressed() {
-                            self.trigger_action();
-                            SelectorState::Idle
-                        }
-                        else if joystick.d_pad.is_some() {
-                            SelectorState::ItemChosed
-                        } 
-                        else if timeout <= 0.0 {
-                            SelectorState::AboutToCancel
-                        }
-                        else {
-                            SelectorState::Deciding(timeout - context.delta())
-                        }
-                    },
-                    SelectorState::ItemChosed => {
-                        self.selector.choice = joystick.d_pad;
-                        
-                        if joystick.d_pad.is_some() {
-                            if joystick.a.is_not_pressed() {
-                                self.swap_item(joystick.d_pad.unwrap());
-                                SelectorState::Idle
-                            }
-                            else {
-                                SelectorState::ItemChosed
-                            }
-                        }
-                        else {
-                            SelectorState::AboutToCancel
-                        }
-                    },
-                    SelectorState::AboutToCancel => {
-                        self.selector.choice = joystick.d_pad;
-                        
-                        if joystick.d_pad.is_some() {
-                            if joystick.a.is_pressed() {
-                                SelectorState::ItemChosed
-                            }
-                            else {
-                                SelectorState::AboutToCancel
-                            }
-                        }
-                        else {
-                            if joystick.a.is_not_pressed() {
-                                SelectorState::Idle
-                            }
-                            else {
-                                SelectorState::AboutToCancel
-                            }
-                        }
-                    }
-                };
-                self.selector.state = next_state;
-                self.selector.update(context);
+        for (p, input) in inputs.iter().enumerate() {
+            self.update_seat(p, *input, &mut FixedStep);
+        }
+
+        self.update_camera();
+
+        self.world.update(&mut FixedStep);
+
+        if self.world.is_everyone_dead() {
+            eprintln!("Nobody made it");
+            self.state = GameState::GameOver { days: self.world.days_survived() };
+            // Nothing to come back to
+            if let Some(ref path) = self.save_path {
+                let _ = std::fs::remove_file(path);
             }
         }
-        
-        {
-            let pos = self.world.get_player_position() - Vector2::new(160.0, 120.0);
-            let offset = self.world.get_player().log_speed.normalize() * 40.0;
-            let offset = (pos + offset).coords * -1.0;
-            self.camera_pos = self.camera_pos * 0.9 + offset * 0.1;
+        else if was_sleeping && !self.world.is_everyone_sleeping() {
+            // A good night's sleep is when the game gets kept
+            self.autosave();
         }
-        
-        self.world.update(context);
     }
 }
 
 impl Scene for Walden {}
 
-use mursten::Game;
-use mursten_ggez_backend::GgezBackend;
-
 fn main() {
-    Game::new(GgezBackend::new(320, 240))
-        .run(Walden::new());
+    // --record <file> plays a new game and writes down every frame of it, --replay <file>
+    // plays one back
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(i) = args.iter().position(|arg| arg == "--record") {
+        let path = args.get(i + 1).cloned().unwrap_or_else(|| "walden.replay".to_string());
+        let seed = std::env::var("WALDEN_SEED").ok().and_then(|seed| seed.parse().ok()).unwrap_or_else(rand::random);
+        eprintln!("Recording seed {} to {}", seed, path);
+        window::run(replay::Recorder::new(seed, path.into()));
+        return;
+    }
+    if let Some(i) = args.iter().position(|arg| arg == "--replay") {
+        let path = args.get(i + 1).cloned().unwrap_or_else(|| "walden.replay".to_string());
+        match replay::Replay::load(path.as_ref()) {
+            Ok(replay) => window::run(replay::Playback::new(replay)),
+            Err(error) => eprintln!("Can't play {}: {}", path, error),
+        }
+        return;
+    }
+    // --server [address] plays a game for whoever connects, --connect <address> joins one
+    if let Some(i) = args.iter().position(|arg| arg == "--server") {
+        let address = args.get(i + 1).cloned().unwrap_or_else(|| "0.0.0.0:7777".to_string());
+        let seed = std::env::var("WALDEN_SEED").ok().and_then(|seed| seed.parse().ok()).unwrap_or_else(rand::random);
+        net::serve(&address, seed);
+        return;
+    }
+    if let Some(i) = args.iter().position(|arg| arg == "--connect") {
+        let address = args.get(i + 1).cloned().unwrap_or_else(|| "127.0.0.1:7777".to_string());
+        match net::Client::connect(address.as_str()) {
+            Ok(client) => window::run(client),
+            Err(error) => eprintln!("Can't connect to {}: {}", address, error),
+        }
+        return;
+    }
+    // The game is kept in walden.save or WALDEN_SAVE. If there is one it carries on from it,
+    // unless --new starts over
+    let path = save::save_path();
+    let game = if path.exists() && !args.iter().any(|arg| arg == "--new") {
+        Walden::resume(path)
+    }
+    else {
+        Walden::start(path)
+    };
+    window::run(game);
 }